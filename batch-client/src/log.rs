@@ -13,7 +13,7 @@ pub fn init_logger(log_level: &str) {
     let env_filter = match std::env::var("RUST_LOG") {
         Ok(v) => v,
         Err(_) => {
-            let modules = vec!["batch_client", "speech_center_client"];
+            let modules = ["batch_client", "speech_center_client"];
             let log_level = log_level.to_string();
             let env_filter = modules
                 .iter()
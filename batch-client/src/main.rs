@@ -4,8 +4,16 @@ extern crate tracing;
 use crate::worker::{Payload, Worker};
 use anyhow::{anyhow, Result};
 use async_channel::Sender;
-use speech_center_client::{SpeechCenterError, Topic};
-use std::path::Path;
+use speech_center_client::{
+    Aggressiveness, Balancing, BreakerState, Cassette, ChannelPool, CircuitBreaker,
+    CredentialsProvider, Language, OutputFormat, RecognitionClient, Recognizer, RetryPolicy,
+    Segmenter, SpeechCenterConfig, SpeechCenterError, Subtitles, TokenFile, Topic, Vad,
+};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 use structopt::StructOpt;
 use tokio::fs::DirEntry;
 use tracing::Instrument;
@@ -13,6 +21,9 @@ use tracing::Instrument;
 mod log;
 mod worker;
 
+/// Extensions of the audio files recognised, any other file in the directory is left alone
+const AUDIO_EXTENSIONS: &[&str] = &["wav", "mp3", "flac", "ogg", "opus", "m4a"];
+
 #[derive(Clone, Debug, StructOpt)]
 struct Args {
     /// Log level. Must be TRACE | DEBUG | INFO | WARN | ERROR
@@ -24,24 +35,25 @@ struct Args {
     )]
     log_level: String,
 
-    /// Path to the JWT authentication token file
+    /// Path to the JWT authentication token file, read again whenever it changes
     #[structopt(short = "t", long = "token-file", required = true)]
     token_file: String,
 
-    /// The URL of the gRPC host or server trying to reach
+    /// The URL of the gRPC host or server trying to reach. Repeat it to balance across several
     #[structopt(
         short = "u",
         long = "url",
         required = true,
+        number_of_values = 1,
         default_value = "https://csr.api.speechcenter.verbio.com"
     )]
-    url: String,
+    urls: Vec<String>,
 
     /// Topic to use for the recognition. Must be GENERIC | BANKING | TELCO
     #[structopt(short = "T", long = "topic", required = true)]
     topic: String,
 
-    /// Directory containing .wav audios in 8kHz and PCM16 encoding to use for the recognition
+    /// Directory containing the audios to use for the recognition. Supported WAV | MP3 | FLAC | OGG (Vorbis, Opus) | M4A
     #[structopt(short = "d", long = "dir", required = true)]
     source_dir: String,
 
@@ -49,36 +61,132 @@ struct Args {
     #[structopt(short = "D", long = "dest-dir", required = true)]
     dest_dir: String,
 
-    /// IETF BCP-47 Language to use for the recognition. Supported en-US | es-ES | pt-BR
+    /// IETF BCP-47 Language to use for the recognition, falling back to the closest supported one.
+    /// Supported en-US | es-ES | pt-BR
     #[structopt(
         short = "l",
         long = "language",
         required = true,
         default_value = "en-US"
     )]
-    language: String,
+    language: Language,
 
     /// Number of workers to use for the recognition
     #[structopt(short = "w", long = "workers", default_value = "4")]
     workers: u16,
+
+    /// Number of connections shared by the workers, spread across the URLs
+    #[structopt(short = "C", long = "connections", default_value = "2")]
+    connections: usize,
+
+    /// How recognitions are spread across the connections. Must be ROUND-ROBIN | LEAST-OUTSTANDING
+    #[structopt(long = "balancing", default_value = "least-outstanding")]
+    balancing: String,
+
+    /// Backup URL to fail over to when the URL is unavailable. Repeat it for several, tried in
+    /// order. Recognitions go through a connection per URL, so a single URL and no connection
+    /// settings are accepted along
+    #[structopt(
+        short = "F",
+        long = "failover-url",
+        number_of_values = 1,
+        conflicts_with_all = &["connections", "balancing"]
+    )]
+    failover_urls: Vec<String>,
+
+    /// Consecutive failures of a URL before recognitions skip it for a while, with failover URLs
+    #[structopt(long = "breaker-failures", default_value = "5")]
+    breaker_failures: u32,
+
+    /// Maximum number of attempts per audio when the service is unavailable or overloaded
+    #[structopt(short = "r", long = "max-attempts", default_value = "3")]
+    max_attempts: u32,
+
+    /// Convert audios of any sample rate, channel count and sample format to 8kHz mono PCM16
+    #[structopt(short = "c", long = "convert")]
+    convert: bool,
+
+    /// Trim the silence before and after the speech of every audio with voice activity detection.
+    /// Must be LOW | NORMAL | HIGH | VERY-HIGH, the higher the more is trimmed
+    #[structopt(long = "vad")]
+    vad: Option<String>,
+
+    /// Silence in milliseconds kept before and after the speech when trimming it
+    #[structopt(long = "vad-padding", default_value = "200")]
+    vad_padding: u64,
+
+    /// Shorten the pauses within the speech to this many milliseconds when trimming silence
+    #[structopt(long = "max-pause")]
+    max_pause: Option<u64>,
+
+    /// Split every audio at its pauses into utterances recognised separately, writing a transcript
+    /// with the start and end of every utterance as JSON with --json
+    #[structopt(long = "segment")]
+    segment: bool,
+
+    /// Silence in milliseconds ending an utterance when segmenting
+    #[structopt(long = "segment-pause", default_value = "500")]
+    segment_pause: u64,
+
+    /// Longest utterance in seconds when segmenting, cut even if there is no pause in it
+    #[structopt(long = "max-segment", default_value = "30")]
+    max_segment: u64,
+
+    /// Utterances of an audio recognised at the same time when segmenting
+    #[structopt(long = "segment-concurrency", default_value = "4")]
+    segment_concurrency: usize,
+
+    /// Write every recognition as JSON into <stem>.json, with its audio duration, latency,
+    /// real-time factor, attempts, endpoint and response metadata, instead of <stem>.txt.
+    /// Same as --format json
+    #[structopt(long = "json", conflicts_with = "format")]
+    json: bool,
+
+    /// Format of the <stem>.<extension> written for every audio. Must be TXT | JSON | SRT | VTT |
+    /// TIMESTAMPED, the last three segmenting the audio into subtitle cues
+    #[structopt(short = "f", long = "format", default_value = "txt")]
+    format: String,
+
+    /// Characters per line of the subtitles
+    #[structopt(long = "max-line-length", default_value = "42")]
+    max_line_length: usize,
+
+    /// Longest a subtitle cue is shown, in seconds
+    #[structopt(long = "max-cue-duration", default_value = "6")]
+    max_cue_duration: u64,
+
+    /// Record the recognitions made into a cassette file
+    #[structopt(long = "record", conflicts_with = "replay")]
+    record: Option<String>,
+
+    /// Answer the calls with the ones recorded in a cassette file, without reaching the service
+    #[structopt(long = "replay")]
+    replay: Option<String>,
 }
 
-async fn start_workers(url: &str, token: &str, count: u16) -> Result<Sender<Payload>> {
+fn start_workers(
+    recognizer: Arc<dyn Recognizer>,
+    count: u16,
+    format: OutputFormat,
+    subtitles: Subtitles,
+    segmenter: Option<Segmenter>,
+) -> Sender<Payload> {
     let (tx, rx) = async_channel::bounded(count as usize);
 
     for idx in 0..count {
-        let url = url.to_string();
-        let token = token.to_string();
-        let rx = rx.clone();
+        let w = Worker::new(
+            recognizer.clone(),
+            rx.clone(),
+            format,
+            subtitles.clone(),
+            segmenter.clone(),
+        );
         tokio::spawn(async move {
             let span = info_span!("Worker", worker=%idx);
-            let w = Worker::new(&url, &token, rx)
-                .await
-                .expect("Error starting worker");
             w.start().instrument(span).await;
         });
     }
-    Ok(tx)
+    tx
 }
 
 async fn ensure_dir_exists(dir: &str) -> Result<()> {
@@ -93,34 +201,31 @@ async fn ensure_dir_exists(dir: &str) -> Result<()> {
             )))
         }
     } else {
-        tokio::fs::create_dir_all(dir).await.map_err(|e| {
-            SpeechCenterError::Unknown(format!("Error creating dirs [dir={}]: {}", dir, e))
-        })?;
+        tokio::fs::create_dir_all(dir)
+            .await
+            .map_err(|e| SpeechCenterError::io(format!("Error creating dirs [dir={}]", dir), e))?;
         Ok(())
     }
 }
 
+/// Queues an audio file unless it was transcribed already, `queued` holding the audio written
+/// into every destination so far.
 fn entry_to_payload(
     f: &DirEntry,
-    language: String,
+    language: Language,
     topic: Topic,
-    dest_dir: &str,
+    dest_dir: &Path,
+    extension: &str,
+    queued: &mut HashMap<PathBuf, PathBuf>,
 ) -> Result<Option<Payload>> {
     let file_path = f.path();
 
-    let extension = file_path.extension().ok_or_else(|| {
-        anyhow!(
-            "File must contain an extension [file={}]",
-            file_path.display()
-        )
-    })?;
-    let extension = extension.to_str().ok_or_else(|| {
-        anyhow!(
-            "File extension not convertible to str [file={}]",
-            file_path.display()
-        )
-    })?;
-    if extension != "wav" {
+    let is_audio = file_path
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| AUDIO_EXTENSIONS.contains(&e.to_lowercase().as_str()));
+    if !file_path.is_file() || !is_audio {
+        debug!("Skipping {}, not an audio file", file_path.display());
         return Ok(None);
     }
 
@@ -138,10 +243,25 @@ fn entry_to_payload(
         )
     })?;
 
-    let dest = Path::new(dest_dir).join(format!("{}.txt", stem));
+    let dest = dest_dir.join(format!("{}.{}", stem, extension));
+    if let Some(other) = queued.get(&dest) {
+        warn!(
+            "Skipping {}, {} is transcribed into {} already",
+            file_path.display(),
+            other.display(),
+            dest.display()
+        );
+        return Ok(None);
+    }
     if dest.exists() {
+        debug!(
+            "Skipping {}, {} exists",
+            file_path.display(),
+            dest.display()
+        );
         return Ok(None);
     }
+    queued.insert(dest.clone(), file_path.clone());
 
     let dest = format!("{}", dest.display());
 
@@ -153,30 +273,120 @@ fn entry_to_payload(
     }))
 }
 
+fn vad(opts: &Args) -> Result<Option<Vad>> {
+    let aggressiveness = match &opts.vad {
+        Some(name) => Aggressiveness::from_name(name)?,
+        None => return Ok(None),
+    };
+    let vad = Vad::new(aggressiveness).padding(Duration::from_millis(opts.vad_padding));
+    Ok(Some(match opts.max_pause {
+        Some(max_pause) => vad.max_pause(Duration::from_millis(max_pause)),
+        None => vad,
+    }))
+}
+
+fn subtitles(opts: &Args) -> Subtitles {
+    Subtitles::default()
+        .max_line_length(opts.max_line_length)
+        .max_duration(Duration::from_secs(opts.max_cue_duration))
+}
+
+fn segmenter(opts: &Args, vad: Vad) -> Segmenter {
+    Segmenter::new(vad)
+        .pause(Duration::from_millis(opts.segment_pause))
+        .max_segment(Duration::from_secs(opts.max_segment))
+        .concurrency(opts.segment_concurrency)
+}
+
 async fn run(
-    url: &str,
-    token: &str,
-    language: &str,
-    source_dir: &str,
-    dest_dir: &str,
+    opts: &Args,
+    credentials: Arc<dyn CredentialsProvider>,
     topic: Topic,
-    workers: u16,
+    language: Language,
 ) -> Result<()> {
     debug!("Ensuring directories exist");
-    ensure_dir_exists(source_dir).await?;
-    ensure_dir_exists(dest_dir).await?;
+    ensure_dir_exists(&opts.source_dir).await?;
+    ensure_dir_exists(&opts.dest_dir).await?;
 
-    info!("Starting {} workers", workers);
-    let tx = start_workers(url, token, workers).await?;
+    info!("Starting {} workers", opts.workers);
+    let mut config = SpeechCenterConfig::new(&opts.urls[0])
+        .retry_policy(RetryPolicy::default().max_attempts(opts.max_attempts));
+    // A single cassette is shared by all the workers
+    if let Some(path) = &opts.record {
+        config = config.cassette(Arc::new(Cassette::record(path)?));
+    } else if let Some(path) = &opts.replay {
+        config = config.cassette(Arc::new(Cassette::replay(path)?));
+    }
+    if !opts.failover_urls.is_empty() && opts.urls.len() > 1 {
+        return Err(anyhow!("Failover URLs back up a single URL, not several"));
+    }
+    let (recognizer, pool) = if opts.failover_urls.is_empty() {
+        let pool = ChannelPool::builder(config)
+            .urls(&opts.urls)
+            .size(opts.connections)
+            .balancing(Balancing::from_name(&opts.balancing)?)
+            .connect()
+            .await?;
+        (RecognitionClient::with_pool(&pool, credentials), Some(pool))
+    } else {
+        let config = config
+            .failover(&opts.failover_urls)
+            .circuit_breaker(CircuitBreaker::default().failure_threshold(opts.breaker_failures));
+        (
+            RecognitionClient::with_config(config, credentials).await?,
+            None,
+        )
+    };
+    let format = match opts.json {
+        true => OutputFormat::Json,
+        false => OutputFormat::from_str(&opts.format)?,
+    };
+    let vad = vad(opts)?;
+    let (vad, segmenter) = match opts.segment || format.is_segmented() {
+        true => (None, Some(segmenter(opts, vad.unwrap_or_default()))),
+        false => (vad, None),
+    };
+    let recognizer = recognizer.with_audio_conversion(opts.convert).with_vad(vad);
+    let tx = start_workers(
+        Arc::new(recognizer.clone()),
+        opts.workers,
+        format,
+        subtitles(opts),
+        segmenter,
+    );
     info!("Workers started");
 
-    let mut dir = tokio::fs::read_dir(source_dir)
+    let extension = format.extension();
+    // Transcriptions written among the audios would be taken for audio in the next run
+    let dest_dir = tokio::fs::canonicalize(&opts.dest_dir)
+        .await
+        .map_err(|e| anyhow::anyhow!(format!("Error resolving dest dir: {}", e)))?;
+    if tokio::fs::canonicalize(&opts.source_dir)
+        .await
+        .ok()
+        .as_ref()
+        == Some(&dest_dir)
+    {
+        return Err(anyhow!(
+            "Dest dir must not be the source dir [dir={}]",
+            opts.dest_dir
+        ));
+    }
+    let mut dir = tokio::fs::read_dir(&opts.source_dir)
         .await
         .map_err(|e| anyhow::anyhow!(format!("Error iterating dir: {}", e)))?;
 
+    let mut queued = HashMap::new();
     while let Ok(Some(f)) = dir.next_entry().await {
-        let payload = entry_to_payload(&f, language.to_string(), topic.clone(), dest_dir)
-            .map_err(|e| anyhow!("Error creating Payload: {}", e))?;
+        let payload = entry_to_payload(
+            &f,
+            language.clone(),
+            topic.clone(),
+            &dest_dir,
+            extension,
+            &mut queued,
+        )
+        .map_err(|e| anyhow!("Error creating Payload: {}", e))?;
         if let Some(payload) = payload {
             info!("Sending file {}", f.path().display());
             if let Err(e) = tx.send(payload).await {
@@ -185,11 +395,23 @@ async fn run(
         }
     }
 
-    for _ in 0..workers {
+    for _ in 0..opts.workers {
         let (close_tx, close_rx) = async_channel::unbounded();
         let _ = tx.send(Payload::Close(close_tx)).await;
         let _ = close_rx.recv().await;
     }
+    if let Some(pool) = pool {
+        info!("{}/{} connections healthy", pool.healthy(), pool.size());
+    }
+    // Breakers log their transitions as they happen, this only sums up where they ended
+    let endpoints = recognizer.endpoints();
+    if !endpoints.is_empty() {
+        let closed = endpoints
+            .iter()
+            .filter(|e| e.state == BreakerState::Closed)
+            .count();
+        info!("{}/{} failover URLs available", closed, endpoints.len());
+    }
 
     Ok(())
 }
@@ -202,24 +424,24 @@ async fn main() {
     debug!("Args: {:?}", opts);
 
     let topic = Topic::from_name(&opts.topic).expect("Error converting topic");
-
-    let token = std::fs::read_to_string(&opts.token_file).expect("Error reading token from file");
-    let token = token.trim().to_string();
-    if token.is_empty() {
-        panic!("Token cannot be empty");
+    let language = opts
+        .language
+        .fallback(topic.languages())
+        .expect("Unsupported language");
+    if language != opts.language {
+        warn!(
+            "Language {} is not supported, using {}",
+            opts.language, language
+        );
     }
 
-    if let Err(e) = run(
-        &opts.url,
-        &token,
-        &opts.language,
-        &opts.source_dir,
-        &opts.dest_dir,
-        topic,
-        opts.workers,
-    )
-    .await
-    {
+    let credentials = Arc::new(TokenFile::new(&opts.token_file));
+    credentials
+        .token()
+        .await
+        .expect("Error reading token from file");
+
+    if let Err(e) = run(&opts, credentials, topic, language).await {
         panic!("Error in execution: {}", e)
     }
 }
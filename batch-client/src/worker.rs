@@ -1,25 +1,44 @@
 use async_channel::{Receiver, Sender};
-use speech_center_client::{RecognitionClient, Result, SpeechCenterError, Topic};
+use speech_center_client::{
+    AudioStream, ChunkSize, Language, OutputFormat, Recognizer, Resource, Result, Segmenter,
+    SpeechCenterError, Subtitles, Topic,
+};
+use std::sync::Arc;
 
 pub enum Payload {
     File {
         source: String,
         dest: String,
         topic: Topic,
-        language: String,
+        language: Language,
     },
     Close(Sender<()>),
 }
 
 pub struct Worker {
-    client: RecognitionClient,
+    recognizer: Arc<dyn Recognizer>,
     rx: Receiver<Payload>,
+    format: OutputFormat,
+    subtitles: Subtitles,
+    /// Splits the audios into utterances recognised separately
+    segmenter: Option<Segmenter>,
 }
 
 impl Worker {
-    pub async fn new(url: &str, token: &str, rx: Receiver<Payload>) -> Result<Self> {
-        let client = RecognitionClient::new(url, token).await?;
-        Ok(Self { client, rx })
+    pub fn new(
+        recognizer: Arc<dyn Recognizer>,
+        rx: Receiver<Payload>,
+        format: OutputFormat,
+        subtitles: Subtitles,
+        segmenter: Option<Segmenter>,
+    ) -> Self {
+        Self {
+            recognizer,
+            rx,
+            format,
+            subtitles,
+            segmenter,
+        }
     }
 
     pub async fn start(mut self) {
@@ -33,9 +52,15 @@ impl Worker {
                 } => {
                     debug!("Processing file {}", source);
                     if let Err(e) = self.process(&source, &dest, topic, language).await {
+                        let hint = match &e {
+                            SpeechCenterError::Unauthenticated(_)
+                            | SpeechCenterError::PermissionDenied(_) => " (check the token file)",
+                            e if e.is_retryable() => " (transient, run the batch again to retry)",
+                            _ => "",
+                        };
                         eprintln!(
-                            "Error processing file [source={}] [dest={}]: {:?}",
-                            source, dest, e
+                            "Error processing file [source={}] [dest={}]: {}{}",
+                            source, dest, e, hint
                         );
                     }
                 }
@@ -53,29 +78,64 @@ impl Worker {
         source: &str,
         dest: &str,
         topic: Topic,
-        language: String,
+        language: Language,
     ) -> Result<()> {
         debug!("Reading file contents: {}", source);
-        let audio = tokio::fs::read(source).await.map_err(|e| {
-            SpeechCenterError::Unknown(format!(
-                "Error reading source file [source={}]: {}",
-                source, e
-            ))
-        })?;
+        let audio = AudioStream::from_file(source, ChunkSize::default()).await?;
+
+        let resource = Resource::Topic(topic);
+        if let Some(segmenter) = &self.segmenter {
+            debug!("Performing segmented recognision");
+            let transcript = segmenter
+                .transcribe(self.recognizer.clone(), language.as_str(), resource, audio)
+                .await?;
+            debug!(
+                "Recognised {} segments of {}",
+                transcript.segments.len(),
+                source
+            );
+            let contents = match self.format {
+                OutputFormat::Text => transcript.transcript,
+                OutputFormat::Json => {
+                    serde_json::to_string_pretty(&transcript).expect("Results are serializable")
+                }
+                OutputFormat::Subtitles(format) => self.subtitles.render(format, &transcript),
+            };
+            return write(dest, contents).await;
+        }
 
         debug!("Performing recognision");
         let res = self
-            .client
-            .recognise_with_topic(&language, topic, audio)
+            .recognizer
+            .recognise_stream(language.as_str(), resource, audio)
             .await?;
+        if res.attempts > 1 {
+            info!("Recognised {} after {} attempts", source, res.attempts);
+        }
+        if let Some(ratio) = res.speech_ratio {
+            debug!(
+                "Speech ratio of {}: {:.2}, {:.2}s of silence trimmed",
+                source,
+                ratio,
+                res.trimmed.as_secs_f64()
+            );
+        }
 
-        debug!("Writing transcription: {}", dest);
-        tokio::fs::write(dest, res.as_bytes()).await.map_err(|e| {
-            SpeechCenterError::Unknown(format!(
-                "Error writing transcription [dest={}]: {}",
-                dest, e
-            ))
-        })?;
-        Ok(())
+        let contents = match self.format {
+            OutputFormat::Json => {
+                serde_json::to_string_pretty(&res).expect("Results are serializable")
+            }
+            _ => res.transcript,
+        };
+        write(dest, contents).await
     }
 }
+
+async fn write(dest: &str, contents: String) -> Result<()> {
+    debug!("Writing transcription: {}", dest);
+    tokio::fs::write(dest, contents.as_bytes())
+        .await
+        .map_err(|e| {
+            SpeechCenterError::io(format!("Error writing transcription [dest={}]", dest), e)
+        })
+}
@@ -1,6 +1,8 @@
 mod recognition;
 mod synthesis;
 
+use speech_center_client::{Cassette, Language, SpeechCenterConfig, SpeechCenterError};
+use std::sync::Arc;
 use structopt::StructOpt;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -12,6 +14,53 @@ enum Args {
     Synthesis(synthesis::Synthesis),
 }
 
+/// Prints the error, which describes its cause already, and exits with a sysexits(3) code
+/// matching its kind.
+fn exit_with_error(context: &str, e: SpeechCenterError) -> ! {
+    eprintln!("{}: {}", context, e);
+    let code = match e {
+        SpeechCenterError::InvalidArgument(_)
+        | SpeechCenterError::InvalidGrammar(_)
+        | SpeechCenterError::InvalidParameter(_)
+        | SpeechCenterError::InvalidAudio(_)
+        | SpeechCenterError::Decoding(_)
+        | SpeechCenterError::UnsupportedAudio(_) => 65,
+        SpeechCenterError::Unauthenticated(_)
+        | SpeechCenterError::PermissionDenied(_)
+        | SpeechCenterError::Credentials { .. } => 77,
+        SpeechCenterError::Config(_) => 78,
+        SpeechCenterError::Io { .. } => 74,
+        e if e.is_retryable() => 69,
+        _ => 1,
+    };
+    std::process::exit(code)
+}
+
+/// Configuration for `url`, recording into or replaying from a cassette when requested.
+fn config(url: &str, record: Option<&str>, replay: Option<&str>) -> SpeechCenterConfig {
+    let (path, cassette) = match (record, replay) {
+        (Some(path), _) => (path, Cassette::record(path)),
+        (_, Some(path)) => (path, Cassette::replay(path)),
+        _ => return SpeechCenterConfig::new(url),
+    };
+    let cassette = cassette.unwrap_or_else(|e| {
+        let e = SpeechCenterError::io(format!("Error opening cassette [file={}]", path), e);
+        exit_with_error("Error creating client", e)
+    });
+    SpeechCenterConfig::new(url).cassette(Arc::new(cassette))
+}
+
+/// The closest of the supported languages, telling when it is not the requested one.
+fn fallback(language: &Language, supported: &[Language]) -> Language {
+    let closest = language
+        .fallback(supported)
+        .unwrap_or_else(|e| exit_with_error("Unsupported language", e));
+    if closest != *language {
+        eprintln!("Language {} is not supported, using {}", language, closest);
+    }
+    closest
+}
+
 #[tokio::main]
 async fn main() {
     match Args::from_args() {
@@ -1,4 +1,12 @@
-use speech_center_client::{RecognitionClient, Topic};
+use crate::{config, exit_with_error, fallback};
+use speech_center_client::{
+    Aggressiveness, AudioStream, ChunkSize, CredentialsProvider, Language, OutputFormat,
+    RecognitionClient, Recognizer, Resource, Segmenter, SpeechCenterError, SpeechService,
+    Subtitles, TokenFile, Topic, Vad,
+};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 use structopt::StructOpt;
 
 #[derive(Clone, Debug, StructOpt)]
@@ -25,55 +33,215 @@ pub struct Recognition {
     #[structopt(short = "g", long = "grammar")]
     grammar: Option<String>,
 
-    /// Path to a .wav audio in 8kHz and PCM16 encoding to use for the recognition
+    /// Path to the audio to use for the recognition. Supported WAV | MP3 | FLAC | OGG (Vorbis, Opus) | M4A
     #[structopt(short = "a", long = "audio", required = true)]
     audio: String,
 
-    /// IETF BCP-47 Language to use for the recognition. Supported en-US | es-ES | pt-BR
+    /// IETF BCP-47 Language to use for the recognition, falling back to the closest supported one.
+    /// Supported en-US | es-ES | pt-BR
     #[structopt(
         short = "l",
         long = "language",
         required = true,
         default_value = "en-US"
     )]
-    language: String,
+    language: Language,
+
+    /// Convert audio of any sample rate, channel count and sample format to 8kHz mono PCM16
+    #[structopt(short = "c", long = "convert")]
+    convert: bool,
+
+    /// Trim the silence before and after the speech with voice activity detection.
+    /// Must be LOW | NORMAL | HIGH | VERY-HIGH, the higher the more is trimmed
+    #[structopt(long = "vad")]
+    vad: Option<String>,
+
+    /// Silence in milliseconds kept before and after the speech when trimming it
+    #[structopt(long = "vad-padding", default_value = "200")]
+    vad_padding: u64,
+
+    /// Shorten the pauses within the speech to this many milliseconds when trimming silence
+    #[structopt(long = "max-pause")]
+    max_pause: Option<u64>,
+
+    /// Split the audio at its pauses into utterances recognised separately, printing a transcript
+    /// with the start and end of every utterance as JSON with --json
+    #[structopt(long = "segment")]
+    segment: bool,
+
+    /// Silence in milliseconds ending an utterance when segmenting
+    #[structopt(long = "segment-pause", default_value = "500")]
+    segment_pause: u64,
+
+    /// Longest utterance in seconds when segmenting, cut even if there is no pause in it
+    #[structopt(long = "max-segment", default_value = "30")]
+    max_segment: u64,
+
+    /// Utterances of the audio recognised at the same time when segmenting
+    #[structopt(long = "segment-concurrency", default_value = "4")]
+    segment_concurrency: usize,
+
+    /// Print the recognition as JSON, with its audio duration, latency, real-time factor,
+    /// attempts, endpoint and response metadata. Same as --format json
+    #[structopt(long = "json", conflicts_with = "format")]
+    json: bool,
+
+    /// Format of the recognition printed. Must be TXT | JSON | SRT | VTT | TIMESTAMPED, the last
+    /// three segmenting the audio into subtitle cues
+    #[structopt(short = "f", long = "format", default_value = "txt")]
+    format: String,
+
+    /// Characters per line of the subtitles
+    #[structopt(long = "max-line-length", default_value = "42")]
+    max_line_length: usize,
+
+    /// Longest a subtitle cue is shown, in seconds
+    #[structopt(long = "max-cue-duration", default_value = "6")]
+    max_cue_duration: u64,
+
+    /// Record the calls made to the service into a cassette file
+    #[structopt(long = "record", conflicts_with = "replay")]
+    record: Option<String>,
+
+    /// Answer the calls with the ones recorded in a cassette file, without reaching the service
+    #[structopt(long = "replay")]
+    replay: Option<String>,
 }
 
 pub async fn process_subcommand(opts: Recognition) {
-    let token = std::fs::read_to_string(&opts.token_file).expect("Error reading token from file");
-    let token = token.trim().to_string();
-    if token.is_empty() {
-        panic!("Token cannot be empty");
-    }
+    let credentials = Arc::new(TokenFile::new(&opts.token_file));
+    credentials
+        .token()
+        .await
+        .unwrap_or_else(|e| exit_with_error("Error reading token from file", e));
 
-    let audio = std::fs::read(&opts.audio).expect("Error reading audio file");
-    if audio.is_empty() {
-        panic!("Audio cannot be empty");
-    }
+    let audio = AudioStream::from_file(&opts.audio, ChunkSize::default())
+        .await
+        .unwrap_or_else(|e| exit_with_error("Error reading audio file", e.into()));
+    let audio = audio
+        .non_empty()
+        .await
+        .unwrap_or_else(|e| {
+            let e = SpeechCenterError::io(format!("Error reading audio [file={}]", opts.audio), e);
+            exit_with_error("Error reading audio file", e)
+        })
+        .unwrap_or_else(|| panic!("Audio cannot be empty"));
 
-    let mut client = RecognitionClient::new(&opts.url, &token)
+    let config = config(&opts.url, opts.record.as_deref(), opts.replay.as_deref());
+    let client = RecognitionClient::with_config(config, credentials)
         .await
-        .expect("Error creating client");
-
-    match (opts.grammar, opts.topic) {
-        (Some(grammar), _) => {
-            let grammar = std::fs::read_to_string(&grammar).expect("Error reading grammar file");
-            let res = client
-                .recognise_with_grammar(&grammar, &opts.language, audio)
-                .await
-                .expect("Error in recognision");
-            println!("Res: {}", res);
+        .unwrap_or_else(|e| exit_with_error("Error creating client", e))
+        .with_audio_conversion(opts.convert);
+    let vad = vad(&opts);
+    let format = match opts.json {
+        true => OutputFormat::Json,
+        false => OutputFormat::from_str(&opts.format)
+            .unwrap_or_else(|e| exit_with_error("Error converting output format", e)),
+    };
+
+    let resource = match (opts.grammar, opts.topic) {
+        (Some(grammar), _) => Resource::Grammar(
+            std::fs::read_to_string(&grammar).expect("Error reading grammar file"),
+        ),
+        (_, Some(topic)) => Resource::Topic(
+            Topic::from_name(&topic)
+                .unwrap_or_else(|e| exit_with_error("Error converting topic", e)),
+        ),
+        _ => {
+            panic!("Either grammar or topic must be defined");
         }
-        (_, Some(topic)) => {
-            let topic = Topic::from_name(&topic).expect("Error converting topic");
-            let res = client
-                .recognise_with_topic(&opts.language, topic, audio)
-                .await
-                .expect("Error in recognision");
-            println!("Res: {}", res);
+    };
+    let supported = match &resource {
+        Resource::Topic(topic) => topic.languages(),
+        Resource::Grammar(_) => SpeechService::Recognition.languages(),
+    };
+    let language = fallback(&opts.language, supported);
+    match opts.segment || format.is_segmented() {
+        true => {
+            let segmenter = Segmenter::new(vad.unwrap_or_default())
+                .pause(Duration::from_millis(opts.segment_pause))
+                .max_segment(Duration::from_secs(opts.max_segment))
+                .concurrency(opts.segment_concurrency);
+            let subtitles = Subtitles::default()
+                .max_line_length(opts.max_line_length)
+                .max_duration(Duration::from_secs(opts.max_cue_duration));
+            let recognizer = Arc::new(client);
+            transcribe(
+                &segmenter,
+                recognizer,
+                language.as_str(),
+                resource,
+                audio,
+                format,
+                &subtitles,
+            )
+            .await
         }
+        false => {
+            let client = client.with_vad(vad);
+            recognise(&client, language.as_str(), resource, audio, format).await
+        }
+    }
+}
+
+fn vad(opts: &Recognition) -> Option<Vad> {
+    let aggressiveness = Aggressiveness::from_name(opts.vad.as_deref()?)
+        .unwrap_or_else(|e| exit_with_error("Error converting VAD aggressiveness", e));
+    let vad = Vad::new(aggressiveness).padding(Duration::from_millis(opts.vad_padding));
+    Some(match opts.max_pause {
+        Some(max_pause) => vad.max_pause(Duration::from_millis(max_pause)),
+        None => vad,
+    })
+}
+
+async fn recognise(
+    recognizer: &dyn Recognizer,
+    language: &str,
+    resource: Resource,
+    audio: AudioStream,
+    format: OutputFormat,
+) {
+    let res = recognizer
+        .recognise_stream(language, resource, audio)
+        .await
+        .unwrap_or_else(|e| exit_with_error("Error in recognition", e));
+    match format {
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&res).expect("Results are serializable")
+        ),
         _ => {
-            panic!("Either grammar or topic must be defined");
+            if let Some(ratio) = res.speech_ratio {
+                println!(
+                    "Speech: {:.0}% of the audio, {:.2}s of silence trimmed",
+                    ratio * 100.0,
+                    res.trimmed.as_secs_f64()
+                );
+            }
+            println!("Res: {}", res.transcript)
         }
     }
 }
+
+async fn transcribe(
+    segmenter: &Segmenter,
+    recognizer: Arc<dyn Recognizer>,
+    language: &str,
+    resource: Resource,
+    audio: AudioStream,
+    format: OutputFormat,
+    subtitles: &Subtitles,
+) {
+    let transcript = segmenter
+        .transcribe(recognizer, language, resource, audio)
+        .await
+        .unwrap_or_else(|e| exit_with_error("Error in recognition", e));
+    match format {
+        OutputFormat::Text => println!("Res: {}", transcript.transcript),
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&transcript).expect("Results are serializable")
+        ),
+        OutputFormat::Subtitles(format) => print!("{}", subtitles.render(format, &transcript)),
+    }
+}
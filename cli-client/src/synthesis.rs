@@ -1,5 +1,11 @@
-use bytes::{Buf, Bytes};
-use speech_center_client::{AudioFormat, SampleRate, Speaker, SynthesisClient};
+use crate::{config, exit_with_error, fallback};
+use speech_center_client::{
+    AudioFormat, CredentialsProvider, Language, SampleRate, Speaker, SpeechCenterError,
+    SpeechService, SynthesisClient, Synthesizer, TokenFile,
+};
+use std::fs::File;
+use std::io::BufWriter;
+use std::sync::Arc;
 use structopt::StructOpt;
 
 #[derive(Clone, Debug, StructOpt)]
@@ -31,7 +37,7 @@ pub struct Synthesis {
     )]
     sample_rate: u32,
 
-    /// Output audio encoding algorithm. Supported PCM (Signed 16-bit little endian PCM)
+    /// Output audio encoding algorithm. Supported PCM (Signed 16-bit little endian PCM) | MULAW (G.711 mu-law) | ALAW (G.711 A-law)
     #[structopt(short = "e", long = "encoding", required = true, default_value = "PCM")]
     encoding: String,
 
@@ -43,74 +49,75 @@ pub struct Synthesis {
     #[structopt(short = "T", long = "text", required = true)]
     text: String,
 
-    /// IETF BCP-47 Language to use for the recognition. Supported en-US | es-ES | pt-BR | ca-ES
+    /// IETF BCP-47 Language to use for the synthesis, falling back to the closest supported one.
+    /// Supported en-US | es-ES | pt-BR | ca-ES
     #[structopt(
         short = "l",
         long = "language",
         required = true,
         default_value = "en-US"
     )]
-    language: String,
+    language: Language,
 
     /// Path to store the synthesis resulting audio
     #[structopt(short = "o", long = "output", required = true)]
     output: String,
+
+    /// Record the calls made to the service into a cassette file
+    #[structopt(long = "record", conflicts_with = "replay")]
+    record: Option<String>,
+
+    /// Answer the calls with the ones recorded in a cassette file, without reaching the service
+    #[structopt(long = "replay")]
+    replay: Option<String>,
 }
 
 pub async fn process_subcommand(opts: Synthesis) {
-    let token = std::fs::read_to_string(&opts.token_file).expect("Error reading token from file");
-    let token = token.trim().to_string();
-    if token.is_empty() {
-        panic!("Token cannot be empty");
-    }
+    let credentials = Arc::new(TokenFile::new(&opts.token_file));
+    credentials
+        .token()
+        .await
+        .unwrap_or_else(|e| exit_with_error("Error reading token from file", e));
 
-    let speaker = Speaker::from_name(&opts.voice, &opts.language)
-        .expect("Unknown Voice/Language combination");
+    let language = fallback(&opts.language, SpeechService::Synthesis.languages());
+    let speaker = Speaker::from_name(&opts.voice, language.as_str())
+        .unwrap_or_else(|e| exit_with_error("Unknown Voice/Language combination", e));
 
-    let sample_rate = SampleRate::try_from(opts.sample_rate).expect("Unknown sample rate");
+    let sample_rate = SampleRate::try_from(opts.sample_rate)
+        .unwrap_or_else(|e| exit_with_error("Unknown sample rate", e));
 
     let audio_format = AudioFormat::from_str(&opts.encoding, &opts.header)
-        .expect("Unknown Audio Encoding/Audio Header combination");
+        .unwrap_or_else(|e| exit_with_error("Unknown Audio Encoding/Audio Header combination", e));
 
     if opts.text.is_empty() {
         panic!("Text cannot be empty");
     }
 
-    let mut client = SynthesisClient::new(&opts.url, &token)
+    let config = config(&opts.url, opts.record.as_deref(), opts.replay.as_deref());
+    let client = SynthesisClient::with_config(config, credentials)
         .await
-        .expect("Error creating client");
+        .unwrap_or_else(|e| exit_with_error("Error creating client", e));
 
-    let audio = client
-        .synthesize(speaker, sample_rate.clone(), audio_format, &opts.text)
+    let synthesizer: &dyn Synthesizer = &client;
+    let res = synthesizer
+        .synthesize(speaker, sample_rate, audio_format, &opts.text)
         .await
-        .expect("Error in recognision");
-
-    println!("Writing: {}B of audio into {}", audio.len(), &opts.output);
-    match opts.header.to_lowercase().as_str() {
-        "wav" => save_wav(&opts.output, audio, sample_rate),
-        "raw" => save_raw(&opts.output, audio),
-        _ => panic!("Unknown audio header"),
-    }
-}
-
-fn save_wav(filename: &str, mut audio: Bytes, sample_rate: SampleRate) {
-    let spec = hound::WavSpec {
-        channels: 1,
-        sample_rate: sample_rate.into(),
-        bits_per_sample: 16,
-        sample_format: hound::SampleFormat::Int,
-    };
-    let mut writer = hound::WavWriter::create(filename, spec)
-        .expect("Could not write the audio into the provided file output");
-    (0..(audio.len() / 2)).for_each(|_| {
-        writer
-            .write_sample(audio.get_i16_le())
-            .expect("Error writing data into provided file output");
+        .unwrap_or_else(|e| exit_with_error("Error in synthesis", e));
+
+    println!(
+        "Writing: {}B of audio ({:.2}s) into {}",
+        res.raw().len(),
+        res.duration().as_secs_f64(),
+        &opts.output
+    );
+    let written = File::create(&opts.output).and_then(|file| match res.audio_format.has_header() {
+        true => res.write_wav(BufWriter::new(file)),
+        false => res.write_raw(BufWriter::new(file)),
+    });
+    written.unwrap_or_else(|e| {
+        exit_with_error(
+            "Error saving the file output",
+            SpeechCenterError::io(&opts.output, e),
+        )
     });
-    writer.finalize().expect("Error saving the file output");
-}
-
-fn save_raw(filename: &str, audio: Bytes) {
-    std::fs::write(filename, audio)
-        .expect("Could not write the audio into the provided file output");
 }
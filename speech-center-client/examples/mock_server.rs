@@ -0,0 +1,16 @@
+//! Serves scripted responses for the tests of the bindings, printing the url to use.
+
+use speech_center_client::testkit::{Match, MockServer, Reply};
+
+#[tokio::main]
+async fn main() {
+    let server = MockServer::builder()
+        .token("token")
+        .respond(Match::Any, Reply::Transcript("hello world".to_string()))
+        .respond(Match::Any, Reply::Audio(vec![1; 1600]))
+        .start()
+        .await
+        .expect("Error starting the mock server");
+    println!("{}", server.url());
+    tokio::signal::ctrl_c().await.ok();
+}
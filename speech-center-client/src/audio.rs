@@ -0,0 +1,444 @@
+use crate::audio_stream::RECOGNITION_SAMPLE_RATE;
+use crate::error::SpeechCenterError;
+use crate::g711;
+use crate::wav::{WavEncoding, WavError, WavSpec};
+use std::f64::consts::PI;
+use std::fmt;
+use std::time::Duration;
+
+/// Zero crossings of the sinc kernel on each side of every output sample
+const KERNEL_ZERO_CROSSINGS: f64 = 8.0;
+/// Fraction of the output Nyquist frequency kept by the anti-aliasing filter
+const CUTOFF: f64 = 0.95;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SampleFormat {
+    U8,
+    S16,
+    S24,
+    S32,
+    F32,
+    ALaw,
+    MuLaw,
+}
+
+impl SampleFormat {
+    pub fn width(self) -> usize {
+        match self {
+            Self::U8 | Self::ALaw | Self::MuLaw => 1,
+            Self::S16 => 2,
+            Self::S24 => 3,
+            Self::S32 | Self::F32 => 4,
+        }
+    }
+
+    fn decode(self, sample: &[u8]) -> f32 {
+        match self {
+            Self::U8 => (f32::from(sample[0]) - 128.0) / 128.0,
+            Self::S16 => f32::from(i16::from_le_bytes([sample[0], sample[1]])) / 32768.0,
+            Self::S24 => {
+                let value = i32::from_le_bytes([0, sample[0], sample[1], sample[2]]) >> 8;
+                value as f32 / 8_388_608.0
+            }
+            Self::S32 => {
+                let value = i32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]);
+                (f64::from(value) / 2_147_483_648.0) as f32
+            }
+            Self::F32 => f32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]),
+            Self::ALaw => f32::from(g711::alaw_to_linear(sample[0])) / 32768.0,
+            Self::MuLaw => f32::from(g711::mulaw_to_linear(sample[0])) / 32768.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct AudioSpec {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub format: SampleFormat,
+}
+
+impl AudioSpec {
+    /// Mono 8kHz signed 16-bit little endian PCM, the only audio accepted by the recognizer.
+    pub const RECOGNITION: AudioSpec = AudioSpec {
+        sample_rate: RECOGNITION_SAMPLE_RATE,
+        channels: 1,
+        format: SampleFormat::S16,
+    };
+
+    pub fn is_recognizable(&self) -> bool {
+        *self == Self::RECOGNITION
+    }
+
+    /// Whether the audio is 8kHz mono G.711, which only needs expanding to be recognised.
+    pub fn is_telephony(&self) -> bool {
+        self.sample_rate == RECOGNITION_SAMPLE_RATE
+            && self.channels == 1
+            && matches!(self.format, SampleFormat::ALaw | SampleFormat::MuLaw)
+    }
+
+    pub fn frame_width(&self) -> usize {
+        self.format.width() * usize::from(self.channels)
+    }
+
+    /// Playing time of `len` bytes of audio.
+    pub fn duration(&self, len: usize) -> Duration {
+        let frames = len / self.frame_width().max(1);
+        Duration::from_secs_f64(frames as f64 / f64::from(self.sample_rate.max(1)))
+    }
+}
+
+impl fmt::Display for AudioSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?}, {} channel(s), {}Hz",
+            self.format, self.channels, self.sample_rate
+        )
+    }
+}
+
+impl TryFrom<WavSpec> for AudioSpec {
+    type Error = WavError;
+
+    fn try_from(spec: WavSpec) -> Result<Self, Self::Error> {
+        let format = match (spec.encoding, spec.bits_per_sample) {
+            (WavEncoding::Pcm, 8) => SampleFormat::U8,
+            (WavEncoding::Pcm, 16) => SampleFormat::S16,
+            (WavEncoding::Pcm, 24) => SampleFormat::S24,
+            (WavEncoding::Pcm, 32) => SampleFormat::S32,
+            (WavEncoding::Float, 32) => SampleFormat::F32,
+            (WavEncoding::ALaw, 8) => SampleFormat::ALaw,
+            (WavEncoding::MuLaw, 8) => SampleFormat::MuLaw,
+            _ => return Err(WavError::Unsupported(spec)),
+        };
+        if spec.channels == 0 || spec.sample_rate == 0 {
+            return Err(WavError::Unsupported(spec));
+        }
+        Ok(Self {
+            sample_rate: spec.sample_rate,
+            channels: spec.channels,
+            format,
+        })
+    }
+}
+
+/// Incrementally converts audio of any [`AudioSpec`] into mono 8kHz signed 16-bit PCM.
+///
+/// Input may be split at any byte, partial frames are kept until the next call.
+pub struct Converter {
+    spec: AudioSpec,
+    pending: Vec<u8>,
+    resampler: Option<Resampler>,
+}
+
+impl Converter {
+    /// Fails for audio without channels or samples, which cannot be split into frames.
+    pub fn new(spec: AudioSpec) -> Result<Self, SpeechCenterError> {
+        if spec.channels == 0 || spec.sample_rate == 0 {
+            return Err(SpeechCenterError::InvalidParameter(format!(
+                "Audio format without channels or sample rate [{}]",
+                spec
+            )));
+        }
+        let resampler = (spec.sample_rate != RECOGNITION_SAMPLE_RATE)
+            .then(|| Resampler::new(spec.sample_rate, RECOGNITION_SAMPLE_RATE));
+        Ok(Self {
+            spec,
+            pending: vec![],
+            resampler,
+        })
+    }
+
+    pub fn process(&mut self, data: &[u8]) -> Vec<u8> {
+        if self.spec.is_recognizable() {
+            return data.to_vec();
+        }
+        self.pending.extend_from_slice(data);
+        let frame_width = self.spec.frame_width();
+        let complete = self.pending.len() - self.pending.len() % frame_width;
+        let samples = self.pending[..complete]
+            .chunks_exact(frame_width)
+            .map(|frame| self.downmix(frame))
+            .collect::<Vec<f32>>();
+        self.pending.drain(..complete);
+
+        match self.resampler.as_mut() {
+            Some(resampler) => to_pcm16(&resampler.process(&samples)),
+            None => to_pcm16(&samples),
+        }
+    }
+
+    pub fn finish(&mut self) -> Vec<u8> {
+        self.pending.clear();
+        match self.resampler.as_mut() {
+            Some(resampler) => to_pcm16(&resampler.finish()),
+            None => vec![],
+        }
+    }
+
+    fn downmix(&self, frame: &[u8]) -> f32 {
+        let sum: f32 = frame
+            .chunks_exact(self.spec.format.width())
+            .map(|sample| self.spec.format.decode(sample))
+            .sum();
+        sum / f32::from(self.spec.channels)
+    }
+}
+
+/// Converts a complete recording into mono 8kHz signed 16-bit PCM.
+pub fn convert(data: &[u8], spec: AudioSpec) -> Result<Vec<u8>, SpeechCenterError> {
+    let mut converter = Converter::new(spec)?;
+    let mut converted = converter.process(data);
+    converted.extend(converter.finish());
+    Ok(converted)
+}
+
+fn to_pcm16(samples: &[f32]) -> Vec<u8> {
+    samples
+        .iter()
+        .flat_map(|s| {
+            let s = (s * 32768.0).round().clamp(-32768.0, 32767.0) as i16;
+            s.to_le_bytes()
+        })
+        .collect()
+}
+
+/// Streaming band-limited resampler based on a Blackman-windowed sinc kernel.
+struct Resampler {
+    from: u64,
+    to: u64,
+    /// Kernel cutoff relative to the input Nyquist frequency
+    cutoff: f64,
+    /// Kernel radius in input samples
+    radius: f64,
+    buffer: Vec<f32>,
+    /// Input index of the first sample in `buffer`, negative while it holds the initial silence
+    offset: i64,
+    /// Number of input samples received
+    received: u64,
+    /// Number of output samples produced
+    produced: u64,
+}
+
+impl Resampler {
+    fn new(from: u32, to: u32) -> Self {
+        let cutoff = (f64::from(to) / f64::from(from)).min(1.0) * CUTOFF;
+        let radius = KERNEL_ZERO_CROSSINGS / cutoff;
+        // Samples before the start of the audio are silence
+        let padding = radius.ceil() as usize;
+        Self {
+            from: u64::from(from),
+            to: u64::from(to),
+            cutoff,
+            radius,
+            buffer: vec![0.0; padding],
+            offset: -(padding as i64),
+            received: 0,
+            produced: 0,
+        }
+    }
+
+    /// Position of the next output sample relative to the start of `buffer`
+    fn position(&self) -> f64 {
+        // Kept as an exact fraction so rounding errors never accumulate over long recordings
+        let numerator = self.produced * self.from;
+        let index = (numerator / self.to) as i64 - self.offset;
+        index as f64 + (numerator % self.to) as f64 / self.to as f64
+    }
+
+    fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+        self.buffer.extend_from_slice(samples);
+        self.received += samples.len() as u64;
+
+        let mut output = vec![];
+        let end = self.buffer.len() as f64 - self.radius - 1.0;
+        while self.position() < end {
+            output.push(self.sample_at(self.position()));
+            self.produced += 1;
+        }
+
+        let consumed = (self.position() - self.radius).floor() - 1.0;
+        if consumed > 0.0 {
+            self.buffer.drain(..consumed as usize);
+            self.offset += consumed as i64;
+        }
+        output
+    }
+
+    fn finish(&mut self) -> Vec<f32> {
+        let padding = self.radius.ceil() as usize + 1;
+        self.buffer.extend(std::iter::repeat_n(0.0, padding));
+
+        let mut output = vec![];
+        while self.produced * self.from < self.received * self.to {
+            output.push(self.sample_at(self.position()));
+            self.produced += 1;
+        }
+        output
+    }
+
+    fn sample_at(&self, position: f64) -> f32 {
+        let first = (position - self.radius).ceil().max(0.0) as usize;
+        let last = ((position + self.radius).floor() as usize).min(self.buffer.len() - 1);
+        let mut sum = 0.0;
+        for (i, sample) in self.buffer[first..=last].iter().enumerate() {
+            let distance = position - (first + i) as f64;
+            sum += f64::from(*sample) * self.kernel(distance);
+        }
+        sum as f32
+    }
+
+    fn kernel(&self, distance: f64) -> f64 {
+        let x = distance * self.cutoff;
+        let sinc = if x == 0.0 {
+            1.0
+        } else {
+            (PI * x).sin() / (PI * x)
+        };
+        let w = 0.5 + 0.5 * distance / self.radius;
+        let window = 0.42 - 0.5 * (2.0 * PI * w).cos() + 0.08 * (4.0 * PI * w).cos();
+        self.cutoff * sinc * window
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pcm16(data: &[u8]) -> Vec<i16> {
+        data.chunks_exact(2)
+            .map(|s| i16::from_le_bytes([s[0], s[1]]))
+            .collect()
+    }
+
+    fn sine(rate: u32, frequency: f64, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| (0.5 * (2.0 * PI * frequency * i as f64 / f64::from(rate)).sin()) as f32)
+            .collect()
+    }
+
+    #[test]
+    fn test_identity() {
+        let data = vec![1, 2, 3, 4, 5, 6];
+        assert_eq!(convert(&data, AudioSpec::RECOGNITION).unwrap(), data);
+    }
+
+    #[test]
+    fn test_downmix_and_formats() {
+        let spec = AudioSpec {
+            sample_rate: 8000,
+            channels: 2,
+            format: SampleFormat::F32,
+        };
+        let data = [0.5f32, 0.0, -0.25, -0.25]
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect::<Vec<u8>>();
+        assert_eq!(pcm16(&convert(&data, spec).unwrap()), vec![8192, -8192]);
+
+        let spec = AudioSpec {
+            sample_rate: 8000,
+            channels: 1,
+            format: SampleFormat::S24,
+        };
+        assert_eq!(
+            pcm16(&convert(&[0, 0, 0x80, 0, 0, 0x40], spec).unwrap()),
+            vec![-32768, 16384]
+        );
+
+        let spec = AudioSpec {
+            sample_rate: 8000,
+            channels: 1,
+            format: SampleFormat::U8,
+        };
+        assert_eq!(
+            pcm16(&convert(&[128, 192, 0], spec).unwrap()),
+            vec![0, 16384, -32768]
+        );
+    }
+
+    #[test]
+    fn test_rejects_empty_spec() {
+        let spec = AudioSpec {
+            sample_rate: 8000,
+            channels: 0,
+            format: SampleFormat::S16,
+        };
+        assert!(matches!(
+            Converter::new(spec),
+            Err(SpeechCenterError::InvalidParameter(_))
+        ));
+        let spec = AudioSpec {
+            sample_rate: 0,
+            channels: 1,
+            format: SampleFormat::F32,
+        };
+        let error = convert(&[0; 8], spec).unwrap_err();
+        assert!(matches!(error, SpeechCenterError::InvalidParameter(_)));
+    }
+
+    #[test]
+    fn test_expand_g711() {
+        let spec = AudioSpec {
+            sample_rate: 8000,
+            channels: 1,
+            format: SampleFormat::MuLaw,
+        };
+        assert!(spec.is_telephony());
+        assert_eq!(
+            pcm16(&convert(&[0xFF, 0x00], spec).unwrap()),
+            vec![0, -32124]
+        );
+    }
+
+    #[test]
+    fn test_resample_in_chunks() {
+        let spec = AudioSpec {
+            sample_rate: 44100,
+            channels: 1,
+            format: SampleFormat::F32,
+        };
+        let data = sine(44100, 440.0, 44100)
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect::<Vec<u8>>();
+
+        let mut converter = Converter::new(spec).unwrap();
+        let mut output = vec![];
+        for chunk in data.chunks(1001) {
+            output.extend(converter.process(chunk));
+        }
+        output.extend(converter.finish());
+        let output = pcm16(&output);
+        assert_eq!(output.len(), 8000);
+
+        let expected = sine(8000, 440.0, 8000);
+        let max_error = output[100..7900]
+            .iter()
+            .zip(&expected[100..7900])
+            .map(|(o, e)| (f32::from(*o) / 32768.0 - e).abs())
+            .fold(0.0, f32::max);
+        assert!(max_error < 0.01, "Resampling error too high: {}", max_error);
+    }
+
+    #[test]
+    fn test_resample_removes_aliasing() {
+        let spec = AudioSpec {
+            sample_rate: 16000,
+            channels: 1,
+            format: SampleFormat::F32,
+        };
+        let data = sine(16000, 6000.0, 16000)
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect::<Vec<u8>>();
+        let output = pcm16(&convert(&data, spec).unwrap());
+        let peak = output[100..7900].iter().map(|s| s.abs()).max().unwrap();
+        assert!(
+            peak < 500,
+            "Frequencies above 4kHz should be filtered: {}",
+            peak
+        );
+    }
+}
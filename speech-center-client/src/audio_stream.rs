@@ -0,0 +1,453 @@
+use crate::audio::{AudioSpec, Converter};
+use crate::decoder::{DecodeError, Decoder};
+use crate::error::SpeechCenterError;
+use crate::wav::{self, WavError};
+use bytes::{Bytes, BytesMut};
+use futures_core::Stream;
+use std::io::{self, Read, SeekFrom};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use symphonia::core::io::{MediaSource, ReadOnlySource};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt};
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+
+/// Decoded chunks buffered ahead of the recognition stream
+const DECODED_CHUNKS_BUFFER: usize = 16;
+
+/// Sample rate in Hz of the audio accepted by the recognizer.
+pub const RECOGNITION_SAMPLE_RATE: u32 = 8000;
+/// Size in bytes of a single signed 16-bit little endian PCM sample.
+pub const RECOGNITION_SAMPLE_WIDTH: usize = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChunkSize {
+    Bytes(usize),
+    Duration(Duration),
+}
+
+impl Default for ChunkSize {
+    fn default() -> Self {
+        Self::Duration(Duration::from_millis(100))
+    }
+}
+
+impl ChunkSize {
+    pub fn to_bytes(self) -> usize {
+        let bytes = match self {
+            Self::Bytes(bytes) => bytes,
+            Self::Duration(duration) => {
+                let samples =
+                    duration.as_micros() * u128::from(RECOGNITION_SAMPLE_RATE) / 1_000_000;
+                samples as usize * RECOGNITION_SAMPLE_WIDTH
+            }
+        };
+        (bytes - bytes % RECOGNITION_SAMPLE_WIDTH).max(RECOGNITION_SAMPLE_WIDTH)
+    }
+}
+
+/// Audio to be recognised, pulled lazily from its source in chunks of a fixed size.
+pub struct AudioStream {
+    spec: AudioSpec,
+    chunk_bytes: usize,
+    inner: Pin<Box<dyn Stream<Item = io::Result<Bytes>> + Send>>,
+}
+
+impl AudioStream {
+    pub fn from_reader<R>(mut reader: R, chunk_size: ChunkSize) -> Self
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+    {
+        let chunk_bytes = chunk_size.to_bytes();
+        let s = async_stream::stream! {
+            loop {
+                let mut buffer = BytesMut::with_capacity(chunk_bytes);
+                match reader.read_buf(&mut buffer).await {
+                    Ok(0) => break,
+                    Ok(_) => yield Ok(buffer.freeze()),
+                    Err(e) => {
+                        yield Err(e);
+                        break;
+                    }
+                }
+            }
+        };
+        Self::rechunked(s, AudioSpec::RECOGNITION, chunk_bytes)
+    }
+
+    pub fn from_stream<S>(stream: S, chunk_size: ChunkSize) -> Self
+    where
+        S: Stream<Item = Bytes> + Send + 'static,
+    {
+        Self::rechunked(
+            stream.map(Ok),
+            AudioSpec::RECOGNITION,
+            chunk_size.to_bytes(),
+        )
+    }
+
+    pub fn from_bytes(audio: impl Into<Bytes>, chunk_size: ChunkSize) -> Self {
+        let audio = audio.into();
+        Self::from_stream(tokio_stream::once(audio), chunk_size)
+    }
+
+    /// Streams the samples of a WAV file, tagged with the format declared in its header.
+    pub async fn from_wav_reader<R>(mut reader: R, chunk_size: ChunkSize) -> Result<Self, WavError>
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+    {
+        let header = wav::read_header(&mut reader).await?;
+        let spec = AudioSpec::try_from(header.spec)?;
+        let audio = match header.data_len {
+            Some(len) => Self::from_reader(reader.take(u64::from(len)), chunk_size),
+            None => Self::from_reader(reader, chunk_size),
+        };
+        Ok(audio.with_spec(spec))
+    }
+
+    /// Decodes compressed audio from a non-seekable source, detecting the format from its content.
+    ///
+    /// The result is already converted into mono 8kHz signed 16-bit PCM.
+    pub async fn from_encoded_reader<R>(
+        reader: R,
+        chunk_size: ChunkSize,
+    ) -> Result<Self, DecodeError>
+    where
+        R: Read + Send + Sync + 'static,
+    {
+        Self::decoded(Box::new(ReadOnlySource::new(reader)), chunk_size).await
+    }
+
+    /// Opens a WAV file or any compressed audio supported by [`Decoder`], based on its content.
+    ///
+    /// Compressed audio is converted into mono 8kHz signed 16-bit PCM, WAV files keep their format.
+    pub async fn from_file(
+        path: impl AsRef<Path>,
+        chunk_size: ChunkSize,
+    ) -> Result<Self, DecodeError> {
+        let mut file = tokio::fs::File::open(path)
+            .await
+            .map_err(|e| DecodeError::Io(e.to_string()))?;
+        let mut magic = [0u8; 12];
+        let read = file
+            .read(&mut magic)
+            .await
+            .map_err(|e| DecodeError::Io(e.to_string()))?;
+        file.seek(SeekFrom::Start(0))
+            .await
+            .map_err(|e| DecodeError::Io(e.to_string()))?;
+        if read == magic.len() && &magic[0..4] == b"RIFF" && &magic[8..12] == b"WAVE" {
+            return Ok(Self::from_wav_reader(file, chunk_size).await?);
+        }
+        Self::decoded(Box::new(file.into_std().await), chunk_size).await
+    }
+
+    async fn decoded(
+        source: Box<dyn MediaSource>,
+        chunk_size: ChunkSize,
+    ) -> Result<Self, DecodeError> {
+        // Decoding is CPU bound and symphonia only reads synchronously
+        let (spec_tx, spec_rx) = oneshot::channel();
+        let (tx, rx) = mpsc::channel(DECODED_CHUNKS_BUFFER);
+        tokio::task::spawn_blocking(move || {
+            let mut decoder = match Decoder::new(source) {
+                Ok(decoder) => decoder,
+                Err(e) => {
+                    let _ = spec_tx.send(Err(e));
+                    return;
+                }
+            };
+            let _ = spec_tx.send(Ok(decoder.spec()));
+            loop {
+                let chunk = match decoder.next_chunk() {
+                    Ok(Some(chunk)) => Ok(Bytes::from(chunk)),
+                    Ok(None) => break,
+                    Err(e) => Err(io::Error::from(e)),
+                };
+                let failed = chunk.is_err();
+                if tx.blocking_send(chunk).is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        let spec = spec_rx
+            .await
+            .map_err(|_| DecodeError::Malformed("Decoder stopped unexpectedly".to_string()))??;
+        let audio = Self::rechunked(ReceiverStream::new(rx), spec, chunk_size.to_bytes());
+        audio
+            .convert()
+            .map_err(|e| DecodeError::Malformed(e.to_string()))
+    }
+
+    /// Declares the format of the audio, mono 8kHz signed 16-bit PCM unless stated otherwise.
+    pub fn with_spec(mut self, spec: AudioSpec) -> Self {
+        self.spec = spec;
+        self
+    }
+
+    pub fn spec(&self) -> AudioSpec {
+        self.spec
+    }
+
+    pub(crate) fn chunk_bytes(&self) -> usize {
+        self.chunk_bytes
+    }
+
+    /// Pulls the first chunk of the audio, returning `None` when there is no audio at all.
+    pub async fn non_empty(mut self) -> io::Result<Option<Self>> {
+        let first = match self.inner.next().await {
+            Some(chunk) => chunk?,
+            None => return Ok(None),
+        };
+        Ok(Some(Self {
+            spec: self.spec,
+            chunk_bytes: self.chunk_bytes,
+            inner: Box::pin(tokio_stream::once(Ok(first)).chain(self.inner)),
+        }))
+    }
+
+    /// Reads the whole audio into memory, returning it along with a stream of the same audio.
+    pub(crate) async fn buffered(mut self) -> io::Result<(Bytes, Self)> {
+        let mut audio = BytesMut::new();
+        while let Some(chunk) = self.next().await {
+            audio.extend_from_slice(&chunk?);
+        }
+        let audio = audio.freeze();
+        let stream = Self::from_bytes(audio.clone(), ChunkSize::Bytes(self.chunk_bytes));
+        Ok((audio, stream.with_spec(self.spec)))
+    }
+
+    /// Converts the audio into the mono 8kHz signed 16-bit PCM accepted by the recognizer.
+    pub fn convert(self) -> Result<Self, SpeechCenterError> {
+        if self.spec.is_recognizable() {
+            return Ok(self);
+        }
+        let mut converter = Converter::new(self.spec)?;
+        let mut source = self.inner;
+        let s = async_stream::stream! {
+            while let Some(data) = source.next().await {
+                match data {
+                    Ok(data) => yield Ok(Bytes::from(converter.process(&data))),
+                    Err(e) => {
+                        yield Err(e);
+                        return;
+                    }
+                }
+            }
+            yield Ok(Bytes::from(converter.finish()));
+        };
+        Ok(Self::rechunked(s, AudioSpec::RECOGNITION, self.chunk_bytes))
+    }
+
+    pub(crate) fn rechunked<S>(stream: S, spec: AudioSpec, chunk_bytes: usize) -> Self
+    where
+        S: Stream<Item = io::Result<Bytes>> + Send + 'static,
+    {
+        let s = async_stream::stream! {
+            tokio::pin!(stream);
+            let mut pending = BytesMut::new();
+            while let Some(data) = stream.next().await {
+                match data {
+                    Ok(data) => {
+                        pending.extend_from_slice(&data);
+                        while pending.len() >= chunk_bytes {
+                            yield Ok(pending.split_to(chunk_bytes).freeze());
+                        }
+                    }
+                    Err(e) => {
+                        yield Err(e);
+                        return;
+                    }
+                }
+            }
+            if !pending.is_empty() {
+                yield Ok(pending.freeze());
+            }
+        };
+        Self {
+            spec,
+            chunk_bytes,
+            inner: Box::pin(s),
+        }
+    }
+}
+
+impl Stream for AudioStream {
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.as_mut().poll_next(cx)
+    }
+}
+
+/// Audio that can be streamed again from the start, so failed calls can be retried.
+///
+/// Up to `limit` bytes are read ahead before the first attempt, as the source cannot be read twice.
+/// Audio ending within them is kept in memory and sent again on every attempt, longer audio is
+/// streamed once and never retried, however much of it was sent before the call failed.
+#[derive(Clone)]
+pub(crate) enum ReplayableAudio {
+    Kept(Arc<[Bytes]>),
+    Once(Arc<Mutex<Option<AudioStream>>>),
+}
+
+impl ReplayableAudio {
+    pub(crate) async fn new(mut source: AudioStream, limit: usize) -> Self {
+        let mut chunks = vec![];
+        let mut read = 0;
+        let mut error = None;
+        while limit > 0 && read <= limit && error.is_none() {
+            match source.next().await {
+                Some(Ok(chunk)) => {
+                    read += chunk.len();
+                    chunks.push(chunk);
+                }
+                // The first attempt reports the error once it reaches it
+                Some(Err(e)) => error = Some(e),
+                None => return Self::Kept(chunks.into()),
+            }
+        }
+        if read > limit {
+            debug!("Audio too long to be replayed, it will not be retried");
+        }
+        let read = chunks.into_iter().map(Ok).chain(error.map(Err));
+        let rest = source.inner;
+        source.inner = Box::pin(tokio_stream::iter(read).chain(rest));
+        Self::Once(Arc::new(Mutex::new(Some(source))))
+    }
+
+    /// Whether the whole audio is in memory, to be sent as many times as needed.
+    pub(crate) fn is_replayable(&self) -> bool {
+        matches!(self, Self::Kept(_))
+    }
+
+    /// Streams the audio from the start, or nothing if it was streamed once already.
+    pub(crate) fn replay(&self) -> impl Stream<Item = io::Result<Bytes>> + Send + 'static {
+        let audio = self.clone();
+        async_stream::stream! {
+            match audio {
+                Self::Kept(chunks) => {
+                    for chunk in chunks.iter() {
+                        yield Ok(chunk.clone());
+                    }
+                }
+                Self::Once(source) => {
+                    let source = source.lock().unwrap().take();
+                    if let Some(mut source) = source {
+                        while let Some(chunk) = source.next().await {
+                            yield chunk;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    async fn collect(mut audio: AudioStream) -> Vec<usize> {
+        let mut sizes = vec![];
+        while let Some(chunk) = audio.next().await {
+            sizes.push(chunk.expect("Chunk should be readable").len());
+        }
+        sizes
+    }
+
+    #[test]
+    fn test_chunk_size_to_bytes() {
+        assert_eq!(
+            ChunkSize::Duration(Duration::from_millis(100)).to_bytes(),
+            1600
+        );
+        assert_eq!(ChunkSize::Bytes(1001).to_bytes(), 1000);
+        assert_eq!(ChunkSize::Bytes(0).to_bytes(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_rechunk_stream() {
+        let parts = vec![
+            Bytes::from(vec![0u8; 3]),
+            Bytes::from(vec![0u8; 10]),
+            Bytes::from(vec![0u8; 1]),
+        ];
+        let audio = AudioStream::from_stream(tokio_stream::iter(parts), ChunkSize::Bytes(4));
+        assert_eq!(collect(audio).await, vec![4, 4, 4, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_decode_encoded_reader() {
+        let flac = crate::decoder::test::flac_file(16000, &[0i16; 3200]);
+        let audio = AudioStream::from_encoded_reader(io::Cursor::new(flac), ChunkSize::default())
+            .await
+            .expect("Should decode the FLAC audio");
+        assert!(audio.spec().is_recognizable());
+        assert_eq!(collect(audio).await, vec![1600, 1600]);
+    }
+
+    #[tokio::test]
+    async fn test_chunk_reader() {
+        let data: &'static [u8] = &[0u8; 3300];
+        let audio = AudioStream::from_reader(data, ChunkSize::default());
+        assert_eq!(collect(audio).await, vec![1600, 1600, 100]);
+    }
+
+    #[tokio::test]
+    async fn test_non_empty() {
+        let data: &'static [u8] = &[0u8; 3300];
+        let audio = AudioStream::from_reader(data, ChunkSize::default());
+        let audio = audio
+            .non_empty()
+            .await
+            .unwrap()
+            .expect("Audio is not empty");
+        assert_eq!(collect(audio).await, vec![1600, 1600, 100]);
+
+        let audio = AudioStream::from_bytes(vec![], ChunkSize::default());
+        assert!(audio.non_empty().await.unwrap().is_none());
+    }
+
+    async fn replayed(audio: &ReplayableAudio) -> Vec<usize> {
+        let mut replay = Box::pin(audio.replay());
+        let mut sizes = vec![];
+        while let Some(chunk) = replay.next().await {
+            sizes.push(chunk.expect("Chunk should be readable").len());
+        }
+        sizes
+    }
+
+    #[tokio::test]
+    async fn test_replay() {
+        let audio = AudioStream::from_bytes(vec![0u8; 3300], ChunkSize::default());
+        let audio = ReplayableAudio::new(audio, 3300).await;
+        assert!(audio.is_replayable());
+
+        let mut first = Box::pin(audio.replay());
+        assert_eq!(first.next().await.unwrap().unwrap().len(), 1600);
+        drop(first);
+        assert_eq!(replayed(&audio).await, vec![1600, 1600, 100]);
+        assert_eq!(replayed(&audio).await, vec![1600, 1600, 100]);
+    }
+
+    #[tokio::test]
+    async fn test_replay_limit() {
+        let audio = AudioStream::from_bytes(vec![0u8; 3300], ChunkSize::default());
+        let audio = ReplayableAudio::new(audio, 3299).await;
+        // Known before anything is sent, the audio read ahead is streamed all the same
+        assert!(!audio.is_replayable());
+        assert_eq!(replayed(&audio).await, vec![1600, 1600, 100]);
+        assert!(replayed(&audio).await.is_empty());
+
+        let audio = AudioStream::from_bytes(vec![0u8; 3300], ChunkSize::default());
+        let audio = ReplayableAudio::new(audio, 0).await;
+        assert!(!audio.is_replayable());
+        assert_eq!(replayed(&audio).await, vec![1600, 1600, 100]);
+    }
+}
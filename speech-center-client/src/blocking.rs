@@ -0,0 +1,356 @@
+//! Synchronous clients for applications that do not run an async runtime.
+//!
+//! Every client runs its calls on an internal tokio runtime, so they must not be used from within
+//! an async context.
+
+use crate::audio::AudioSpec;
+use crate::audio_stream::{AudioStream, ChunkSize};
+use crate::config::SpeechCenterConfig;
+use crate::credentials::{CredentialsProvider, StaticToken};
+use crate::recognizer_client::Resource;
+use crate::result::{RecognitionResult, SynthesisResult};
+use crate::service::Recognizer;
+use crate::synthesizer_client::{AudioFormat, SampleRate, Speaker};
+use crate::{Result, SpeechCenterError, Topic, Vad};
+use bytes::Bytes;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::ReceiverStream;
+
+/// Chunks read ahead from a blocking reader while the previous ones are being sent.
+const READ_AHEAD_CHUNKS: usize = 16;
+
+fn runtime() -> Result<Arc<Runtime>> {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .thread_name("speech-center-client")
+        .enable_all()
+        .build()
+        .map(Arc::new)
+        .map_err(|e| SpeechCenterError::io("Error starting runtime", e))
+}
+
+/// Streams the reader from a dedicated thread, as it may block for as long as it needs.
+fn reader_stream<R>(mut reader: R, chunk_size: ChunkSize) -> AudioStream
+where
+    R: Read + Send + 'static,
+{
+    let chunk_bytes = chunk_size.to_bytes();
+    let (tx, rx) = mpsc::channel(READ_AHEAD_CHUNKS);
+    std::thread::spawn(move || loop {
+        let mut buffer = vec![0; chunk_bytes];
+        let chunk = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => {
+                buffer.truncate(read);
+                Ok(Bytes::from(buffer))
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => Err(e),
+        };
+        let failed = chunk.is_err();
+        if tx.blocking_send(chunk).is_err() || failed {
+            break;
+        }
+    });
+    AudioStream::rechunked(ReceiverStream::new(rx), AudioSpec::RECOGNITION, chunk_bytes)
+}
+
+/// Blocking [`crate::RecognitionClient`].
+#[derive(Clone, Debug)]
+pub struct RecognitionClient {
+    inner: crate::RecognitionClient,
+    runtime: Arc<Runtime>,
+}
+
+impl RecognitionClient {
+    pub fn new(url: &str, credentials: &str) -> Result<Self> {
+        let credentials = Arc::new(StaticToken::new(credentials));
+        Self::with_config(SpeechCenterConfig::new(url), credentials)
+    }
+
+    pub fn with_config(
+        config: SpeechCenterConfig,
+        credentials: Arc<dyn CredentialsProvider>,
+    ) -> Result<Self> {
+        let runtime = runtime()?;
+        let inner = runtime.block_on(crate::RecognitionClient::with_config(config, credentials))?;
+        Ok(Self { inner, runtime })
+    }
+
+    /// Converts audio in any supported format to mono 8kHz PCM16 instead of rejecting it.
+    pub fn with_audio_conversion(mut self, enabled: bool) -> Self {
+        self.inner = self.inner.with_audio_conversion(enabled);
+        self
+    }
+
+    /// Trims the silence before and after the speech with voice activity detection.
+    pub fn with_vad(mut self, vad: Option<Vad>) -> Self {
+        self.inner = self.inner.with_vad(vad);
+        self
+    }
+
+    pub fn recognise_with_topic(
+        &self,
+        language: &str,
+        topic: Topic,
+        audio: Vec<u8>,
+    ) -> Result<RecognitionResult> {
+        self.runtime
+            .block_on(self.inner.recognise_with_topic(language, topic, audio))
+    }
+
+    pub fn recognise_with_grammar(
+        &self,
+        grammar: &str,
+        language: &str,
+        audio: Vec<u8>,
+    ) -> Result<RecognitionResult> {
+        self.runtime
+            .block_on(self.inner.recognise_with_grammar(grammar, language, audio))
+    }
+
+    /// Recognises mono 8kHz signed 16-bit PCM streamed from the reader as it is read.
+    pub fn recognise_reader_with_topic<R>(
+        &self,
+        language: &str,
+        topic: Topic,
+        audio: R,
+    ) -> Result<RecognitionResult>
+    where
+        R: Read + Send + 'static,
+    {
+        let audio = reader_stream(audio, ChunkSize::default());
+        self.runtime.block_on(
+            self.inner
+                .recognise_stream_with_topic(language, topic, audio),
+        )
+    }
+
+    /// Recognises mono 8kHz signed 16-bit PCM streamed from the reader as it is read.
+    pub fn recognise_reader_with_grammar<R>(
+        &self,
+        grammar: &str,
+        language: &str,
+        audio: R,
+    ) -> Result<RecognitionResult>
+    where
+        R: Read + Send + 'static,
+    {
+        let audio = reader_stream(audio, ChunkSize::default());
+        self.runtime.block_on(
+            self.inner
+                .recognise_stream_with_grammar(grammar, language, audio),
+        )
+    }
+
+    /// Starts recognising mono 8kHz signed 16-bit PCM pushed to the returned recognition.
+    pub fn start_with_topic(&self, language: &str, topic: Topic) -> Recognition {
+        self.start(language, Resource::Topic(topic))
+    }
+
+    /// Starts recognising mono 8kHz signed 16-bit PCM pushed to the returned recognition.
+    pub fn start_with_grammar(&self, grammar: &str, language: &str) -> Recognition {
+        self.start(language, Resource::Grammar(grammar.to_string()))
+    }
+
+    fn start(&self, language: &str, resource: Resource) -> Recognition {
+        let chunk_bytes = ChunkSize::default().to_bytes();
+        let (tx, rx) = mpsc::channel(READ_AHEAD_CHUNKS);
+        let audio =
+            AudioStream::rechunked(ReceiverStream::new(rx), AudioSpec::RECOGNITION, chunk_bytes);
+        let inner = self.inner.clone();
+        let language = language.to_string();
+        let result = self
+            .runtime
+            .spawn(async move { inner.recognise_stream(&language, resource, audio).await });
+        Recognition {
+            audio: Some(tx),
+            result,
+            runtime: self.runtime.clone(),
+        }
+    }
+}
+
+/// Recognition in progress, sending the audio as it is pushed.
+///
+/// Dropping it without calling [`Recognition::finish`] cancels the call.
+#[derive(Debug)]
+pub struct Recognition {
+    audio: Option<mpsc::Sender<io::Result<Bytes>>>,
+    result: JoinHandle<Result<RecognitionResult>>,
+    runtime: Arc<Runtime>,
+}
+
+impl Recognition {
+    /// Queues the audio to be sent, waiting while too much audio is already queued.
+    ///
+    /// Fails if the call has already ended, whose outcome is then returned by
+    /// [`Recognition::finish`].
+    pub fn push(&mut self, audio: &[u8]) -> Result<()> {
+        let sent = match &self.audio {
+            Some(tx) => tx.blocking_send(Ok(Bytes::copy_from_slice(audio))).is_ok(),
+            None => false,
+        };
+        if !sent {
+            self.audio = None;
+            return Err(SpeechCenterError::io(
+                "Error pushing audio",
+                io::Error::new(io::ErrorKind::BrokenPipe, "Recognition already ended"),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Ends the audio and waits for the transcript.
+    pub fn finish(mut self) -> Result<RecognitionResult> {
+        self.audio = None;
+        self.runtime
+            .block_on(&mut self.result)
+            .map_err(|e| SpeechCenterError::io("Error waiting for the recognition", e.into()))?
+    }
+}
+
+impl Drop for Recognition {
+    fn drop(&mut self) {
+        self.result.abort();
+    }
+}
+
+/// Blocking [`crate::SynthesisClient`].
+#[derive(Clone, Debug)]
+pub struct SynthesisClient {
+    inner: crate::SynthesisClient,
+    runtime: Arc<Runtime>,
+}
+
+impl SynthesisClient {
+    pub fn new(url: &str, credentials: &str) -> Result<Self> {
+        let credentials = Arc::new(StaticToken::new(credentials));
+        Self::with_config(SpeechCenterConfig::new(url), credentials)
+    }
+
+    pub fn with_config(
+        config: SpeechCenterConfig,
+        credentials: Arc<dyn CredentialsProvider>,
+    ) -> Result<Self> {
+        let runtime = runtime()?;
+        let inner = runtime.block_on(crate::SynthesisClient::with_config(config, credentials))?;
+        Ok(Self { inner, runtime })
+    }
+
+    pub fn synthesize(
+        &self,
+        speaker: Speaker,
+        sample_rate: SampleRate,
+        audio_format: AudioFormat,
+        text: &str,
+    ) -> Result<SynthesisResult> {
+        self.runtime.block_on(
+            self.inner
+                .synthesize(speaker, sample_rate, audio_format, text),
+        )
+    }
+
+    /// Synthesizes the text into the writer, in the requested format.
+    pub fn synthesize_to<W: Write>(
+        &self,
+        speaker: Speaker,
+        sample_rate: SampleRate,
+        audio_format: AudioFormat,
+        text: &str,
+        writer: W,
+    ) -> Result<SynthesisResult> {
+        let res = self.synthesize(speaker, sample_rate, audio_format, text)?;
+        let written = match res.audio_format.has_header() {
+            true => res.write_wav(writer),
+            false => res.write_raw(writer),
+        };
+        written.map_err(|e| SpeechCenterError::io("Error writing synthesized audio", e))?;
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testkit::{audio_digest, Match, MockServer, Reply};
+    use std::time::Duration;
+
+    #[test]
+    fn test_blocking_clients() {
+        let audio = vec![1u8; 8000];
+        let server_runtime = runtime().unwrap();
+        let server = server_runtime
+            .block_on(
+                MockServer::builder()
+                    .respond(
+                        Match::Audio(audio_digest(&audio)),
+                        Reply::Transcript("hello".to_string()),
+                    )
+                    .respond(Match::Text("hello".to_string()), Reply::Audio(vec![0; 16]))
+                    .start(),
+            )
+            .unwrap();
+        let credentials = Arc::new(StaticToken::new(""));
+
+        let client = RecognitionClient::with_config(server.config(), credentials.clone())
+            .expect("Should connect to the mock server");
+        let res = client
+            .recognise_reader_with_topic("en-US", Topic::Generic, io::Cursor::new(audio.clone()))
+            .expect("Should recognise the audio read");
+        assert_eq!(res.transcript, "hello");
+        let res = client
+            .recognise_with_topic("en-US", Topic::Generic, audio.clone())
+            .unwrap();
+        assert_eq!(res.transcript, "hello");
+        let mut recognition = client.start_with_topic("en-US", Topic::Generic);
+        for chunk in audio.chunks(1000) {
+            recognition.push(chunk).expect("Should queue the audio");
+        }
+        assert_eq!(recognition.finish().unwrap().transcript, "hello");
+
+        let client = SynthesisClient::with_config(server.config(), credentials)
+            .expect("Should connect to the mock server");
+        let mut output = vec![];
+        let res = client
+            .synthesize_to(
+                Speaker::EnUsTommy,
+                SampleRate::Khz8,
+                AudioFormat::RawLpcmS16le,
+                "hello",
+                &mut output,
+            )
+            .expect("Should synthesize the text");
+        assert_eq!(res.duration(), Duration::from_millis(1));
+        assert_eq!(output, [0; 16]);
+    }
+
+    #[test]
+    fn test_read_error() {
+        struct Failing;
+        impl Read for Failing {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::other("Disconnected"))
+            }
+        }
+        let server_runtime = runtime().unwrap();
+        let server = server_runtime
+            .block_on(
+                MockServer::builder()
+                    .respond(Match::Any, Reply::Transcript("hello".to_string()))
+                    .start(),
+            )
+            .unwrap();
+        let client =
+            RecognitionClient::with_config(server.config(), Arc::new(StaticToken::new("")))
+                .unwrap();
+        let error = client
+            .recognise_reader_with_topic("en-US", Topic::Generic, Failing)
+            .expect_err("Should fail reading the audio");
+        assert!(matches!(error, SpeechCenterError::Io { .. }));
+    }
+}
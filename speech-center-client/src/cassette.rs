@@ -0,0 +1,459 @@
+//! Recording of recognition and synthesis exchanges, to replay them later without the service.
+
+use crate::csr_grpc_gateway::recognition_request::RequestUnion;
+use crate::csr_grpc_gateway::recognition_resource::ResourceUnion;
+use crate::csr_grpc_gateway::{RecognitionInit, RecognitionRequest, RecognitionResponse};
+use crate::speechcenter_tts_v1::synthesis_voice::SynthesisUnion;
+use crate::speechcenter_tts_v1::{SynthesisRequest, SynthesisResponse};
+use crate::Result;
+use async_trait::async_trait;
+use futures_core::Stream;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::time::Instant;
+use tokio_stream::StreamExt;
+use tonic::{Code, Status};
+
+/// Hex encoded SHA-256 of the audio, as sent to the recognizer after any conversion.
+pub fn audio_digest(audio: &[u8]) -> String {
+    hex(AudioHasher::default().update(audio))
+}
+
+fn hex(hasher: &AudioHasher) -> String {
+    hasher
+        .0
+        .clone()
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Incremental [`audio_digest`] of streamed audio.
+#[derive(Clone, Default)]
+pub(crate) struct AudioHasher(Sha256);
+
+impl AudioHasher {
+    pub(crate) fn update(&mut self, chunk: &[u8]) -> &mut Self {
+        self.0.update(chunk);
+        self
+    }
+
+    pub(crate) fn digest(&self) -> String {
+        hex(self)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "service")]
+enum Request {
+    Recognition {
+        language: String,
+        topic: Option<i32>,
+        grammar: Option<String>,
+        audio_digest: String,
+    },
+    Synthesis {
+        voice: Option<i32>,
+        text: String,
+        sample_rate: i32,
+        audio_format: i32,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Response {
+    Transcript(String),
+    /// Base64 encoded audio
+    Audio(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct RecordedStatus {
+    code: i32,
+    message: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Interaction {
+    request: Request,
+    response: Option<Response>,
+    status: Option<RecordedStatus>,
+    duration_ms: u64,
+}
+
+#[derive(Debug)]
+enum Mode {
+    Record(tokio::sync::Mutex<tokio::fs::File>),
+    Replay(Mutex<Vec<(Interaction, bool)>>),
+}
+
+/// File of recorded exchanges, one JSON object per line.
+///
+/// When recording, every call made by the clients is appended to the file. When replaying, the
+/// clients never reach the service and answer with the recorded exchanges instead, as late as
+/// they were answered, in the order they were recorded, repeating the last one for requests made
+/// more times than recorded.
+#[derive(Debug)]
+pub struct Cassette {
+    path: PathBuf,
+    mode: Mode,
+}
+
+impl Cassette {
+    /// Starts recording into `path`, replacing any previous recording.
+    pub fn record(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = tokio::fs::File::from_std(File::create(&path)?);
+        Ok(Self {
+            path,
+            mode: Mode::Record(tokio::sync::Mutex::new(file)),
+        })
+    }
+
+    /// Loads the exchanges recorded in `path` to replay them.
+    pub fn replay(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut interactions = vec![];
+        for line in BufReader::new(File::open(&path)?).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let interaction = serde_json::from_str(&line)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            interactions.push((interaction, false));
+        }
+        Ok(Self {
+            path,
+            mode: Mode::Replay(Mutex::new(interactions)),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn is_replay(&self) -> bool {
+        matches!(self.mode, Mode::Replay(_))
+    }
+
+    async fn save(&self, request: Request, result: Result<Response, &Status>, duration: Duration) {
+        let file = match &self.mode {
+            Mode::Record(file) => file,
+            Mode::Replay(_) => return,
+        };
+        let (response, status) = match result {
+            Ok(response) => (Some(response), None),
+            Err(status) => (
+                None,
+                Some(RecordedStatus {
+                    code: status.code() as i32,
+                    message: status.message().to_string(),
+                }),
+            ),
+        };
+        let interaction = Interaction {
+            request,
+            response,
+            status,
+            duration_ms: duration.as_millis() as u64,
+        };
+        let mut line = serde_json::to_vec(&interaction).expect("Interactions are serializable");
+        line.push(b'\n');
+        let mut file = file.lock().await;
+        if let Err(e) = file.write_all(&line).await.and(file.flush().await) {
+            error!("Error recording into {}: {}", self.path.display(), e);
+        }
+    }
+
+    /// Recorded outcome of the first unplayed exchange matching the request, as a service call.
+    ///
+    /// The outcome is returned once as much time as recorded has passed since `started`.
+    async fn play(&self, request: &Request, started: Instant) -> Result<Response, Status> {
+        let interactions = match &self.mode {
+            Mode::Replay(interactions) => interactions,
+            Mode::Record(_) => {
+                return Err(Status::failed_precondition(format!(
+                    "Cannot replay while recording into {}",
+                    self.path.display()
+                )))
+            }
+        };
+        let (result, duration) = {
+            let mut interactions = interactions.lock().unwrap();
+            let mut matching = interactions
+                .iter_mut()
+                .filter(|(interaction, _)| interaction.request == *request)
+                .peekable();
+            let (interaction, played) = loop {
+                match matching.next() {
+                    Some((interaction, played)) if !*played || matching.peek().is_none() => {
+                        break (interaction, played)
+                    }
+                    Some(_) => continue,
+                    None => {
+                        return Err(Status::not_found(format!(
+                            "No recorded interaction for the request in {}",
+                            self.path.display()
+                        )))
+                    }
+                }
+            };
+            *played = true;
+            let result = match (&interaction.response, &interaction.status) {
+                (Some(response), _) => Ok(response.clone()),
+                (None, Some(status)) => Err(Status::new(
+                    Code::from_i32(status.code),
+                    status.message.clone(),
+                )),
+                (None, None) => Err(Status::data_loss("Recorded interaction without a response")),
+            };
+            (result, Duration::from_millis(interaction.duration_ms))
+        };
+        tokio::time::sleep_until(started + duration).await;
+        result
+    }
+}
+
+/// Requests of a recognition, the init message followed by the audio.
+pub(crate) trait RequestStream: Stream<Item = RecognitionRequest> + Send + 'static {}
+
+impl<S: Stream<Item = RecognitionRequest> + Send + 'static> RequestStream for S {}
+
+/// Outcome of a call, errors of the call itself kept apart from the status returned by the service.
+pub(crate) type CallResult<T> = Result<Result<tonic::Response<T>, Status>>;
+
+/// Recognizer service called by the client, the remote one or a layer around it.
+#[async_trait]
+pub(crate) trait RecognizeStream: Send {
+    async fn recognize_stream(
+        &mut self,
+        requests: impl RequestStream,
+    ) -> CallResult<RecognitionResponse>;
+}
+
+/// Synthesizer service called by the client, the remote one or a layer around it.
+#[async_trait]
+pub(crate) trait Synthesize: Send {
+    async fn synthesize(&mut self, request: SynthesisRequest) -> CallResult<SynthesisResponse>;
+}
+
+/// Layer recording the calls made to the inner service into the cassette, or answering them from
+/// it without calling the inner service when replaying.
+pub(crate) struct Recorded<S> {
+    inner: S,
+    cassette: Option<Arc<Cassette>>,
+}
+
+impl<S> Recorded<S> {
+    /// Layer around `inner`, passing the calls through when there is no cassette.
+    pub(crate) fn new(inner: S, cassette: Option<Arc<Cassette>>) -> Self {
+        Self { inner, cassette }
+    }
+}
+
+#[async_trait]
+impl<S: RecognizeStream> RecognizeStream for Recorded<S> {
+    async fn recognize_stream(
+        &mut self,
+        requests: impl RequestStream,
+    ) -> CallResult<RecognitionResponse> {
+        let cassette = match &self.cassette {
+            Some(cassette) => cassette.clone(),
+            None => return self.inner.recognize_stream(requests).await,
+        };
+        let started = Instant::now();
+        // Recordings are matched by the digest of the audio, as sent after any conversion
+        let sent = Arc::new(Mutex::new((None, AudioHasher::default())));
+        let seen = sent.clone();
+        let requests = requests.map(move |request| {
+            let mut seen = seen.lock().unwrap();
+            match &request.request_union {
+                Some(RequestUnion::Init(init)) => seen.0 = Some(init.clone()),
+                Some(RequestUnion::Audio(audio)) => {
+                    seen.1.update(audio);
+                }
+                None => {}
+            }
+            request
+        });
+        let recorded = || {
+            let sent = sent.lock().unwrap();
+            recognition(sent.0.as_ref(), sent.1.digest())
+        };
+
+        if cassette.is_replay() {
+            // The audio is consumed all the same, as the service would
+            tokio::pin!(requests);
+            while requests.next().await.is_some() {}
+            return Ok(match cassette.play(&recorded(), started).await {
+                Ok(Response::Transcript(text)) => {
+                    Ok(tonic::Response::new(RecognitionResponse { text }))
+                }
+                Ok(_) => Err(Status::data_loss("Recorded response is not a transcript")),
+                Err(status) => Err(status),
+            });
+        }
+        let r = self.inner.recognize_stream(requests).await?;
+        let response = r
+            .as_ref()
+            .map(|r| Response::Transcript(r.get_ref().text.clone()));
+        cassette.save(recorded(), response, started.elapsed()).await;
+        Ok(r)
+    }
+}
+
+#[async_trait]
+impl<S: Synthesize> Synthesize for Recorded<S> {
+    async fn synthesize(&mut self, request: SynthesisRequest) -> CallResult<SynthesisResponse> {
+        let cassette = match &self.cassette {
+            Some(cassette) => cassette.clone(),
+            None => return self.inner.synthesize(request).await,
+        };
+        let started = Instant::now();
+        let recorded = synthesis(&request);
+
+        if cassette.is_replay() {
+            return Ok(match cassette.play(&recorded, started).await {
+                Ok(Response::Audio(audio)) => match base64::decode(audio) {
+                    Ok(audio) => Ok(tonic::Response::new(SynthesisResponse { audio })),
+                    Err(e) => Err(Status::data_loss(e.to_string())),
+                },
+                Ok(_) => Err(Status::data_loss("Recorded response is not audio")),
+                Err(status) => Err(status),
+            });
+        }
+        let r = self.inner.synthesize(request).await?;
+        let response = r
+            .as_ref()
+            .map(|r| Response::Audio(base64::encode(&r.get_ref().audio)));
+        cassette.save(recorded, response, started.elapsed()).await;
+        Ok(r)
+    }
+}
+
+/// Recorded request of a recognition, given its init message and the digest of its audio.
+fn recognition(init: Option<&RecognitionInit>, audio_digest: String) -> Request {
+    let language = init
+        .and_then(|init| init.parameters.as_ref())
+        .map(|parameters| parameters.language.clone());
+    let resource = init
+        .and_then(|init| init.resource.as_ref())
+        .and_then(|resource| resource.resource_union.as_ref());
+    let (topic, grammar) = match resource {
+        Some(ResourceUnion::Topic(topic)) => (Some(*topic), None),
+        Some(ResourceUnion::InlineGrammar(grammar)) => (None, Some(grammar.clone())),
+        None => (None, None),
+    };
+    Request::Recognition {
+        language: language.unwrap_or_default(),
+        topic,
+        grammar,
+        audio_digest,
+    }
+}
+
+fn synthesis(request: &SynthesisRequest) -> Request {
+    let voice = request
+        .voice
+        .as_ref()
+        .and_then(|voice| voice.synthesis_union.as_ref());
+    Request::Synthesis {
+        voice: match voice {
+            Some(SynthesisUnion::Voice(voice)) => Some(*voice),
+            _ => None,
+        },
+        text: request.text.clone(),
+        sample_rate: request.voice_sampling_rate,
+        audio_format: request.audio_format,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn request(digest: &str) -> Request {
+        Request::Recognition {
+            language: "en-US".to_string(),
+            topic: Some(0),
+            grammar: None,
+            audio_digest: digest.to_string(),
+        }
+    }
+
+    async fn play(cassette: &Cassette, digest: &str) -> Result<Response, Status> {
+        cassette.play(&request(digest), Instant::now()).await
+    }
+
+    #[test]
+    fn test_audio_digest() {
+        let mut hasher = AudioHasher::default();
+        hasher.update(b"ab").update(b"c");
+        assert_eq!(hasher.digest(), audio_digest(b"abc"));
+        assert_eq!(
+            audio_digest(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_record_and_replay() {
+        let path = std::env::temp_dir().join(format!("cassette-{}.jsonl", std::process::id()));
+        let cassette = Cassette::record(&path).unwrap();
+        let duration = Duration::from_millis(20);
+        let overloaded = Status::unavailable("Overloaded");
+        cassette
+            .save(request("a"), Err(&overloaded), duration)
+            .await;
+        cassette
+            .save(
+                request("a"),
+                Ok(Response::Transcript("first".to_string())),
+                duration,
+            )
+            .await;
+        cassette
+            .save(
+                request("b"),
+                Ok(Response::Transcript("second".to_string())),
+                Duration::ZERO,
+            )
+            .await;
+        let status = cassette
+            .play(&request("a"), Instant::now())
+            .await
+            .expect_err("Should not replay while recording");
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        drop(cassette);
+
+        let cassette = Cassette::replay(&path).unwrap();
+        assert!(cassette.is_replay());
+        let started = Instant::now();
+        let status = cassette
+            .play(&request("a"), started)
+            .await
+            .expect_err("Should replay the failure");
+        assert_eq!(status.code(), Code::Unavailable);
+        // Replayed exchanges take as long as recorded
+        assert!(started.elapsed() >= duration);
+        let transcript = Response::Transcript("first".to_string());
+        assert_eq!(play(&cassette, "a").await.unwrap(), transcript);
+        assert_eq!(play(&cassette, "a").await.unwrap(), transcript);
+        assert_eq!(
+            play(&cassette, "b").await.unwrap(),
+            Response::Transcript("second".to_string())
+        );
+        let status = play(&cassette, "c").await.expect_err("Should not replay");
+        assert_eq!(status.code(), Code::NotFound);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
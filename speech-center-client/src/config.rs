@@ -0,0 +1,247 @@
+use crate::cassette::Cassette;
+use crate::failover::{CircuitBreaker, Failover};
+use crate::pool::Channels;
+use crate::retry::RetryPolicy;
+use crate::{Result, SpeechCenterError};
+use std::error::Error;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity, Uri};
+
+/// Connection settings shared by the recognition and synthesis clients.
+#[derive(Clone, Debug)]
+pub struct SpeechCenterConfig {
+    pub(crate) url: String,
+    pub(crate) connect_timeout: Option<Duration>,
+    pub(crate) request_timeout: Option<Duration>,
+    pub(crate) keep_alive_interval: Option<Duration>,
+    pub(crate) keep_alive_timeout: Option<Duration>,
+    pub(crate) initial_stream_window_size: Option<u32>,
+    pub(crate) initial_connection_window_size: Option<u32>,
+    pub(crate) gzip: bool,
+    pub(crate) ca_certificate: Option<Vec<u8>>,
+    pub(crate) identity: Option<(Vec<u8>, Vec<u8>)>,
+    pub(crate) domain_name: Option<String>,
+    pub(crate) plaintext: bool,
+    pub(crate) retry: RetryPolicy,
+    pub(crate) cassette: Option<Arc<Cassette>>,
+    pub(crate) failover: Vec<String>,
+    pub(crate) circuit_breaker: CircuitBreaker,
+}
+
+impl SpeechCenterConfig {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            connect_timeout: None,
+            request_timeout: None,
+            keep_alive_interval: None,
+            keep_alive_timeout: None,
+            initial_stream_window_size: None,
+            initial_connection_window_size: None,
+            gzip: false,
+            ca_certificate: None,
+            identity: None,
+            domain_name: None,
+            plaintext: false,
+            retry: RetryPolicy::default(),
+            cassette: None,
+            failover: vec![],
+            circuit_breaker: CircuitBreaker::default(),
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Deadline for every recognition or synthesis call, including the upload of the audio.
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    /// Interval between HTTP/2 pings, keeping idle connections alive.
+    pub fn keep_alive_interval(mut self, interval: Duration) -> Self {
+        self.keep_alive_interval = Some(interval);
+        self
+    }
+
+    /// Time to wait for a ping acknowledgement before closing the connection.
+    pub fn keep_alive_timeout(mut self, timeout: Duration) -> Self {
+        self.keep_alive_timeout = Some(timeout);
+        self
+    }
+
+    pub fn initial_stream_window_size(mut self, size: u32) -> Self {
+        self.initial_stream_window_size = Some(size);
+        self
+    }
+
+    pub fn initial_connection_window_size(mut self, size: u32) -> Self {
+        self.initial_connection_window_size = Some(size);
+        self
+    }
+
+    /// Compresses requests and accepts compressed responses with gzip.
+    pub fn gzip(mut self, enabled: bool) -> Self {
+        self.gzip = enabled;
+        self
+    }
+
+    /// Trusts the PEM encoded CA certificate on top of the system roots.
+    pub fn ca_certificate(mut self, pem: impl Into<Vec<u8>>) -> Self {
+        self.ca_certificate = Some(pem.into());
+        self
+    }
+
+    /// Authenticates the client with a PEM encoded certificate and private key (mTLS).
+    pub fn identity(
+        mut self,
+        certificate_pem: impl Into<Vec<u8>>,
+        key_pem: impl Into<Vec<u8>>,
+    ) -> Self {
+        self.identity = Some((certificate_pem.into(), key_pem.into()));
+        self
+    }
+
+    /// Overrides the domain name used for SNI and certificate verification.
+    pub fn domain_name(mut self, domain_name: impl Into<String>) -> Self {
+        self.domain_name = Some(domain_name.into());
+        self
+    }
+
+    /// Disables TLS, meant for local test servers.
+    pub fn plaintext(mut self, enabled: bool) -> Self {
+        self.plaintext = enabled;
+        self
+    }
+
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    /// Records every call into the cassette, or answers them from it when replaying.
+    pub fn cassette(mut self, cassette: Arc<Cassette>) -> Self {
+        self.cassette = Some(cassette);
+        self
+    }
+
+    /// Endpoints of the same service to fail over to, in order, when `url` is unavailable.
+    pub fn failover<I, S>(mut self, urls: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.failover = urls.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Circuit breaker settings for every endpoint, used along with [`Self::failover`].
+    pub fn circuit_breaker(mut self, circuit_breaker: CircuitBreaker) -> Self {
+        self.circuit_breaker = circuit_breaker;
+        self
+    }
+
+    fn tls_config(&self) -> ClientTlsConfig {
+        let mut tls = ClientTlsConfig::new();
+        if let Some(ca_certificate) = &self.ca_certificate {
+            tls = tls.ca_certificate(Certificate::from_pem(ca_certificate));
+        }
+        if let Some((certificate, key)) = &self.identity {
+            tls = tls.identity(Identity::from_pem(certificate, key));
+        }
+        if let Some(domain_name) = &self.domain_name {
+            tls = tls.domain_name(domain_name);
+        }
+        tls
+    }
+
+    pub(crate) fn endpoint(&self) -> Result<Endpoint> {
+        let uri = Uri::from_str(&self.url).map_err(|e| {
+            SpeechCenterError::Config(format!(
+                "Error building Uri from string [uri={}]: {}",
+                self.url, e
+            ))
+        })?;
+        let mut endpoint = Channel::builder(uri)
+            .initial_stream_window_size(self.initial_stream_window_size)
+            .initial_connection_window_size(self.initial_connection_window_size);
+        if let Some(timeout) = self.connect_timeout {
+            endpoint = endpoint.connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.request_timeout {
+            endpoint = endpoint.timeout(timeout);
+        }
+        if let Some(interval) = self.keep_alive_interval {
+            endpoint = endpoint
+                .http2_keep_alive_interval(interval)
+                .keep_alive_while_idle(true);
+        }
+        if let Some(timeout) = self.keep_alive_timeout {
+            endpoint = endpoint.keep_alive_timeout(timeout);
+        }
+        if self.plaintext {
+            return Ok(endpoint);
+        }
+        endpoint
+            .tls_config(self.tls_config())
+            .map_err(|e| SpeechCenterError::Config(format!("Error setting up tls: {:?}", e)))
+    }
+
+    pub(crate) async fn channels(&self) -> Result<Channels> {
+        match self.failover.is_empty() {
+            true => Ok(Channels::Single {
+                channel: self.connect().await?,
+                url: self.url.clone(),
+            }),
+            false => Ok(Channels::Failover(Failover::connect(self).await?)),
+        }
+    }
+
+    pub(crate) async fn connect(&self) -> Result<Channel> {
+        // Replayed calls never reach the service
+        if self.cassette.as_ref().is_some_and(|c| c.is_replay()) {
+            return Ok(self.endpoint()?.connect_lazy());
+        }
+        self.endpoint()?
+            .connect()
+            .await
+            .map_err(|e| SpeechCenterError::Connection {
+                url: self.url.clone(),
+                reason: e.source().map(|e| e.to_string()).unwrap_or_default(),
+                source: Arc::new(e),
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_plaintext_connection_error() {
+        let error = SpeechCenterConfig::new("http://127.0.0.1:9999")
+            .plaintext(true)
+            .connect_timeout(Duration::from_secs(1))
+            .connect()
+            .await
+            .expect_err("Should not be able to connect anywhere");
+        assert!(matches!(error, SpeechCenterError::Connection { .. }));
+    }
+
+    #[test]
+    fn test_invalid_url() {
+        let error = SpeechCenterConfig::new("not a url")
+            .endpoint()
+            .expect_err("Should not accept the url");
+        assert!(matches!(error, SpeechCenterError::Config(_)));
+    }
+}
@@ -0,0 +1,439 @@
+use crate::{Result, SpeechCenterError};
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+use tonic::metadata::MetadataValue;
+use tonic::Request;
+
+/// Tokens are refreshed this long before they expire, so no request is sent with a token that
+/// expires on its way to the server. Short-lived tokens are used for at least half their lifetime.
+const REFRESH_MARGIN: Duration = Duration::from_secs(60);
+
+/// Value of the authorization header along with its expiry, when known.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Token {
+    value: String,
+    expires_at: Option<SystemTime>,
+    refresh_at: Option<SystemTime>,
+}
+
+impl Token {
+    /// Reads the expiry from the `exp` claim when the token is a JWT.
+    pub fn new(value: impl Into<String>) -> Self {
+        let value = value.into();
+        let expires_at = jwt_expiry(&value);
+        Self::expiring(value, expires_at)
+    }
+
+    fn expiring(value: String, expires_at: Option<SystemTime>) -> Self {
+        let refresh_at = expires_at.map(|expires_at| {
+            let lifetime = expires_at
+                .duration_since(SystemTime::now())
+                .unwrap_or_default();
+            expires_at - REFRESH_MARGIN.min(lifetime / 2)
+        });
+        Self {
+            value,
+            expires_at,
+            refresh_at,
+        }
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn expires_at(&self) -> Option<SystemTime> {
+        self.expires_at
+    }
+
+    fn needs_refresh(&self) -> bool {
+        self.refresh_at
+            .is_some_and(|refresh_at| refresh_at <= SystemTime::now())
+    }
+}
+
+fn jwt_expiry(token: &str) -> Option<SystemTime> {
+    #[derive(Deserialize)]
+    struct Claims {
+        exp: Option<f64>,
+    }
+
+    let token = token.strip_prefix("Bearer ").unwrap_or(token);
+    let payload = token.split('.').nth(1)?;
+    let payload =
+        base64::decode_config(payload.trim_end_matches('='), base64::URL_SAFE_NO_PAD).ok()?;
+    let exp = serde_json::from_slice::<Claims>(&payload).ok()?.exp?;
+    // The claim comes from outside, so expiries past what the clock can hold are ignored
+    Duration::try_from_secs_f64(exp)
+        .ok()
+        .and_then(|exp| UNIX_EPOCH.checked_add(exp))
+}
+
+/// Source of the token sent along every request.
+#[async_trait]
+pub trait CredentialsProvider: fmt::Debug + Send + Sync {
+    /// Returns a token valid for at least the next minute whenever the source allows it.
+    async fn token(&self) -> Result<Token>;
+}
+
+pub(crate) async fn authorize<T>(
+    credentials: &dyn CredentialsProvider,
+    request: &mut Request<T>,
+) -> Result<()> {
+    let token = credentials.token().await?;
+    let value = MetadataValue::from_str(token.value()).map_err(|e| {
+        SpeechCenterError::credentials(
+            format!("Error converting credentials to MetadataValue: {}", e),
+            Some(Arc::new(e) as _),
+        )
+    })?;
+    request.metadata_mut().insert("authorization", value);
+    Ok(())
+}
+
+fn parse_token(token: &str, source: &str) -> Result<Token> {
+    match token.trim() {
+        "" => Err(SpeechCenterError::credentials(
+            format!("Empty token [source={}]", source),
+            None,
+        )),
+        token => Ok(Token::new(token)),
+    }
+}
+
+/// Token fixed for the lifetime of the provider.
+#[derive(Clone, Debug)]
+pub struct StaticToken(Token);
+
+impl StaticToken {
+    pub fn new(token: impl Into<String>) -> Self {
+        Self(Token::new(token))
+    }
+}
+
+#[async_trait]
+impl CredentialsProvider for StaticToken {
+    async fn token(&self) -> Result<Token> {
+        Ok(self.0.clone())
+    }
+}
+
+/// Token read from an environment variable on every request.
+#[derive(Clone, Debug)]
+pub struct EnvToken {
+    var: String,
+}
+
+impl EnvToken {
+    pub fn new(var: impl Into<String>) -> Self {
+        Self { var: var.into() }
+    }
+}
+
+#[async_trait]
+impl CredentialsProvider for EnvToken {
+    async fn token(&self) -> Result<Token> {
+        let token = std::env::var(&self.var).map_err(|e| {
+            SpeechCenterError::credentials(
+                format!("Error reading token [var={}]: {}", self.var, e),
+                Some(Arc::new(e) as _),
+            )
+        })?;
+        parse_token(&token, &self.var)
+    }
+}
+
+/// Token read from a file, read again whenever the file changes or the token is about to expire.
+///
+/// Meant to be paired with an external process renewing the token in place.
+#[derive(Debug)]
+pub struct TokenFile {
+    path: PathBuf,
+    cached: Mutex<Option<(SystemTime, Token)>>,
+}
+
+impl TokenFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            cached: Mutex::new(None),
+        }
+    }
+}
+
+#[async_trait]
+impl CredentialsProvider for TokenFile {
+    async fn token(&self) -> Result<Token> {
+        let read_error = |e: std::io::Error| {
+            SpeechCenterError::credentials(
+                format!("Error reading token [file={}]: {}", self.path.display(), e),
+                Some(Arc::new(e) as _),
+            )
+        };
+        let modified = tokio::fs::metadata(&self.path)
+            .await
+            .and_then(|m| m.modified())
+            .map_err(read_error)?;
+
+        let mut cached = self.cached.lock().await;
+        match &*cached {
+            Some((read_at, token)) if *read_at == modified && !token.needs_refresh() => {
+                return Ok(token.clone())
+            }
+            _ => {}
+        }
+        debug!("Reading token from {}", self.path.display());
+        let token = tokio::fs::read_to_string(&self.path)
+            .await
+            .map_err(read_error)?;
+        let token = parse_token(&token, &self.path.display().to_string())?;
+        *cached = Some((modified, token.clone()));
+        Ok(token)
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    token_type: Option<String>,
+    expires_in: Option<u64>,
+}
+
+/// OAuth2 client credentials grant against the given token endpoint, fetching a new token ahead
+/// of the expiry of the current one.
+#[derive(Debug)]
+pub struct ClientCredentials {
+    token_url: String,
+    client_id: String,
+    client_secret: String,
+    scope: Option<String>,
+    http: reqwest::Client,
+    cached: Mutex<Option<Token>>,
+}
+
+impl ClientCredentials {
+    pub fn new(
+        token_url: impl Into<String>,
+        client_id: impl Into<String>,
+        client_secret: impl Into<String>,
+    ) -> Self {
+        Self {
+            token_url: token_url.into(),
+            client_id: client_id.into(),
+            client_secret: client_secret.into(),
+            scope: None,
+            http: reqwest::Client::new(),
+            cached: Mutex::new(None),
+        }
+    }
+
+    pub fn scope(mut self, scope: impl Into<String>) -> Self {
+        self.scope = Some(scope.into());
+        self
+    }
+
+    async fn fetch(&self) -> Result<Token> {
+        let request_error = |e: reqwest::Error| {
+            SpeechCenterError::credentials(
+                format!("Error requesting token [url={}]: {}", self.token_url, e),
+                Some(Arc::new(e) as _),
+            )
+        };
+        let mut form = vec![
+            ("grant_type", "client_credentials"),
+            ("client_id", &self.client_id),
+            ("client_secret", &self.client_secret),
+        ];
+        if let Some(scope) = &self.scope {
+            form.push(("scope", scope));
+        }
+
+        debug!("Requesting token from {}", self.token_url);
+        let response = self
+            .http
+            .post(&self.token_url)
+            .form(&form)
+            .send()
+            .await
+            .map_err(request_error)?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(SpeechCenterError::credentials(
+                format!(
+                    "Token request rejected [url={}] [status={}]: {}",
+                    self.token_url, status, body
+                ),
+                None,
+            ));
+        }
+        let response = response
+            .json::<TokenResponse>()
+            .await
+            .map_err(request_error)?;
+
+        let token_type = response.token_type.as_deref().unwrap_or("Bearer");
+        let token = Token::new(format!("{} {}", token_type, response.access_token));
+        if token.expires_at.is_some() {
+            return Ok(token);
+        }
+        let expires_at = response
+            .expires_in
+            .and_then(|expires_in| SystemTime::now().checked_add(Duration::from_secs(expires_in)));
+        Ok(Token::expiring(token.value, expires_at))
+    }
+}
+
+#[async_trait]
+impl CredentialsProvider for ClientCredentials {
+    async fn token(&self) -> Result<Token> {
+        let mut cached = self.cached.lock().await;
+        match &*cached {
+            Some(token) if !token.needs_refresh() => Ok(token.clone()),
+            _ => {
+                let token = self.fetch().await?;
+                *cached = Some(token.clone());
+                Ok(token)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn jwt(exp: impl fmt::Display) -> String {
+        let encode = |json: String| base64::encode_config(json, base64::URL_SAFE_NO_PAD);
+        format!(
+            "{}.{}.signature",
+            encode(r#"{"alg":"HS256","typ":"JWT"}"#.to_string()),
+            encode(format!(r#"{{"sub":"test","exp":{}}}"#, exp))
+        )
+    }
+
+    fn from_now(secs: u64) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + secs
+    }
+
+    /// Serves a single HTTP response per body, returning the url to reach it.
+    async fn token_endpoint(bodies: Vec<String>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/token", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            for body in bodies {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = [0u8; 4096];
+                let _ = socket.read(&mut request).await;
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn test_jwt_expiry() {
+        let exp = from_now(3600);
+        let token = Token::new(format!("Bearer {}", jwt(exp)));
+        assert_eq!(
+            token.expires_at(),
+            Some(UNIX_EPOCH + Duration::from_secs(exp))
+        );
+        assert!(!token.needs_refresh());
+        assert!(Token::new(jwt(from_now(0) - 10)).needs_refresh());
+        // Tokens shorter than twice the margin are refreshed halfway through their lifetime
+        assert!(!Token::new(jwt(from_now(10))).needs_refresh());
+        assert_eq!(Token::new("opaque").expires_at(), None);
+        assert_eq!(Token::new(jwt("1e300")).expires_at(), None);
+        assert_eq!(Token::new(jwt(-1)).expires_at(), None);
+    }
+
+    #[tokio::test]
+    async fn test_token_file() {
+        let path = std::env::temp_dir().join(format!("token-{}", std::process::id()));
+        std::fs::write(&path, "first\n").unwrap();
+        let credentials = TokenFile::new(&path);
+        assert_eq!(credentials.token().await.unwrap().value(), "first");
+
+        std::fs::write(&path, "second").unwrap();
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(credentials.token().await.unwrap().value(), "second");
+
+        std::fs::write(&path, "  ").unwrap();
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(20))
+            .unwrap();
+        let error = credentials
+            .token()
+            .await
+            .expect_err("Should reject empty tokens");
+        assert!(matches!(error, SpeechCenterError::Credentials { .. }));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_client_credentials_refresh() {
+        let expiring = jwt(from_now(0) - 1);
+        let valid = jwt(from_now(3600));
+        let url = token_endpoint(vec![
+            format!(r#"{{"access_token":"{}","token_type":"Bearer"}}"#, expiring),
+            format!(r#"{{"access_token":"{}"}}"#, valid),
+        ])
+        .await;
+        let credentials = ClientCredentials::new(url, "id", "secret");
+
+        let token = credentials.token().await.expect("Should fetch a token");
+        assert_eq!(token.value(), format!("Bearer {}", expiring));
+        // The first token has expired, so a new one is fetched
+        let token = credentials.token().await.expect("Should refresh the token");
+        assert_eq!(token.value(), format!("Bearer {}", valid));
+        // The endpoint only answers twice, so the last token must come from the cache
+        let token = credentials.token().await.expect("Should reuse the token");
+        assert_eq!(token.value(), format!("Bearer {}", valid));
+    }
+
+    #[tokio::test]
+    async fn test_client_credentials_expires_in() {
+        let url = token_endpoint(vec![
+            r#"{"access_token":"opaque","token_type":"Bearer","expires_in":3600}"#.to_string(),
+        ])
+        .await;
+        let token = ClientCredentials::new(url, "id", "secret")
+            .token()
+            .await
+            .expect("Should fetch a token");
+        assert!(token.expires_at().is_some());
+        assert!(!token.needs_refresh());
+    }
+
+    #[tokio::test]
+    async fn test_client_credentials_short_lived() {
+        let url = token_endpoint(vec![
+            r#"{"access_token":"opaque","token_type":"Bearer","expires_in":30}"#.to_string(),
+        ])
+        .await;
+        let credentials = ClientCredentials::new(url, "id", "secret");
+        credentials.token().await.expect("Should fetch a token");
+        // The endpoint only answers once, so the token must be reused despite the margin
+        let token = credentials.token().await.expect("Should reuse the token");
+        assert_eq!(token.value(), "Bearer opaque");
+    }
+}
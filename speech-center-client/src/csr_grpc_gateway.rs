@@ -161,8 +161,8 @@ pub mod speech_recognizer_server {
     #[derive(Debug)]
     pub struct SpeechRecognizerServer<T: SpeechRecognizer> {
         inner: _Inner<T>,
-        accept_compression_encodings: (),
-        send_compression_encodings: (),
+        accept_compression_encodings: EnabledCompressionEncodings,
+        send_compression_encodings: EnabledCompressionEncodings,
     }
     struct _Inner<T>(Arc<T>);
     impl<T: SpeechRecognizer> SpeechRecognizerServer<T> {
@@ -181,6 +181,16 @@ pub mod speech_recognizer_server {
         {
             InterceptedService::new(Self::new(inner), interceptor)
         }
+        #[doc = r" Enable decompressing requests with `gzip`."]
+        pub fn accept_gzip(mut self) -> Self {
+            self.accept_compression_encodings.enable_gzip();
+            self
+        }
+        #[doc = r" Compress responses with `gzip`, if the client supports it."]
+        pub fn send_gzip(mut self) -> Self {
+            self.send_compression_encodings.enable_gzip();
+            self
+        }
     }
     impl<T, B> tonic::codegen::Service<http::Request<B>> for SpeechRecognizerServer<T>
     where
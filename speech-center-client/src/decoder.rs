@@ -0,0 +1,280 @@
+use crate::audio::{AudioSpec, SampleFormat};
+use crate::audio_stream::RECOGNITION_SAMPLE_RATE;
+use crate::wav::WavError;
+use opus_decoder::OpusDecoder;
+use std::io;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL, CODEC_TYPE_OPUS};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader};
+use symphonia::core::io::{MediaSource, MediaSourceStream};
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+/// Opus packets are timestamped at 48kHz regardless of the decoding rate
+const OPUS_TIMEBASE_RATE: u32 = 48000;
+
+#[derive(Clone, Debug, PartialEq, thiserror::Error)]
+pub enum DecodeError {
+    #[error("Unsupported container format: {}", _0)]
+    UnsupportedFormat(String),
+    #[error("Unsupported codec: {}", _0)]
+    UnsupportedCodec(String),
+    #[error("No audio track found")]
+    NoAudioTrack,
+    #[error("Malformed audio: {}", _0)]
+    Malformed(String),
+    #[error("Invalid WAV audio: {}", _0)]
+    Wav(#[from] WavError),
+    #[error("Error reading audio: {}", _0)]
+    Io(String),
+}
+
+impl From<SymphoniaError> for DecodeError {
+    fn from(e: SymphoniaError) -> Self {
+        match e {
+            SymphoniaError::IoError(e) => Self::Io(e.to_string()),
+            SymphoniaError::Unsupported(what) => Self::UnsupportedFormat(what.to_string()),
+            e => Self::Malformed(e.to_string()),
+        }
+    }
+}
+
+impl From<DecodeError> for io::Error {
+    fn from(e: DecodeError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+enum Codec {
+    Symphonia(Box<dyn symphonia::core::codecs::Decoder>),
+    Opus {
+        decoder: Box<OpusDecoder>,
+        channels: usize,
+        /// Samples per channel still to be dropped from the start of the stream
+        pre_skip: usize,
+    },
+}
+
+/// Synchronous decoder for compressed audio, with the container detected from its content.
+///
+/// Supports MP3, FLAC, Ogg Vorbis, Ogg Opus and AAC in MP4/M4A.
+pub struct Decoder {
+    format: Box<dyn FormatReader>,
+    track_id: u32,
+    codec: Codec,
+    spec: AudioSpec,
+}
+
+impl Decoder {
+    pub fn new(source: Box<dyn MediaSource>) -> Result<Self, DecodeError> {
+        let stream = MediaSourceStream::new(source, Default::default());
+        let probed = symphonia::default::get_probe().format(
+            &Hint::new(),
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )?;
+        let format = probed.format;
+        let track = format
+            .tracks()
+            .iter()
+            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or(DecodeError::NoAudioTrack)?;
+        let params = &track.codec_params;
+        let channels = params
+            .channels
+            .map(|c| c.count())
+            .ok_or_else(|| DecodeError::Malformed("Unknown channel count".to_string()))?;
+
+        let (codec, spec) = if params.codec == CODEC_TYPE_OPUS {
+            if channels > 2 {
+                return Err(DecodeError::UnsupportedCodec(format!(
+                    "Opus with {} channels",
+                    channels
+                )));
+            }
+            let decoder = OpusDecoder::new(RECOGNITION_SAMPLE_RATE, channels)
+                .map_err(|e| DecodeError::Malformed(e.to_string()))?;
+            let pre_skip = params.delay.unwrap_or(0) * RECOGNITION_SAMPLE_RATE / OPUS_TIMEBASE_RATE;
+            let codec = Codec::Opus {
+                decoder: Box::new(decoder),
+                channels,
+                pre_skip: pre_skip as usize,
+            };
+            let spec = AudioSpec {
+                sample_rate: RECOGNITION_SAMPLE_RATE,
+                channels: channels as u16,
+                format: SampleFormat::S16,
+            };
+            (codec, spec)
+        } else {
+            let sample_rate = params
+                .sample_rate
+                .ok_or_else(|| DecodeError::Malformed("Unknown sample rate".to_string()))?;
+            let decoder = symphonia::default::get_codecs()
+                .make(params, &DecoderOptions::default())
+                .map_err(|e| match e {
+                    SymphoniaError::Unsupported(codec) => {
+                        DecodeError::UnsupportedCodec(codec.to_string())
+                    }
+                    e => DecodeError::from(e),
+                })?;
+            let spec = AudioSpec {
+                sample_rate,
+                channels: channels as u16,
+                format: SampleFormat::F32,
+            };
+            (Codec::Symphonia(decoder), spec)
+        };
+
+        let track_id = track.id;
+        Ok(Self {
+            format,
+            track_id,
+            codec,
+            spec,
+        })
+    }
+
+    /// Format of the samples returned by [`Decoder::next_chunk`]
+    pub fn spec(&self) -> AudioSpec {
+        self.spec
+    }
+
+    /// Decodes the next packet into little endian samples, `None` once the audio is over.
+    pub fn next_chunk(&mut self) -> Result<Option<Vec<u8>>, DecodeError> {
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e.into()),
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+
+            match &mut self.codec {
+                Codec::Symphonia(decoder) => match decoder.decode(&packet) {
+                    Ok(decoded) => {
+                        let mut samples =
+                            SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
+                        samples.copy_interleaved_ref(decoded);
+                        let data = samples.samples().iter().flat_map(|s| s.to_le_bytes());
+                        return Ok(Some(data.collect()));
+                    }
+                    // Corrupt frames are skipped, as any player would
+                    Err(SymphoniaError::DecodeError(e)) => {
+                        warn!("Skipping undecodable audio frame: {}", e);
+                    }
+                    Err(e) => return Err(e.into()),
+                },
+                Codec::Opus {
+                    decoder,
+                    channels,
+                    pre_skip,
+                } => {
+                    let mut pcm = vec![0i16; decoder.max_frame_size_per_channel() * *channels];
+                    let decoded = decoder
+                        .decode(packet.buf(), &mut pcm, false)
+                        .map_err(|e| DecodeError::Malformed(e.to_string()))?;
+                    let skipped = decoded.min(*pre_skip);
+                    *pre_skip -= skipped;
+                    let data = pcm[skipped * *channels..decoded * *channels]
+                        .iter()
+                        .flat_map(|s| s.to_le_bytes());
+                    return Ok(Some(data.collect()));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    fn crc8(data: &[u8]) -> u8 {
+        data.iter().fold(0u8, |crc, byte| {
+            (0..8).fold(crc ^ byte, |crc, _| match crc & 0x80 {
+                0 => crc << 1,
+                _ => (crc << 1) ^ 0x07,
+            })
+        })
+    }
+
+    fn crc16(data: &[u8]) -> u16 {
+        data.iter().fold(0u16, |crc, byte| {
+            (0..8).fold(crc ^ (u16::from(*byte) << 8), |crc, _| match crc & 0x8000 {
+                0 => crc << 1,
+                _ => (crc << 1) ^ 0x8005,
+            })
+        })
+    }
+
+    /// Encodes mono 16-bit samples as a FLAC file made of verbatim frames.
+    pub(crate) fn flac_file(sample_rate: u32, samples: &[i16]) -> Vec<u8> {
+        const BLOCK: usize = 1024;
+        let mut flac = b"fLaC".to_vec();
+        flac.extend_from_slice(&[0x80, 0, 0, 34]);
+        flac.extend_from_slice(&(BLOCK as u16).to_be_bytes());
+        flac.extend_from_slice(&(BLOCK as u16).to_be_bytes());
+        flac.extend_from_slice(&[0; 6]);
+        // 20 bits of sample rate, 3 bits of channels - 1, 5 bits of bits per sample - 1, and 36
+        // bits of total samples
+        let info = (u64::from(sample_rate) << 44) | (15u64 << 36) | samples.len() as u64;
+        flac.extend_from_slice(&info.to_be_bytes());
+        flac.extend_from_slice(&[0; 16]);
+
+        for (number, block) in samples.chunks(BLOCK).enumerate() {
+            let mut frame = vec![0xFF, 0xF8, 0x70, 0x08, number as u8];
+            frame.extend_from_slice(&(block.len() as u16 - 1).to_be_bytes());
+            frame.push(crc8(&frame));
+            frame.push(0x02);
+            block
+                .iter()
+                .for_each(|s| frame.extend_from_slice(&s.to_be_bytes()));
+            let crc = crc16(&frame);
+            frame.extend_from_slice(&crc.to_be_bytes());
+            flac.extend(frame);
+        }
+        flac
+    }
+
+    #[test]
+    fn test_decode_flac() {
+        let samples = (0..4000)
+            .map(|i| (i % 100) as i16 * 100)
+            .collect::<Vec<_>>();
+        let flac = flac_file(16000, &samples);
+        let mut decoder =
+            Decoder::new(Box::new(Cursor::new(flac))).expect("Should recognise the FLAC file");
+        let spec = AudioSpec {
+            sample_rate: 16000,
+            channels: 1,
+            format: SampleFormat::F32,
+        };
+        assert_eq!(decoder.spec(), spec);
+
+        let mut decoded = vec![];
+        while let Some(chunk) = decoder.next_chunk().expect("Should decode the FLAC file") {
+            decoded.extend(chunk);
+        }
+        let decoded = decoded
+            .chunks_exact(4)
+            .map(|s| (f32::from_le_bytes([s[0], s[1], s[2], s[3]]) * 32768.0) as i16)
+            .collect::<Vec<_>>();
+        assert_eq!(decoded, samples);
+    }
+
+    #[test]
+    fn test_unknown_format() {
+        let error = Decoder::new(Box::new(Cursor::new(vec![7u8; 4096])))
+            .err()
+            .expect("Should not decode noise");
+        assert!(matches!(error, DecodeError::UnsupportedFormat(_)));
+    }
+}
@@ -1,11 +1,217 @@
+use crate::audio::AudioSpec;
+use crate::decoder::DecodeError;
+use crate::wav::WavError;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use tonic::metadata::MetadataMap;
+use tonic::{Code, Status};
+
+type Source = Arc<dyn Error + Send + Sync>;
+
+/// Failed gRPC call, keeping the status returned by the service.
+#[derive(Clone, Debug)]
+pub struct RpcError {
+    status: Arc<Status>,
+    attempts: u32,
+}
+
+impl RpcError {
+    pub fn code(&self) -> Code {
+        self.status.code()
+    }
+
+    pub fn message(&self) -> &str {
+        self.status.message()
+    }
+
+    pub fn metadata(&self) -> &MetadataMap {
+        self.status.metadata()
+    }
+
+    pub fn status(&self) -> &Status {
+        &self.status
+    }
+
+    /// Number of attempts made before giving up
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{:?}] {}", self.code(), self.message())?;
+        if self.attempts > 1 {
+            write!(f, " after {} attempts", self.attempts)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for RpcError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.status.source()
+    }
+}
+
+/// Errors of the client, describing their cause in the message as well as returning it as source.
 #[derive(Clone, Debug, thiserror::Error)]
 pub enum SpeechCenterError {
-    #[error("Connection error: {}", _0)]
-    Connection(String),
-    #[error("Recognision error: {}", _0)]
-    Recognision(String),
-    #[error("Synthesis error: {}", _0)]
-    Synthesis(String),
-    #[error("Unknown error: {}", _0)]
-    Unknown(String),
+    #[error("Invalid argument: {}", _0)]
+    InvalidArgument(#[source] RpcError),
+    #[error("Invalid grammar: {}", _0)]
+    InvalidGrammar(#[source] RpcError),
+    #[error("Unauthenticated: {}", _0)]
+    Unauthenticated(#[source] RpcError),
+    #[error("Permission denied: {}", _0)]
+    PermissionDenied(#[source] RpcError),
+    #[error("Service unavailable: {}", _0)]
+    Unavailable(#[source] RpcError),
+    #[error("Timeout: {}", _0)]
+    Timeout(#[source] RpcError),
+    #[error("Resource exhausted: {}", _0)]
+    ResourceExhausted(#[source] RpcError),
+    #[error("Service error: {}", _0)]
+    Service(#[source] RpcError),
+    #[error("Could not connect [url={}] {}", url, reason)]
+    Connection {
+        url: String,
+        reason: String,
+        #[source]
+        source: Source,
+    },
+    #[error("Credentials error: {}", message)]
+    Credentials {
+        message: String,
+        #[source]
+        source: Option<Source>,
+    },
+    #[error("Invalid configuration: {}", _0)]
+    Config(String),
+    #[error("Invalid parameter: {}", _0)]
+    InvalidParameter(String),
+    #[error("Invalid audio: {}", _0)]
+    InvalidAudio(#[from] WavError),
+    #[error("Error decoding audio: {}", _0)]
+    Decoding(#[from] DecodeError),
+    #[error(
+        "Unsupported audio [{}], expected mono 8kHz signed 16-bit PCM or audio conversion",
+        _0
+    )]
+    UnsupportedAudio(AudioSpec),
+    #[error("{}: {}", context, source)]
+    Io {
+        context: String,
+        #[source]
+        source: Arc<io::Error>,
+    },
+}
+
+impl SpeechCenterError {
+    /// Classifies the status of a call that failed after the given number of attempts.
+    pub(crate) fn from_status(status: Status, attempts: u32) -> Self {
+        let code = status.code();
+        // The service reports grammar compilation errors as invalid arguments
+        let grammar = status.message().to_lowercase().contains("grammar");
+        let error = RpcError {
+            status: Arc::new(status),
+            attempts,
+        };
+        match code {
+            Code::InvalidArgument if grammar => Self::InvalidGrammar(error),
+            Code::InvalidArgument | Code::OutOfRange => Self::InvalidArgument(error),
+            Code::Unauthenticated => Self::Unauthenticated(error),
+            Code::PermissionDenied => Self::PermissionDenied(error),
+            Code::Unavailable => Self::Unavailable(error),
+            Code::DeadlineExceeded => Self::Timeout(error),
+            Code::ResourceExhausted => Self::ResourceExhausted(error),
+            _ => Self::Service(error),
+        }
+    }
+
+    pub fn io(context: impl Into<String>, source: io::Error) -> Self {
+        Self::Io {
+            context: context.into(),
+            source: Arc::new(source),
+        }
+    }
+
+    pub(crate) fn credentials(message: impl Into<String>, source: Option<Source>) -> Self {
+        Self::Credentials {
+            message: message.into(),
+            source,
+        }
+    }
+
+    /// Details of the failed call, for errors returned by the service.
+    pub fn rpc(&self) -> Option<&RpcError> {
+        match self {
+            Self::InvalidArgument(e)
+            | Self::InvalidGrammar(e)
+            | Self::Unauthenticated(e)
+            | Self::PermissionDenied(e)
+            | Self::Unavailable(e)
+            | Self::Timeout(e)
+            | Self::ResourceExhausted(e)
+            | Self::Service(e) => Some(e),
+            _ => None,
+        }
+    }
+
+    pub fn code(&self) -> Option<Code> {
+        self.rpc().map(RpcError::code)
+    }
+
+    /// Whether the same call may succeed if made again later.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Unavailable(_)
+            | Self::Timeout(_)
+            | Self::ResourceExhausted(_)
+            | Self::Connection { .. } => true,
+            Self::Service(e) => e.code() == Code::Aborted,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_status() {
+        let error = SpeechCenterError::from_status(Status::unauthenticated("Expired token"), 1);
+        assert!(matches!(error, SpeechCenterError::Unauthenticated(_)));
+        assert_eq!(error.code(), Some(Code::Unauthenticated));
+        assert!(!error.is_retryable());
+        assert_eq!(
+            error.to_string(),
+            "Unauthenticated: [Unauthenticated] Expired token"
+        );
+
+        let error = SpeechCenterError::from_status(Status::unavailable("Overloaded"), 3);
+        assert!(matches!(error, SpeechCenterError::Unavailable(_)));
+        assert!(error.is_retryable());
+        assert_eq!(error.rpc().map(RpcError::attempts), Some(3));
+        assert!(error.to_string().ends_with("after 3 attempts"));
+
+        let error =
+            SpeechCenterError::from_status(Status::invalid_argument("Malformed grammar"), 1);
+        assert!(matches!(error, SpeechCenterError::InvalidGrammar(_)));
+        let error = SpeechCenterError::from_status(Status::internal("Crashed"), 1);
+        assert!(matches!(error, SpeechCenterError::Service(_)));
+    }
+
+    #[test]
+    fn test_source() {
+        let error = SpeechCenterError::io("Error reading audio", io::Error::other("Disk failure"));
+        assert_eq!(error.to_string(), "Error reading audio: Disk failure");
+        assert_eq!(
+            error.source().map(|e| e.to_string()),
+            Some("Disk failure".to_string())
+        );
+    }
 }
@@ -0,0 +1,330 @@
+use crate::config::SpeechCenterConfig;
+use crate::pool::Lease;
+use crate::{Result, SpeechCenterError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tonic::transport::Channel;
+use tonic::{Code, Status};
+
+/// State of the circuit breaker of an endpoint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BreakerState {
+    /// Calls go through
+    Closed,
+    /// Calls skip the endpoint until the breaker lets a probe through
+    Open,
+    /// A single probe call decides whether the breaker closes or opens again
+    HalfOpen,
+}
+
+/// When the circuit breaker of an endpoint opens, and for how long.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CircuitBreaker {
+    pub(crate) failure_threshold: u32,
+    pub(crate) open_duration: Duration,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            open_duration: Duration::from_secs(30),
+        }
+    }
+}
+
+impl CircuitBreaker {
+    /// Consecutive failed calls opening the breaker.
+    pub fn failure_threshold(mut self, failures: u32) -> Self {
+        self.failure_threshold = failures.max(1);
+        self
+    }
+
+    /// Time the breaker stays open before letting a probe call through.
+    pub fn open_duration(mut self, duration: Duration) -> Self {
+        self.open_duration = duration;
+        self
+    }
+}
+
+/// Circuit breaker state of an endpoint.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EndpointStatus {
+    pub url: String,
+    pub state: BreakerState,
+    pub consecutive_failures: u32,
+}
+
+#[derive(Debug)]
+struct Breaker {
+    state: BreakerState,
+    failures: u32,
+    opened_at: Option<Instant>,
+    probing: bool,
+}
+
+impl Breaker {
+    fn available(&self, now: Instant, open_duration: Duration) -> bool {
+        match self.state {
+            BreakerState::Closed => true,
+            BreakerState::Open => self.opened_at.is_some_and(|at| now >= at + open_duration),
+            BreakerState::HalfOpen => !self.probing,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Endpoint {
+    url: String,
+    channel: Channel,
+    settings: CircuitBreaker,
+    breaker: Mutex<Breaker>,
+}
+
+impl Endpoint {
+    fn available(&self, now: Instant) -> bool {
+        let breaker = self.breaker.lock().unwrap();
+        breaker.available(now, self.settings.open_duration)
+    }
+
+    /// Takes the endpoint for a call if its breaker lets it through, returning whether the call
+    /// is a probe. Checked under the same lock as the state change, so only one probe goes out.
+    fn try_acquire(&self, now: Instant) -> Option<bool> {
+        let mut breaker = self.breaker.lock().unwrap();
+        if !breaker.available(now, self.settings.open_duration) {
+            return None;
+        }
+        if breaker.state == BreakerState::Closed {
+            return Some(false);
+        }
+        info!("Circuit breaker for {} half-open, probing", self.url);
+        breaker.state = BreakerState::HalfOpen;
+        breaker.probing = true;
+        Some(true)
+    }
+
+    fn open(&self, breaker: &mut Breaker) {
+        breaker.state = BreakerState::Open;
+        breaker.opened_at = Some(Instant::now());
+        breaker.probing = false;
+    }
+
+    /// Updates the breaker with the outcome of a call made through the endpoint.
+    pub(crate) fn record<T>(&self, result: &std::result::Result<T, Status>) {
+        // Only failures telling the endpoint is unhealthy count, not rejected requests
+        let failed = match result {
+            Err(status) => matches!(status.code(), Code::Unavailable | Code::DeadlineExceeded),
+            Ok(_) => false,
+        };
+        let mut breaker = self.breaker.lock().unwrap();
+        if !failed {
+            if breaker.state != BreakerState::Closed {
+                info!("Circuit breaker for {} closed", self.url);
+            }
+            breaker.state = BreakerState::Closed;
+            breaker.failures = 0;
+            breaker.probing = false;
+            return;
+        }
+        breaker.failures += 1;
+        let open = breaker.state == BreakerState::HalfOpen
+            || breaker.failures >= self.settings.failure_threshold;
+        if open {
+            warn!(
+                "Circuit breaker for {} opened after {} consecutive failures",
+                self.url, breaker.failures
+            );
+            self.open(&mut breaker);
+        }
+    }
+
+    /// Lets another probe through if the call of the current one was abandoned.
+    pub(crate) fn release(&self) {
+        self.breaker.lock().unwrap().probing = false;
+    }
+
+    fn status(&self) -> EndpointStatus {
+        let breaker = self.breaker.lock().unwrap();
+        EndpointStatus {
+            url: self.url.clone(),
+            state: breaker.state,
+            consecutive_failures: breaker.failures,
+        }
+    }
+}
+
+/// Ordered endpoints of the same service, each behind a circuit breaker.
+///
+/// Every attempt of a call goes to the next endpoint whose breaker is not open, so calls fail
+/// over to the following endpoints when retried.
+#[derive(Clone, Debug)]
+pub(crate) struct Failover {
+    endpoints: Arc<Vec<Arc<Endpoint>>>,
+}
+
+impl Failover {
+    /// Connects to every endpoint, failing only if none of them can be connected.
+    pub(crate) async fn connect(config: &SpeechCenterConfig) -> Result<Self> {
+        let urls = std::iter::once(&config.url).chain(&config.failover);
+        let mut endpoints = vec![];
+        let mut error = None;
+        let mut connected = 0;
+        for url in urls {
+            let mut config = config.clone();
+            config.url = url.clone();
+            let mut breaker = Breaker {
+                state: BreakerState::Closed,
+                failures: 0,
+                opened_at: None,
+                probing: false,
+            };
+            let channel = match config.connect().await {
+                Ok(channel) => {
+                    connected += 1;
+                    channel
+                }
+                Err(e @ SpeechCenterError::Connection { .. }) => {
+                    warn!("{}, opening its circuit breaker", e);
+                    breaker.state = BreakerState::Open;
+                    breaker.opened_at = Some(Instant::now());
+                    error.get_or_insert(e);
+                    config.endpoint()?.connect_lazy()
+                }
+                Err(e) => return Err(e),
+            };
+            endpoints.push(Arc::new(Endpoint {
+                url: url.clone(),
+                channel,
+                settings: config.circuit_breaker.clone(),
+                breaker: Mutex::new(breaker),
+            }));
+        }
+        match error {
+            Some(e) if connected == 0 => Err(e),
+            _ => Ok(Self {
+                endpoints: Arc::new(endpoints),
+            }),
+        }
+    }
+
+    /// Takes the channel of the endpoint for the given attempt of a call, starting at 1.
+    pub(crate) fn lease(&self, attempt: u32) -> Option<Lease> {
+        let now = Instant::now();
+        let available = self
+            .endpoints
+            .iter()
+            .filter(|e| e.available(now))
+            .collect::<Vec<_>>();
+        // Endpoints may have been taken for a probe since, in which case the next one is tried
+        let start = (attempt.max(1) - 1) as usize;
+        (0..available.len())
+            .map(|i| available[(start + i) % available.len()])
+            .find_map(|endpoint| {
+                let probe = endpoint.try_acquire(now)?;
+                Some(Lease::endpoint(
+                    endpoint.channel.clone(),
+                    endpoint.url.clone(),
+                    endpoint.clone(),
+                    probe,
+                ))
+            })
+    }
+
+    pub(crate) fn status(&self) -> Vec<EndpointStatus> {
+        self.endpoints.iter().map(|e| e.status()).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testkit::{Match, MockServer, Reply};
+    use crate::{RecognitionClient, RetryPolicy, StaticToken, Topic};
+
+    #[tokio::test]
+    async fn test_failover() {
+        let primary = MockServer::builder()
+            .respond(Match::Any, Reply::Transcript("primary".to_string()))
+            .start()
+            .await
+            .unwrap();
+        let backup = MockServer::builder()
+            .respond(Match::Any, Reply::Transcript("backup".to_string()))
+            .start()
+            .await
+            .unwrap();
+        let config = primary
+            .config()
+            .failover([backup.url()])
+            .circuit_breaker(
+                CircuitBreaker::default()
+                    .failure_threshold(2)
+                    .open_duration(Duration::from_millis(100)),
+            )
+            .retry_policy(RetryPolicy::default().backoff(
+                Duration::from_millis(1),
+                Duration::from_millis(1),
+                1.0,
+            ));
+        let client = RecognitionClient::with_config(config, Arc::new(StaticToken::new("")))
+            .await
+            .expect("Should connect to both endpoints");
+        let recognise = || client.recognise_with_topic("en-US", Topic::Generic, vec![0; 160]);
+
+        assert_eq!(recognise().await.unwrap().transcript, "primary");
+        primary.fail(1, Code::Unavailable, "Overloaded");
+        let res = recognise().await.unwrap();
+        assert_eq!((res.transcript.as_str(), res.attempts), ("backup", 2));
+        assert_eq!(client.endpoints()[0].consecutive_failures, 1);
+
+        primary.fail(1, Code::Unavailable, "Overloaded");
+        recognise().await.unwrap();
+        let states = client
+            .endpoints()
+            .iter()
+            .map(|e| e.state)
+            .collect::<Vec<_>>();
+        assert_eq!(states, [BreakerState::Open, BreakerState::Closed]);
+        assert_eq!(recognise().await.unwrap().transcript, "backup");
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(recognise().await.unwrap().transcript, "primary");
+        assert_eq!(client.endpoints()[0].state, BreakerState::Closed);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_single_probe() {
+        let config = SpeechCenterConfig::new("http://127.0.0.1:9").plaintext(true);
+        let endpoint = Endpoint {
+            url: config.url.clone(),
+            channel: config.endpoint().unwrap().connect_lazy(),
+            settings: CircuitBreaker::default().open_duration(Duration::ZERO),
+            breaker: Mutex::new(Breaker {
+                state: BreakerState::Open,
+                failures: 5,
+                opened_at: Some(Instant::now()),
+                probing: false,
+            }),
+        };
+        let failover = Failover {
+            endpoints: Arc::new(vec![Arc::new(endpoint)]),
+        };
+        let barrier = Arc::new(std::sync::Barrier::new(16));
+        let threads = (0..16)
+            .map(|_| {
+                let (failover, barrier) = (failover.clone(), barrier.clone());
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    failover.lease(1)
+                })
+            })
+            .collect::<Vec<_>>();
+        let leases = threads
+            .into_iter()
+            .filter_map(|thread| thread.join().unwrap())
+            .collect::<Vec<_>>();
+        // Only the probe goes through the half-open breaker
+        assert_eq!(leases.len(), 1);
+        assert_eq!(failover.status()[0].state, BreakerState::HalfOpen);
+    }
+}
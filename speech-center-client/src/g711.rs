@@ -0,0 +1,114 @@
+const SIGN_BIT: u8 = 0x80;
+const QUANT_MASK: u8 = 0x0F;
+const SEG_SHIFT: u8 = 4;
+const SEG_MASK: u8 = 0x70;
+const MULAW_BIAS: i32 = 0x84;
+const MULAW_CLIP: i32 = 8159;
+
+const ALAW_SEGMENT_ENDS: [i32; 8] = [0x1F, 0x3F, 0x7F, 0xFF, 0x1FF, 0x3FF, 0x7FF, 0xFFF];
+const MULAW_SEGMENT_ENDS: [i32; 8] = [0x3F, 0x7F, 0xFF, 0x1FF, 0x3FF, 0x7FF, 0xFFF, 0x1FFF];
+
+fn segment(value: i32, ends: &[i32; 8]) -> u8 {
+    ends.iter().position(|end| value <= *end).unwrap_or(8) as u8
+}
+
+pub fn mulaw_to_linear(mulaw: u8) -> i16 {
+    let mulaw = !mulaw;
+    let exponent = (mulaw & SEG_MASK) >> SEG_SHIFT;
+    let magnitude = ((i32::from(mulaw & QUANT_MASK) << 3) + MULAW_BIAS) << exponent;
+    match mulaw & SIGN_BIT {
+        0 => (magnitude - MULAW_BIAS) as i16,
+        _ => (MULAW_BIAS - magnitude) as i16,
+    }
+}
+
+pub fn linear_to_mulaw(sample: i16) -> u8 {
+    let sample = i32::from(sample) >> 2;
+    let (sample, mask) = match sample {
+        s if s < 0 => (-s, 0x7F),
+        s => (s, 0xFF),
+    };
+    let sample = sample.min(MULAW_CLIP) + (MULAW_BIAS >> 2);
+    let segment = segment(sample, &MULAW_SEGMENT_ENDS);
+    if segment >= 8 {
+        return 0x7F ^ mask;
+    }
+    let value = (segment << 4) | ((sample >> (segment + 1)) as u8 & QUANT_MASK);
+    value ^ mask
+}
+
+pub fn alaw_to_linear(alaw: u8) -> i16 {
+    let alaw = alaw ^ 0x55;
+    let exponent = (alaw & SEG_MASK) >> SEG_SHIFT;
+    let mantissa = i32::from(alaw & QUANT_MASK) << 4;
+    let magnitude = match exponent {
+        0 => mantissa + 8,
+        e => (mantissa + 0x108) << (e - 1),
+    };
+    match alaw & SIGN_BIT {
+        0 => -magnitude as i16,
+        _ => magnitude as i16,
+    }
+}
+
+pub fn linear_to_alaw(sample: i16) -> u8 {
+    let sample = i32::from(sample) >> 3;
+    let (sample, mask) = match sample {
+        s if s < 0 => (-s - 1, 0x55),
+        s => (s, 0xD5),
+    };
+    let segment = segment(sample, &ALAW_SEGMENT_ENDS);
+    if segment >= 8 {
+        return 0x7F ^ mask;
+    }
+    let mantissa = match segment {
+        0 | 1 => (sample >> 1) as u8,
+        s => (sample >> s) as u8,
+    };
+    ((segment << 4) | (mantissa & QUANT_MASK)) ^ mask
+}
+
+/// Encodes signed 16-bit little endian PCM into mu-law.
+pub fn encode_mulaw(pcm: &[u8]) -> Vec<u8> {
+    pcm.chunks_exact(2)
+        .map(|s| linear_to_mulaw(i16::from_le_bytes([s[0], s[1]])))
+        .collect()
+}
+
+/// Encodes signed 16-bit little endian PCM into A-law.
+pub fn encode_alaw(pcm: &[u8]) -> Vec<u8> {
+    pcm.chunks_exact(2)
+        .map(|s| linear_to_alaw(i16::from_le_bytes([s[0], s[1]])))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_mulaw() {
+        assert_eq!(mulaw_to_linear(0xFF), 0);
+        assert_eq!(mulaw_to_linear(0x00), -32124);
+        assert_eq!(mulaw_to_linear(0x80), 32124);
+        assert_eq!(linear_to_mulaw(0), 0xFF);
+        assert_eq!(linear_to_mulaw(i16::MAX), 0x80);
+        for code in 0..=255u8 {
+            let sample = mulaw_to_linear(code);
+            assert_eq!(mulaw_to_linear(linear_to_mulaw(sample)), sample);
+        }
+    }
+
+    #[test]
+    fn test_alaw() {
+        assert_eq!(alaw_to_linear(0xD5), 8);
+        assert_eq!(alaw_to_linear(0x55), -8);
+        assert_eq!(alaw_to_linear(0xAA), 32256);
+        assert_eq!(linear_to_alaw(0), 0xD5);
+        assert_eq!(linear_to_alaw(i16::MIN), 0x2A);
+        for code in 0..=255u8 {
+            let sample = alaw_to_linear(code);
+            assert_eq!(alaw_to_linear(linear_to_alaw(sample)), sample);
+        }
+    }
+}
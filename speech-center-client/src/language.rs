@@ -0,0 +1,248 @@
+use crate::{Result, SpeechCenterError};
+use std::borrow::Cow;
+use std::fmt;
+use std::str::FromStr;
+
+/// BCP-47 language tag, normalised as `es`, `es-ES`, `sr-Latn-RS` or `ca-ES-valencia`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Language {
+    tag: Cow<'static, str>,
+}
+
+const RECOGNITION_LANGUAGES: &[Language] = &[Language::EN_US, Language::ES_ES, Language::PT_BR];
+const SYNTHESIS_LANGUAGES: &[Language] = &[
+    Language::EN_US,
+    Language::ES_ES,
+    Language::PT_BR,
+    Language::CA_ES,
+];
+
+/// Service a language is checked against.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SpeechService {
+    Recognition,
+    Synthesis,
+}
+
+impl SpeechService {
+    /// Languages the service accepts, whatever the topic or speaker.
+    pub fn languages(self) -> &'static [Language] {
+        match self {
+            Self::Recognition => RECOGNITION_LANGUAGES,
+            Self::Synthesis => SYNTHESIS_LANGUAGES,
+        }
+    }
+}
+
+impl Language {
+    pub const EN_US: Language = Language::from_static("en-US");
+    pub const ES_ES: Language = Language::from_static("es-ES");
+    pub const PT_BR: Language = Language::from_static("pt-BR");
+    pub const CA_ES: Language = Language::from_static("ca-ES");
+
+    const fn from_static(tag: &'static str) -> Self {
+        Self {
+            tag: Cow::Borrowed(tag),
+        }
+    }
+
+    /// Parses a tag such as `en-us` or `pt_BR`, normalising the case of its subtags.
+    pub fn parse(tag: &str) -> Result<Self> {
+        let invalid =
+            || SpeechCenterError::InvalidParameter(format!("Invalid language tag: {:?}", tag));
+        let mut subtags = tag.trim().split(['-', '_']);
+        let primary = subtags.next().unwrap_or_default();
+        if !(2..=8).contains(&primary.len()) || !primary.chars().all(|c| c.is_ascii_alphabetic()) {
+            return Err(invalid());
+        }
+        let mut normalised = primary.to_ascii_lowercase();
+        // Script and region are only recognised in their place, right after the primary subtag
+        let mut expect_script = true;
+        let mut expect_region = true;
+        for subtag in subtags {
+            if subtag.is_empty()
+                || subtag.len() > 8
+                || !subtag.chars().all(|c| c.is_ascii_alphanumeric())
+            {
+                return Err(invalid());
+            }
+            normalised.push('-');
+            if expect_script && is_script(subtag) {
+                normalised.push_str(&subtag[..1].to_ascii_uppercase());
+                normalised.push_str(&subtag[1..].to_ascii_lowercase());
+                expect_script = false;
+            } else if expect_region && is_region(subtag) {
+                normalised.push_str(&subtag.to_ascii_uppercase());
+                expect_script = false;
+                expect_region = false;
+            } else {
+                normalised.push_str(&subtag.to_ascii_lowercase());
+                expect_script = false;
+                expect_region = false;
+            }
+        }
+        Ok(Self {
+            tag: Cow::Owned(normalised),
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.tag
+    }
+
+    /// Primary language subtag, such as `es` for `es-ES`.
+    pub fn primary(&self) -> &str {
+        self.subtags().next().unwrap_or_default()
+    }
+
+    /// Region subtag, such as `ES` for `es-ES`.
+    pub fn region(&self) -> Option<&str> {
+        self.subtags()
+            .skip(1)
+            .take(2)
+            .find(|subtag| is_region(subtag))
+    }
+
+    fn subtags(&self) -> impl Iterator<Item = &str> {
+        self.tag.split('-')
+    }
+
+    /// Whether the service accepts the language.
+    pub fn is_supported_by(&self, service: SpeechService) -> bool {
+        service.languages().contains(self)
+    }
+
+    /// The language itself if supported, or else a supported language sharing its primary subtag,
+    /// so `es-MX` and `es` fall back to `es-ES`.
+    pub fn closest<'a>(&self, supported: &'a [Language]) -> Option<&'a Language> {
+        supported
+            .iter()
+            .find(|language| *language == self)
+            .or_else(|| {
+                supported
+                    .iter()
+                    .find(|language| language.primary() == self.primary())
+            })
+    }
+
+    /// Fails unless the language is one of the supported ones, suggesting the closest one.
+    pub fn check(&self, supported: &[Language]) -> Result<()> {
+        if supported.contains(self) {
+            return Ok(());
+        }
+        let hint = match self.closest(supported) {
+            Some(closest) => format!("did you mean {}?", closest),
+            None => format!("supported: {}", join(supported)),
+        };
+        Err(SpeechCenterError::InvalidParameter(format!(
+            "Language {} is not supported, {}",
+            self, hint
+        )))
+    }
+
+    /// The closest supported language, failing if there is none.
+    pub fn fallback(&self, supported: &[Language]) -> Result<Language> {
+        match self.closest(supported) {
+            Some(closest) => Ok(closest.clone()),
+            None => self.check(supported).map(|_| self.clone()),
+        }
+    }
+}
+
+fn is_script(subtag: &str) -> bool {
+    subtag.len() == 4 && subtag.chars().all(|c| c.is_ascii_alphabetic())
+}
+
+fn is_region(subtag: &str) -> bool {
+    (subtag.len() == 2 && subtag.chars().all(|c| c.is_ascii_alphabetic()))
+        || (subtag.len() == 3 && subtag.chars().all(|c| c.is_ascii_digit()))
+}
+
+fn join(languages: &[Language]) -> String {
+    languages
+        .iter()
+        .map(Language::as_str)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl FromStr for Language {
+    type Err = SpeechCenterError;
+
+    fn from_str(tag: &str) -> Result<Self> {
+        Self::parse(tag)
+    }
+}
+
+impl fmt::Display for Language {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.tag)
+    }
+}
+
+impl AsRef<str> for Language {
+    fn as_ref(&self) -> &str {
+        &self.tag
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Speaker, Topic};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Language::parse("en-us").unwrap(), Language::EN_US);
+        assert_eq!(Language::parse(" PT_br ").unwrap(), Language::PT_BR);
+        assert_eq!(Language::parse("es").unwrap().as_str(), "es");
+        assert_eq!(Language::parse("es-419").unwrap().region(), Some("419"));
+        let serbian = Language::parse("SR-latn-rs").unwrap();
+        assert_eq!(serbian.as_str(), "sr-Latn-RS");
+        assert_eq!(serbian.primary(), "sr");
+        assert_eq!(serbian.region(), Some("RS"));
+        let valencian: Language = "ca-es-VALENCIA".parse().unwrap();
+        assert_eq!(valencian.to_string(), "ca-ES-valencia");
+        for tag in ["", "e", "en-", "en--us", "en-u$", "1en", "en-toolongsubtag"] {
+            assert!(
+                matches!(
+                    Language::parse(tag),
+                    Err(SpeechCenterError::InvalidParameter(_))
+                ),
+                "{:?}",
+                tag
+            );
+        }
+    }
+
+    #[test]
+    fn test_support() {
+        assert!(Language::EN_US.is_supported_by(SpeechService::Recognition));
+        assert!(!Language::CA_ES.is_supported_by(SpeechService::Recognition));
+        assert!(Language::CA_ES.is_supported_by(SpeechService::Synthesis));
+        assert!(Topic::Banking.languages().contains(&Language::ES_ES));
+        assert_eq!(Speaker::PtBrLuma.language(), Language::PT_BR);
+
+        let recognition = SpeechService::Recognition.languages();
+        let closest = |tag: &str| Language::parse(tag).unwrap().closest(recognition).cloned();
+        assert_eq!(closest("es-ES"), Some(Language::ES_ES));
+        assert_eq!(closest("es-MX"), Some(Language::ES_ES));
+        assert_eq!(closest("pt"), Some(Language::PT_BR));
+        assert_eq!(closest("fr-FR"), None);
+
+        let british = Language::parse("en-GB").unwrap();
+        assert_eq!(british.fallback(recognition).unwrap(), Language::EN_US);
+        let error = british.check(recognition).unwrap_err();
+        assert!(
+            error.to_string().contains("did you mean en-US?"),
+            "{}",
+            error
+        );
+        let error = Language::CA_ES.fallback(recognition).unwrap_err();
+        assert!(
+            error.to_string().contains("supported: en-US, es-ES, pt-BR"),
+            "{}",
+            error
+        );
+    }
+}
@@ -1,12 +1,58 @@
+#[macro_use]
+extern crate tracing;
+
+pub mod audio;
+mod audio_stream;
+#[cfg(any(test, feature = "blocking"))]
+pub mod blocking;
+pub mod cassette;
+mod config;
+pub mod credentials;
+pub mod decoder;
 mod error;
+mod failover;
+pub mod g711;
+mod language;
+mod pool;
 mod recognizer_client;
+mod result;
+mod retry;
+mod segment;
+mod service;
+mod speech_center;
+mod subtitles;
 mod synthesizer_client;
+#[cfg(any(test, feature = "testkit"))]
+pub mod testkit;
+mod vad;
+pub mod wav;
 
 mod csr_grpc_gateway;
 #[path = "speechcenter.tts.v1.rs"]
 mod speechcenter_tts_v1;
 
-pub use error::SpeechCenterError;
-pub use recognizer_client::{Client as RecognitionClient, Topic};
+pub use audio::{AudioSpec, SampleFormat};
+pub use audio_stream::{AudioStream, ChunkSize, RECOGNITION_SAMPLE_RATE, RECOGNITION_SAMPLE_WIDTH};
+pub use cassette::Cassette;
+pub use config::SpeechCenterConfig;
+pub use credentials::{
+    ClientCredentials, CredentialsProvider, EnvToken, StaticToken, Token, TokenFile,
+};
+pub use decoder::DecodeError;
+pub use error::{RpcError, SpeechCenterError};
+pub use failover::{BreakerState, CircuitBreaker, EndpointStatus};
+pub use language::{Language, SpeechService};
+pub use pool::{Balancing, ChannelPool, ChannelPoolBuilder, ChannelStatus};
+pub use recognizer_client::{Client as RecognitionClient, Resource, Topic};
+pub use result::{RecognitionResult, SynthesisResult, Transcript, TranscriptSegment};
+pub use retry::{Retried, RetryPolicy};
+pub use segment::{Segment, Segmenter};
+pub use service::{
+    Cached, FakeRecognition, FakeRecognizer, FakeSynthesis, FakeSynthesizer, Fallback, Recognizer,
+    Synthesizer,
+};
+pub use speech_center::{SpeechCenter, SpeechCenterBuilder, RECOGNITION_URL, SYNTHESIS_URL};
+pub use subtitles::{Cue, OutputFormat, SubtitleFormat, Subtitles};
 pub use synthesizer_client::{AudioFormat, Client as SynthesisClient, SampleRate, Speaker};
+pub use vad::{Aggressiveness, Vad, VadStats, VAD_FRAME};
 pub type Result<T, E = SpeechCenterError> = std::result::Result<T, E>;
@@ -0,0 +1,509 @@
+use crate::config::SpeechCenterConfig;
+use crate::credentials::CredentialsProvider;
+use crate::failover::{Endpoint, Failover};
+use crate::{Result, SpeechCenterError};
+use std::error::Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use tonic::codec::ProstCodec;
+use tonic::codegen::http::uri::PathAndQuery;
+use tonic::transport::Channel;
+use tonic::{Code, Request, Status};
+
+/// How calls are spread across the channels of a [`ChannelPool`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Balancing {
+    RoundRobin,
+    /// Picks the channel with the fewest calls in flight
+    LeastOutstanding,
+}
+
+impl Balancing {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name.to_lowercase().as_str() {
+            "round-robin" => Ok(Self::RoundRobin),
+            "least-outstanding" => Ok(Self::LeastOutstanding),
+            _ => Err(SpeechCenterError::InvalidParameter(format!(
+                "Unknown balancing: {}",
+                name
+            ))),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Slot {
+    config: SpeechCenterConfig,
+    channel: Mutex<Option<Channel>>,
+    outstanding: AtomicUsize,
+}
+
+impl Slot {
+    fn channel(&self) -> Option<Channel> {
+        self.channel.lock().unwrap().clone()
+    }
+
+    fn evict(&self, reason: &Status) {
+        if self.channel.lock().unwrap().take().is_some() {
+            warn!(
+                "Evicting channel to {}: [{:?}] {}",
+                self.config.url,
+                reason.code(),
+                reason.message()
+            );
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    config: SpeechCenterConfig,
+    slots: Vec<Arc<Slot>>,
+    balancing: Balancing,
+    next: AtomicUsize,
+}
+
+impl Inner {
+    /// Evicts the live channels failing a probe and reconnects the evicted ones.
+    async fn check(&self, timeout: Duration) {
+        for slot in &self.slots {
+            if let Some(channel) = slot.channel() {
+                // Replayed calls never reach the service, so there is nothing to probe
+                if slot.config.cassette.as_ref().is_some_and(|c| c.is_replay()) {
+                    continue;
+                }
+                if let Some(status) = probe(channel, timeout).await {
+                    slot.evict(&status);
+                }
+                continue;
+            }
+            match slot.config.connect().await {
+                Ok(channel) => {
+                    info!("Reconnected channel to {}", slot.config.url);
+                    *slot.channel.lock().unwrap() = Some(channel);
+                }
+                Err(e) => debug!("Channel to {} still unavailable: {}", slot.config.url, e),
+            }
+        }
+    }
+}
+
+/// Sends a gRPC health check through the channel, returning why it failed if the channel is
+/// broken. Any answer from the server, even one saying the health service is not implemented,
+/// shows that the connection works.
+async fn probe(channel: Channel, timeout: Duration) -> Option<Status> {
+    let mut client = tonic::client::Grpc::new(channel);
+    let check = async {
+        if let Err(e) = client.ready().await {
+            return Some(Status::unavailable(e.to_string()));
+        }
+        let path = PathAndQuery::from_static("/grpc.health.v1.Health/Check");
+        let codec = ProstCodec::<(), ()>::default();
+        client
+            .unary(Request::new(()), path, codec)
+            .await
+            .err()
+            .filter(is_transport)
+    };
+    tokio::time::timeout(timeout, check)
+        .await
+        .unwrap_or_else(|_| Some(Status::deadline_exceeded("Health check timed out")))
+}
+
+/// Whether the call failed at the transport level rather than in the service.
+fn is_transport(status: &Status) -> bool {
+    // Statuses returned by the service itself have no source
+    matches!(status.code(), Code::Unavailable | Code::Unknown) && status.source().is_some()
+}
+
+/// State of a channel of the pool.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChannelStatus {
+    pub url: String,
+    pub healthy: bool,
+    /// Calls in flight
+    pub outstanding: usize,
+}
+
+/// Set of channels to one or more urls shared by all the clients built on it.
+///
+/// Every health check interval, live channels are probed with a gRPC health check and evicted if
+/// it fails at the transport level or times out, while evicted channels are reconnected. Calls
+/// failing at the transport level evict their channel right away. Calls go to the remaining ones.
+#[derive(Clone, Debug)]
+pub struct ChannelPool {
+    inner: Arc<Inner>,
+}
+
+impl ChannelPool {
+    /// Pool connecting to the url of the configuration unless given others.
+    pub fn builder(config: SpeechCenterConfig) -> ChannelPoolBuilder {
+        ChannelPoolBuilder {
+            urls: vec![config.url.clone()],
+            config,
+            size: None,
+            balancing: Balancing::LeastOutstanding,
+            health_check_interval: Duration::from_secs(5),
+        }
+    }
+
+    pub fn config(&self) -> &SpeechCenterConfig {
+        &self.inner.config
+    }
+
+    pub fn size(&self) -> usize {
+        self.inner.slots.len()
+    }
+
+    /// Number of channels currently connected.
+    pub fn healthy(&self) -> usize {
+        self.status().iter().filter(|s| s.healthy).count()
+    }
+
+    pub fn status(&self) -> Vec<ChannelStatus> {
+        self.inner
+            .slots
+            .iter()
+            .map(|slot| ChannelStatus {
+                url: slot.config.url.clone(),
+                healthy: slot.channel().is_some(),
+                outstanding: slot.outstanding.load(Ordering::SeqCst),
+            })
+            .collect()
+    }
+
+    /// Takes a healthy channel for a call, or none if all of them are evicted.
+    pub(crate) fn lease(&self) -> Option<Lease> {
+        let slots = &self.inner.slots;
+        let (slot, channel) = match self.inner.balancing {
+            Balancing::RoundRobin => {
+                let start = self.inner.next.fetch_add(1, Ordering::Relaxed);
+                (0..slots.len())
+                    .map(|i| &slots[(start + i) % slots.len()])
+                    .find_map(|s| Some((s, s.channel()?)))?
+            }
+            Balancing::LeastOutstanding => slots
+                .iter()
+                .filter_map(|s| Some((s, s.channel()?)))
+                .min_by_key(|(s, _)| s.outstanding.load(Ordering::SeqCst))?,
+        };
+        slot.outstanding.fetch_add(1, Ordering::SeqCst);
+        Some(Lease {
+            channel,
+            url: slot.config.url.clone(),
+            slot: Some(slot.clone()),
+            endpoint: None,
+            probe: false,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct ChannelPoolBuilder {
+    config: SpeechCenterConfig,
+    urls: Vec<String>,
+    size: Option<usize>,
+    balancing: Balancing,
+    health_check_interval: Duration,
+}
+
+impl ChannelPoolBuilder {
+    /// Urls serving the same service, replacing the one of the configuration.
+    pub fn urls<I, S>(mut self, urls: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.urls = urls.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Number of channels, spread evenly across the urls. One per url by default.
+    pub fn size(mut self, size: usize) -> Self {
+        self.size = Some(size);
+        self
+    }
+
+    pub fn balancing(mut self, balancing: Balancing) -> Self {
+        self.balancing = balancing;
+        self
+    }
+
+    /// Time between health checks, which probe live channels and reconnect evicted ones. A probe
+    /// not answered within the interval evicts its channel.
+    pub fn health_check_interval(mut self, interval: Duration) -> Self {
+        self.health_check_interval = interval;
+        self
+    }
+
+    /// Connects all the channels, failing only if none of them can be connected.
+    pub async fn connect(self) -> Result<ChannelPool> {
+        if self.urls.is_empty() {
+            return Err(SpeechCenterError::Config(
+                "Channel pool without urls".to_string(),
+            ));
+        }
+        let size = self.size.unwrap_or(self.urls.len()).max(1);
+        let mut slots = Vec::with_capacity(size);
+        let mut error = None;
+        for i in 0..size {
+            let mut config = self.config.clone();
+            config.url = self.urls[i % self.urls.len()].clone();
+            let channel = match config.connect().await {
+                Ok(channel) => Some(channel),
+                Err(e @ SpeechCenterError::Connection { .. }) => {
+                    warn!("{}, will retry in the background", e);
+                    error = Some(e);
+                    None
+                }
+                Err(e) => return Err(e),
+            };
+            slots.push(Arc::new(Slot {
+                config,
+                channel: Mutex::new(channel),
+                outstanding: AtomicUsize::new(0),
+            }));
+        }
+        let pool = ChannelPool {
+            inner: Arc::new(Inner {
+                config: self.config,
+                slots,
+                balancing: self.balancing,
+                next: AtomicUsize::new(0),
+            }),
+        };
+        if let Some(error) = error.filter(|_| pool.healthy() == 0) {
+            return Err(error);
+        }
+        spawn_health_checks(Arc::downgrade(&pool.inner), self.health_check_interval);
+        Ok(pool)
+    }
+}
+
+fn spawn_health_checks(inner: Weak<Inner>, interval: Duration) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(interval).await;
+            // Stops once the pool is dropped
+            match inner.upgrade() {
+                Some(inner) => inner.check(interval).await,
+                None => break,
+            }
+        }
+    });
+}
+
+/// Channels a client sends its calls through: a dedicated one, a pool or failover endpoints.
+#[derive(Clone, Debug)]
+pub(crate) enum Channels {
+    Single { channel: Channel, url: String },
+    Pool(ChannelPool),
+    Failover(Failover),
+}
+
+impl Channels {
+    /// Channel for the given attempt of a call, starting at 1.
+    pub(crate) fn lease(&self, attempt: u32) -> Option<Lease> {
+        match self {
+            Self::Single { channel, url } => Some(Lease {
+                channel: channel.clone(),
+                url: url.clone(),
+                slot: None,
+                endpoint: None,
+                probe: false,
+            }),
+            Self::Pool(pool) => pool.lease(),
+            Self::Failover(failover) => failover.lease(attempt),
+        }
+    }
+}
+
+/// Channel taken for a single call, counted as outstanding until dropped.
+#[derive(Debug)]
+pub(crate) struct Lease {
+    channel: Channel,
+    url: String,
+    slot: Option<Arc<Slot>>,
+    endpoint: Option<Arc<Endpoint>>,
+    probe: bool,
+}
+
+impl Lease {
+    pub(crate) fn endpoint(
+        channel: Channel,
+        url: String,
+        endpoint: Arc<Endpoint>,
+        probe: bool,
+    ) -> Self {
+        Self {
+            channel,
+            url,
+            slot: None,
+            endpoint: Some(endpoint),
+            probe,
+        }
+    }
+
+    pub(crate) fn channel(&self) -> Channel {
+        self.channel.clone()
+    }
+
+    /// URL the channel is connected to.
+    pub(crate) fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Evicts the channel if the call failed at the transport level, and updates the circuit
+    /// breaker of its endpoint.
+    pub(crate) fn report<T>(&self, result: &std::result::Result<T, Status>) {
+        if let Some(endpoint) = &self.endpoint {
+            endpoint.record(result);
+        }
+        if let (Some(slot), Err(status)) = (&self.slot, result) {
+            if is_transport(status) {
+                slot.evict(status);
+            }
+        }
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        if let Some(slot) = &self.slot {
+            slot.outstanding.fetch_sub(1, Ordering::SeqCst);
+        }
+        if let (Some(endpoint), true) = (&self.endpoint, self.probe) {
+            endpoint.release();
+        }
+    }
+}
+
+/// Service behind a leased channel, authorizing every call with the credentials.
+pub(crate) struct Remote {
+    pub(crate) lease: Lease,
+    pub(crate) gzip: bool,
+    pub(crate) credentials: Arc<dyn CredentialsProvider>,
+}
+
+/// Status of calls made when every channel is evicted or behind an open circuit breaker.
+pub(crate) fn no_channel() -> Status {
+    Status::unavailable("No healthy channel available")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testkit::{Match, MockServer, Reply};
+    use crate::{RecognitionClient, RetryPolicy, StaticToken, Topic};
+
+    async fn server() -> MockServer {
+        MockServer::builder()
+            .respond(Match::Any, Reply::Transcript("hello".to_string()))
+            .start()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_balancing() {
+        let (a, b) = (server().await, server().await);
+        let pool = ChannelPool::builder(a.config())
+            .urls([a.url(), b.url()])
+            .size(4)
+            .balancing(Balancing::RoundRobin)
+            .connect()
+            .await
+            .expect("Should connect to both servers");
+        assert_eq!(pool.size(), 4);
+        let client = RecognitionClient::with_pool(&pool, Arc::new(StaticToken::new("")));
+        for _ in 0..4 {
+            client
+                .recognise_with_topic("en-US", Topic::Generic, vec![0; 160])
+                .await
+                .unwrap();
+        }
+        assert_eq!((a.calls(), b.calls()), (2, 2));
+
+        let pool = ChannelPool::builder(a.config())
+            .urls([a.url(), b.url()])
+            .connect()
+            .await
+            .unwrap();
+        let leases = (0..4).map(|_| pool.lease().unwrap()).collect::<Vec<_>>();
+        let outstanding = pool
+            .status()
+            .iter()
+            .map(|s| s.outstanding)
+            .collect::<Vec<_>>();
+        assert_eq!(outstanding, [2, 2]);
+        drop(leases);
+        assert!(pool.status().iter().all(|s| s.outstanding == 0));
+    }
+
+    #[tokio::test]
+    async fn test_eviction() {
+        let (a, b) = (server().await, server().await);
+        let config = a
+            .config()
+            .connect_timeout(Duration::from_secs(1))
+            .retry_policy(RetryPolicy::default().backoff(
+                Duration::from_millis(1),
+                Duration::from_millis(1),
+                1.0,
+            ));
+        let pool = ChannelPool::builder(config)
+            .urls([a.url(), b.url(), "http://127.0.0.1:9".to_string()])
+            .balancing(Balancing::RoundRobin)
+            .health_check_interval(Duration::from_secs(60))
+            .connect()
+            .await
+            .expect("Should connect to the running servers");
+        assert_eq!(pool.healthy(), 2);
+
+        let b_url = b.url();
+        drop(b);
+        let client = RecognitionClient::with_pool(&pool, Arc::new(StaticToken::new("")));
+        for _ in 0..4 {
+            client
+                .recognise_with_topic("en-US", Topic::Generic, vec![0; 160])
+                .await
+                .expect("Should be retried on the running server");
+        }
+        assert_eq!(a.calls(), 4);
+        let healthy = pool
+            .status()
+            .into_iter()
+            .filter(|s| s.healthy)
+            .map(|s| s.url)
+            .collect::<Vec<_>>();
+        assert_eq!(healthy, [a.url()]);
+        assert!(!healthy.contains(&b_url));
+    }
+
+    #[tokio::test]
+    async fn test_health_check() {
+        let (a, b) = (server().await, server().await);
+        let pool = ChannelPool::builder(a.config())
+            .urls([a.url(), b.url()])
+            .health_check_interval(Duration::from_millis(50))
+            .connect()
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        // The mock server has no health service, which still shows the channels work
+        assert_eq!(pool.healthy(), 2);
+
+        drop(b);
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let healthy = pool
+            .status()
+            .into_iter()
+            .filter(|s| s.healthy)
+            .map(|s| s.url)
+            .collect::<Vec<_>>();
+        assert_eq!(healthy, [a.url()]);
+        // The broken channel was found without sending any call
+        assert_eq!(a.calls(), 0);
+    }
+}
@@ -1,19 +1,30 @@
+use crate::audio_stream::{AudioStream, ChunkSize, ReplayableAudio};
+use crate::cassette::{CallResult, Cassette, RecognizeStream, Recorded, RequestStream};
+use crate::config::SpeechCenterConfig;
+use crate::credentials::{self, CredentialsProvider, StaticToken};
 use crate::csr_grpc_gateway::recognition_request::RequestUnion;
 use crate::csr_grpc_gateway::recognition_resource::{Model, ResourceUnion};
 use crate::csr_grpc_gateway::speech_recognizer_client::SpeechRecognizerClient;
 use crate::csr_grpc_gateway::{
     RecognitionInit, RecognitionParameters, RecognitionRequest, RecognitionResource,
+    RecognitionResponse,
 };
-use crate::{Result, SpeechCenterError};
-use std::error::Error;
-use std::str::FromStr;
-use tonic::codegen::InterceptedService;
-use tonic::metadata::{Ascii, MetadataValue};
-use tonic::service::Interceptor;
-use tonic::transport::{Channel, ClientTlsConfig, Uri};
-use tonic::{Request, Status};
+use crate::failover::EndpointStatus;
+use crate::language::{Language, SpeechService};
+use crate::pool::{self, ChannelPool, Channels, Remote};
+use crate::result::{self, RecognitionResult};
+use crate::retry::{self, RetryPolicy};
+use crate::vad::Vad;
+use crate::{AudioSpec, Result, SpeechCenterError};
+use async_trait::async_trait;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_stream::StreamExt;
+use tonic::transport::Channel;
+use tonic::Request;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Topic {
     Generic,
     Banking,
@@ -26,13 +37,22 @@ impl Topic {
             "generic" => Ok(Topic::Generic),
             "banking" => Ok(Topic::Banking),
             "telco" => Ok(Topic::Telco),
-            _ => Err(SpeechCenterError::Unknown(format!(
+            _ => Err(SpeechCenterError::InvalidParameter(format!(
                 "Unknown model name: {}",
                 name
             ))),
         }
     }
 
+    /// Languages the topic can be recognised in.
+    pub fn languages(&self) -> &'static [Language] {
+        match self {
+            Topic::Generic | Topic::Banking | Topic::Telco => {
+                SpeechService::Recognition.languages()
+            }
+        }
+    }
+
     pub fn to_model(self) -> Model {
         match self {
             Topic::Generic => Model::Generic,
@@ -42,134 +62,303 @@ impl Topic {
     }
 }
 
-pub struct AddAuthorizationInterceptor {
-    credentials: MetadataValue<Ascii>,
-}
-
-impl AddAuthorizationInterceptor {
-    pub fn new(token: &str) -> Result<Self> {
-        let credentials = tonic::metadata::MetadataValue::from_str(token).map_err(|e| {
-            SpeechCenterError::Unknown(format!(
-                "Error converting credentials to MetadataValue: {}",
-                e
-            ))
-        })?;
-        Ok(Self { credentials })
-    }
+/// What the recognizer listens for: an out-of-the-box topic or an inline ABNF grammar.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Resource {
+    Topic(Topic),
+    Grammar(String),
 }
 
-impl Interceptor for AddAuthorizationInterceptor {
-    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
-        request
-            .metadata_mut()
-            .insert("authorization", self.credentials.clone());
-        Ok(request)
-    }
-}
-
-#[derive(Debug)]
+/// Handle to the service, cheap to clone and usable concurrently from many tasks.
+#[derive(Clone, Debug)]
 pub struct Client {
-    inner: SpeechRecognizerClient<InterceptedService<Channel, AddAuthorizationInterceptor>>,
+    channels: Channels,
+    gzip: bool,
+    credentials: Arc<dyn CredentialsProvider>,
+    retry: RetryPolicy,
+    cassette: Option<Arc<Cassette>>,
+    convert_audio: bool,
+    vad: Option<Vad>,
 }
 
 impl Client {
     pub async fn new(url: &str, credentials: &str) -> Result<Self> {
-        let uri = Uri::from_str(url).map_err(|e| {
-            SpeechCenterError::Unknown(format!(
-                "Error building Uri from string [uri={}]: {}",
-                url, e
-            ))
-        })?;
-        let channel = Channel::builder(uri)
-            .tls_config(ClientTlsConfig::default())
-            .map_err(|e| SpeechCenterError::Unknown(format!("Error setting up tls: {:?}", e)))?;
-        let channel = channel.connect().await.map_err(|e| {
-            SpeechCenterError::Connection(format!(
-                "Could not connect [url={}] {}",
-                url,
-                e.source().map(|e| e.to_string()).unwrap_or_default()
-            ))
-        })?;
-
-        let interceptor = AddAuthorizationInterceptor::new(credentials)?;
-        let c = SpeechRecognizerClient::with_interceptor(channel, interceptor);
-        Ok(Self { inner: c })
+        let credentials = Arc::new(StaticToken::new(credentials));
+        Self::with_config(SpeechCenterConfig::new(url), credentials).await
+    }
+
+    pub async fn with_config(
+        config: SpeechCenterConfig,
+        credentials: Arc<dyn CredentialsProvider>,
+    ) -> Result<Self> {
+        let channels = config.channels().await?;
+        Ok(Self::from_channels(channels, config, credentials))
+    }
+
+    /// Client sending its calls through the channels of the pool.
+    pub fn with_pool(pool: &ChannelPool, credentials: Arc<dyn CredentialsProvider>) -> Self {
+        Self::from_channels(
+            Channels::Pool(pool.clone()),
+            pool.config().clone(),
+            credentials,
+        )
+    }
+
+    /// Circuit breaker states of the failover endpoints, empty without failover.
+    pub fn endpoints(&self) -> Vec<EndpointStatus> {
+        match &self.channels {
+            Channels::Failover(failover) => failover.status(),
+            _ => vec![],
+        }
+    }
+
+    pub(crate) fn from_channels(
+        channels: Channels,
+        config: SpeechCenterConfig,
+        credentials: Arc<dyn CredentialsProvider>,
+    ) -> Self {
+        Self {
+            channels,
+            gzip: config.gzip,
+            credentials,
+            retry: config.retry,
+            cassette: config.cassette,
+            convert_audio: false,
+            vad: None,
+        }
+    }
+
+    /// Converts audio in any supported format to mono 8kHz PCM16 instead of rejecting it.
+    pub fn with_audio_conversion(mut self, enabled: bool) -> Self {
+        self.convert_audio = enabled;
+        self
+    }
+
+    /// Trims the silence before and after the speech with voice activity detection, reporting
+    /// the speech ratio in the results.
+    pub fn with_vad(mut self, vad: Option<Vad>) -> Self {
+        self.vad = vad;
+        self
     }
 
     pub async fn recognise_with_topic(
-        &mut self,
+        &self,
         language: &str,
         topic: Topic,
         audio: Vec<u8>,
-    ) -> Result<String> {
-        let model = topic.to_model();
-        let initial = RecognitionRequest {
-            request_union: Some(RequestUnion::Init(RecognitionInit {
-                parameters: Some(RecognitionParameters {
-                    language: language.to_string(),
-                }),
-                resource: Some(RecognitionResource {
-                    resource_union: Some(ResourceUnion::Topic(i32::from(model))),
-                }),
-            })),
-        };
-        self.recognise(audio, initial).await
+    ) -> Result<RecognitionResult> {
+        let audio = AudioStream::from_bytes(audio, ChunkSize::default());
+        self.recognise_stream_with_topic(language, topic, audio)
+            .await
     }
 
     pub async fn recognise_with_grammar(
-        &mut self,
+        &self,
         grammar: &str,
         language: &str,
         audio: Vec<u8>,
-    ) -> Result<String> {
+    ) -> Result<RecognitionResult> {
+        let audio = AudioStream::from_bytes(audio, ChunkSize::default());
+        self.recognise_stream_with_grammar(grammar, language, audio)
+            .await
+    }
+
+    pub async fn recognise_stream_with_topic(
+        &self,
+        language: &str,
+        topic: Topic,
+        audio: AudioStream,
+    ) -> Result<RecognitionResult> {
+        self.call(language, Resource::Topic(topic), audio).await
+    }
+
+    pub async fn recognise_stream_with_grammar(
+        &self,
+        grammar: &str,
+        language: &str,
+        audio: AudioStream,
+    ) -> Result<RecognitionResult> {
+        self.call(language, Resource::Grammar(grammar.to_string()), audio)
+            .await
+    }
+
+    async fn call(
+        &self,
+        language: &str,
+        resource: Resource,
+        audio: AudioStream,
+    ) -> Result<RecognitionResult> {
+        // Unsupported languages are rejected before any audio is sent
+        let language = Language::parse(language)?;
+        match &resource {
+            Resource::Topic(topic) => language.check(topic.languages())?,
+            Resource::Grammar(_) => language.check(SpeechService::Recognition.languages())?,
+        }
+        let resource_union = match resource {
+            Resource::Topic(topic) => ResourceUnion::Topic(i32::from(topic.to_model())),
+            Resource::Grammar(grammar) => ResourceUnion::InlineGrammar(grammar),
+        };
         let initial = RecognitionRequest {
             request_union: Some(RequestUnion::Init(RecognitionInit {
                 parameters: Some(RecognitionParameters {
                     language: language.to_string(),
                 }),
                 resource: Some(RecognitionResource {
-                    resource_union: Some(ResourceUnion::InlineGrammar(grammar.to_string())),
+                    resource_union: Some(resource_union),
                 }),
             })),
         };
-        self.recognise(audio, initial).await
-    }
-
-    async fn recognise(&mut self, audio: Vec<u8>, initial: RecognitionRequest) -> Result<String> {
-        let audio_req = RecognitionRequest {
-            request_union: Some(RequestUnion::Audio(audio)),
+        let audio = match audio.spec() {
+            spec if spec.is_recognizable() => audio,
+            spec if spec.is_telephony() => audio.convert()?,
+            _ if self.convert_audio => audio.convert()?,
+            spec => return Err(SpeechCenterError::UnsupportedAudio(spec)),
         };
-        let s = async_stream::stream! {
-            yield initial;
-            yield audio_req;
+        let (audio, vad) = match &self.vad {
+            Some(vad) => {
+                let (audio, stats) = vad.trim(audio);
+                (audio, Some(stats))
+            }
+            None => (audio, None),
         };
+        // Audio is kept in memory for retries up to the policy limit, longer audio is sent once
+        let audio = ReplayableAudio::new(audio, self.retry.replay_limit()).await;
+        let replayable = audio.is_replayable();
+        let started = Instant::now();
 
-        let r = self
-            .inner
-            .recognize_stream(Request::new(s))
-            .await
-            .map_err(|e| {
-                SpeechCenterError::Recognision(format!(
-                    "Error in recognition: [{}] {}",
-                    e.code(),
-                    e.message()
-                ))
-            })?;
-        let res = r.get_ref();
-        Ok(res.text.to_string())
+        let mut attempt = 0;
+        let call = || {
+            attempt += 1;
+            let lease = self.channels.lease(attempt);
+            let gzip = self.gzip;
+            let credentials = self.credentials.clone();
+            let cassette = self.cassette.clone();
+            let initial = initial.clone();
+            let mut audio = Box::pin(audio.replay());
+            let attempts = attempt;
+            async move {
+                let lease = match lease {
+                    Some(lease) => lease,
+                    None => return Ok(Err(pool::no_channel())),
+                };
+                let endpoint = lease.url().to_string();
+                let remote = Remote {
+                    lease,
+                    gzip,
+                    credentials,
+                };
+                // Request streams cannot fail, so read errors are kept aside and reported once the
+                // call is over instead of returning a transcription of partial audio.
+                let read_error = Arc::new(Mutex::new(None));
+                let stream_error = read_error.clone();
+                let sent = Arc::new(AtomicUsize::new(0));
+                let stream_sent = sent.clone();
+                let s = async_stream::stream! {
+                    yield initial;
+                    while let Some(chunk) = audio.next().await {
+                        match chunk {
+                            Ok(chunk) => {
+                                stream_sent.fetch_add(chunk.len(), Ordering::Relaxed);
+                                yield RecognitionRequest {
+                                    request_union: Some(RequestUnion::Audio(chunk.to_vec())),
+                                }
+                            }
+                            Err(e) => {
+                                *stream_error.lock().unwrap() = Some(e);
+                                break;
+                            }
+                        }
+                    }
+                };
+
+                let r = Recorded::new(remote, cassette)
+                    .recognize_stream(s)
+                    .await?
+                    .map(|r| {
+                        let metadata = result::metadata(r.metadata());
+                        let text = r.into_inner().text;
+                        RecognitionResult {
+                            endpoint: Some(endpoint),
+                            metadata,
+                            ..RecognitionResult::new(text, Duration::ZERO, Duration::ZERO)
+                        }
+                    });
+                if let Some(e) = read_error.lock().unwrap().take() {
+                    return Err(SpeechCenterError::io("Error reading audio", e));
+                }
+                let audio_duration = AudioSpec::RECOGNITION.duration(sent.load(Ordering::Relaxed));
+                match r {
+                    // Errors returned by the call itself are not retried
+                    Err(status) if !replayable => {
+                        Err(SpeechCenterError::from_status(status, attempts))
+                    }
+                    r => Ok(r.map(|res| RecognitionResult {
+                        audio_duration,
+                        ..res
+                    })),
+                }
+            }
+        };
+        let res = retry::retry(&self.retry, call).await?;
+        let vad = vad.map(|stats| *stats.lock().unwrap());
+        Ok(RecognitionResult {
+            attempts: res.attempts,
+            trimmed: vad.map(|stats| stats.trimmed()).unwrap_or_default(),
+            speech_ratio: vad.and_then(|stats| stats.speech_ratio()),
+            ..res.value.with_latency(started.elapsed())
+        })
+    }
+}
+
+fn service(channel: Channel, gzip: bool) -> SpeechRecognizerClient<Channel> {
+    let client = SpeechRecognizerClient::new(channel);
+    match gzip {
+        true => client.send_gzip().accept_gzip(),
+        false => client,
+    }
+}
+
+#[async_trait]
+impl RecognizeStream for Remote {
+    async fn recognize_stream(
+        &mut self,
+        requests: impl RequestStream,
+    ) -> CallResult<RecognitionResponse> {
+        let mut request = Request::new(requests);
+        credentials::authorize(&*self.credentials, &mut request).await?;
+        let r = service(self.lease.channel(), self.gzip)
+            .recognize_stream(request)
+            .await;
+        self.lease.report(&r);
+        Ok(r)
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::testkit::{audio_digest, Match, MockServer, Reply};
+    use crate::RpcError;
+    use std::time::Duration;
+    use tonic::Code;
+
+    async fn client(server: &MockServer, token: &str) -> Client {
+        let config = server.config().retry_policy(RetryPolicy::default().backoff(
+            Duration::from_millis(1),
+            Duration::from_millis(1),
+            1.0,
+        ));
+        Client::with_config(config, Arc::new(StaticToken::new(token)))
+            .await
+            .expect("Should connect to the mock server")
+    }
 
     #[tokio::test]
     async fn test_empty_url() {
         let error = Client::new("", "")
             .await
             .expect_err("Should not be able to create a client");
-        assert!(matches!(error, SpeechCenterError::Unknown(_)));
+        assert!(matches!(error, SpeechCenterError::Config(_)));
     }
 
     #[tokio::test]
@@ -177,6 +366,208 @@ mod test {
         let error = Client::new("http://127.0.0.1:9999", "")
             .await
             .expect_err("Should not be able to connect anywhere");
-        assert!(matches!(error, SpeechCenterError::Connection(_)));
+        assert!(matches!(error, SpeechCenterError::Connection { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_recognise() {
+        let audio = (0..8000u32)
+            .flat_map(|i| (i as i16).to_le_bytes())
+            .collect::<Vec<_>>();
+        let server = MockServer::builder()
+            .token("token")
+            .respond(
+                Match::Audio(audio_digest(&audio)),
+                Reply::Transcript("hello".to_string()),
+            )
+            .respond(
+                Match::Grammar("yes | no".to_string()),
+                Reply::Transcript("yes".to_string()),
+            )
+            .start()
+            .await
+            .unwrap();
+        let client = client(&server, "token").await;
+
+        let res = client
+            .recognise_with_topic("en-US", Topic::Generic, audio.clone())
+            .await
+            .expect("Should recognise the audio");
+        assert_eq!(res.transcript, "hello");
+        assert_eq!(res.audio_duration, Duration::from_secs(1));
+        assert!(res.real_time_factor.is_some_and(|rtf| rtf > 0.0));
+        assert_eq!(res.attempts, 1);
+        assert_eq!(res.endpoint, Some(server.url()));
+        assert_eq!(res.metadata["x-mock-call"], "1");
+        let res = client
+            .recognise_with_grammar("yes | no", "en-US", vec![0; 160])
+            .await
+            .expect("Should recognise with the grammar");
+        assert_eq!(res.transcript, "yes");
+        let error = client
+            .recognise_with_topic("en-US", Topic::Generic, vec![0; 160])
+            .await
+            .expect_err("Should not recognise unknown audio");
+        assert_eq!(error.code(), Some(Code::NotFound));
+    }
+
+    #[tokio::test]
+    async fn test_unauthenticated() {
+        let server = MockServer::builder()
+            .token("token")
+            .respond(Match::Any, Reply::Transcript("hello".to_string()))
+            .start()
+            .await
+            .unwrap();
+        let error = client(&server, "expired")
+            .await
+            .recognise_with_topic("en-US", Topic::Generic, vec![0; 160])
+            .await
+            .expect_err("Should reject the token");
+        assert!(matches!(error, SpeechCenterError::Unauthenticated(_)));
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_language() {
+        let server = MockServer::builder()
+            .token("token")
+            .respond(Match::Any, Reply::Transcript("hola".to_string()))
+            .start()
+            .await
+            .unwrap();
+        let client = client(&server, "token").await;
+        let res = client
+            .recognise_with_topic("es_es", Topic::Generic, vec![0; 160])
+            .await
+            .expect("Should normalise the language");
+        assert_eq!(res.transcript, "hola");
+        let error = client
+            .recognise_with_grammar("si | no", "ca-ES", vec![0; 160])
+            .await
+            .expect_err("Should reject the language");
+        assert!(matches!(error, SpeechCenterError::InvalidParameter(_)));
+        assert_eq!(server.calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_vad() {
+        let audio = crate::vad::test::noise_tone_noise([1000, 1000, 1000], 0.0, 0.3);
+        let vad = Vad::default().padding(Duration::ZERO);
+        let (trimmed, _) = vad.trim_bytes(&audio);
+        let server = MockServer::builder()
+            .respond(
+                Match::Audio(audio_digest(&trimmed)),
+                Reply::Transcript("hello".to_string()),
+            )
+            .start()
+            .await
+            .unwrap();
+        let client = client(&server, "").await.with_vad(Some(vad));
+        let res = client
+            .recognise_with_topic("en-US", Topic::Generic, audio)
+            .await
+            .expect("Should send the trimmed audio");
+        assert_eq!(res.transcript, "hello");
+        assert_eq!(
+            res.audio_duration,
+            AudioSpec::RECOGNITION.duration(trimmed.len())
+        );
+        assert_eq!(res.audio_duration + res.trimmed, Duration::from_secs(3));
+        assert!(res.speech_ratio.is_some_and(|ratio| ratio < 0.5));
+    }
+
+    #[tokio::test]
+    async fn test_retry_replays_audio() {
+        let audio = vec![1u8; 8000];
+        let server = MockServer::builder()
+            .respond(
+                Match::Audio(audio_digest(&audio)),
+                Reply::Transcript("hello".to_string()),
+            )
+            .fail(2, Code::Unavailable, "Overloaded")
+            .start()
+            .await
+            .unwrap();
+        let audio = AudioStream::from_bytes(audio, ChunkSize::Bytes(1000));
+        let res = client(&server, "")
+            .await
+            .recognise_stream_with_topic("en-US", Topic::Generic, audio)
+            .await
+            .expect("Should succeed on the third attempt");
+        assert_eq!(res.attempts, 3);
+        assert_eq!(res.transcript, "hello");
+        assert_eq!(server.calls(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_no_retry_past_replay_limit() {
+        let server = MockServer::builder()
+            .respond(Match::Any, Reply::Transcript("hello".to_string()))
+            .fail(1, Code::Unavailable, "Overloaded")
+            .start()
+            .await
+            .unwrap();
+        let config = server.config().retry_policy(
+            RetryPolicy::default()
+                .backoff(Duration::from_millis(1), Duration::from_millis(1), 1.0)
+                .max_replay_bytes(4000),
+        );
+        let client = Client::with_config(config, Arc::new(StaticToken::new("")))
+            .await
+            .unwrap();
+        let audio = AudioStream::from_bytes(vec![1u8; 8000], ChunkSize::Bytes(1000));
+        let error = client
+            .recognise_stream_with_topic("en-US", Topic::Generic, audio)
+            .await
+            .expect_err("Should not retry audio that was not kept");
+        assert!(matches!(error, SpeechCenterError::Unavailable(_)));
+        assert_eq!(error.rpc().map(RpcError::attempts), Some(1));
+        assert_eq!(server.calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_cassette() {
+        let path = std::env::temp_dir().join(format!("recognition-{}.jsonl", std::process::id()));
+        let audio = vec![1u8; 8000];
+        let server = MockServer::builder()
+            .respond(
+                Match::Audio(audio_digest(&audio)),
+                Reply::Transcript("hello".to_string()),
+            )
+            .start()
+            .await
+            .unwrap();
+        let cassette = Arc::new(Cassette::record(&path).unwrap());
+        let client = Client::with_config(
+            server.config().cassette(cassette),
+            Arc::new(StaticToken::new("")),
+        )
+        .await
+        .unwrap();
+        let chunks = AudioStream::from_bytes(audio.clone(), ChunkSize::Bytes(1000));
+        client
+            .recognise_stream_with_topic("en-US", Topic::Generic, chunks)
+            .await
+            .expect("Should recognise the audio");
+        drop((client, server));
+
+        let cassette = Arc::new(Cassette::replay(&path).unwrap());
+        let config = SpeechCenterConfig::new("http://127.0.0.1:9999")
+            .plaintext(true)
+            .cassette(cassette);
+        let client = Client::with_config(config, Arc::new(StaticToken::new("")))
+            .await
+            .expect("Should not connect when replaying");
+        let res = client
+            .recognise_with_topic("en-US", Topic::Generic, audio)
+            .await
+            .expect("Should replay the recognition");
+        assert_eq!(res.transcript, "hello");
+        let error = client
+            .recognise_with_topic("en-US", Topic::Generic, vec![0; 160])
+            .await
+            .expect_err("Should not replay unrecorded audio");
+        assert_eq!(error.code(), Some(Code::NotFound));
+        std::fs::remove_file(&path).unwrap();
     }
 }
@@ -0,0 +1,381 @@
+use crate::synthesizer_client::{AudioFormat, SampleRate, Speaker};
+use crate::wav::{self, WavEncoding, WavSpec};
+use crate::{g711, AudioSpec, SampleFormat};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::time::Duration;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tonic::metadata::{KeyAndValueRef, MetadataMap};
+
+/// Transcript of a recognition along with how it went.
+///
+/// Durations are serialized as fractional seconds.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RecognitionResult {
+    pub transcript: String,
+    /// Playing time of the audio sent to the recognizer
+    #[serde(with = "seconds")]
+    pub audio_duration: Duration,
+    /// Playing time of the silence trimmed before sending the audio
+    #[serde(default, with = "seconds")]
+    pub trimmed: Duration,
+    /// Time from the start of the call to the transcript, including retries
+    #[serde(with = "seconds")]
+    pub latency: Duration,
+    /// Latency over audio duration, none without audio
+    pub real_time_factor: Option<f64>,
+    /// Fraction of the audio taken for speech, none without voice activity detection
+    #[serde(default)]
+    pub speech_ratio: Option<f64>,
+    /// Number of attempts made, 0 for cached results
+    pub attempts: u32,
+    /// URL of the endpoint that answered, none when it did not come from the service
+    pub endpoint: Option<String>,
+    /// Headers and trailers of the response, binary ones base64 encoded
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl RecognitionResult {
+    pub fn new(transcript: impl Into<String>, audio_duration: Duration, latency: Duration) -> Self {
+        Self {
+            transcript: transcript.into(),
+            audio_duration,
+            trimmed: Duration::ZERO,
+            latency,
+            real_time_factor: real_time_factor(latency, audio_duration),
+            speech_ratio: None,
+            attempts: 1,
+            endpoint: None,
+            metadata: BTreeMap::new(),
+        }
+    }
+
+    /// Replaces the latency, updating the real-time factor.
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self.real_time_factor = real_time_factor(latency, self.audio_duration);
+        self
+    }
+
+    pub fn into_transcript(self) -> String {
+        self.transcript
+    }
+}
+
+/// Transcript of long audio recognised an utterance at a time.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Transcript {
+    /// Transcripts of the utterances, separated by spaces
+    pub transcript: String,
+    #[serde(with = "seconds")]
+    pub audio_duration: Duration,
+    /// Time from reading the audio to the last transcript
+    #[serde(with = "seconds")]
+    pub latency: Duration,
+    pub real_time_factor: Option<f64>,
+    /// Fraction of the audio taken for speech, none without audio
+    pub speech_ratio: Option<f64>,
+    pub segments: Vec<TranscriptSegment>,
+}
+
+impl Transcript {
+    pub fn new(
+        segments: Vec<TranscriptSegment>,
+        audio_duration: Duration,
+        speech_ratio: Option<f64>,
+        latency: Duration,
+    ) -> Self {
+        let transcript = segments
+            .iter()
+            .map(|segment| segment.result.transcript.trim())
+            .filter(|transcript| !transcript.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ");
+        Self {
+            transcript,
+            audio_duration,
+            latency,
+            real_time_factor: real_time_factor(latency, audio_duration),
+            speech_ratio,
+            segments,
+        }
+    }
+}
+
+/// Recognition of an utterance, with its offsets in the audio.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TranscriptSegment {
+    #[serde(with = "seconds")]
+    pub start: Duration,
+    #[serde(with = "seconds")]
+    pub end: Duration,
+    #[serde(flatten)]
+    pub result: RecognitionResult,
+}
+
+/// Synthesized audio along with what it was synthesized from.
+///
+/// The audio is kept without header, so that it can be written as WAV or raw whatever the
+/// header requested.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SynthesisResult {
+    pub speaker: Speaker,
+    pub text: String,
+    pub sample_rate: SampleRate,
+    /// Format requested, the one of [`SynthesisResult::audio`]
+    pub audio_format: AudioFormat,
+    /// Number of attempts made, 0 for cached results
+    pub attempts: u32,
+    data: Bytes,
+}
+
+impl SynthesisResult {
+    /// Result of a single attempt holding `data`, samples in the encoding of the format.
+    pub fn new(
+        speaker: Speaker,
+        sample_rate: SampleRate,
+        audio_format: AudioFormat,
+        text: impl Into<String>,
+        data: impl Into<Bytes>,
+    ) -> Self {
+        Self {
+            speaker,
+            text: text.into(),
+            sample_rate,
+            audio_format,
+            attempts: 1,
+            data: data.into(),
+        }
+    }
+
+    pub fn spec(&self) -> AudioSpec {
+        AudioSpec {
+            sample_rate: self.sample_rate.clone().into(),
+            channels: 1,
+            format: self.audio_format.sample_format(),
+        }
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.spec().duration(self.data.len())
+    }
+
+    /// Audio in the requested format, in a WAV file if it has a header.
+    pub fn audio(&self) -> Bytes {
+        match self.audio_format.has_header() {
+            true => self.to_wav(),
+            false => self.raw(),
+        }
+    }
+
+    /// Samples without header.
+    pub fn raw(&self) -> Bytes {
+        self.data.clone()
+    }
+
+    pub fn to_wav(&self) -> Bytes {
+        let mut wav = self.wav_header();
+        wav.extend_from_slice(&self.data);
+        Bytes::from(wav)
+    }
+
+    /// Signed 16-bit samples, expanding G.711 audio.
+    pub fn pcm(&self) -> Vec<i16> {
+        match self.audio_format.sample_format() {
+            SampleFormat::MuLaw => self
+                .data
+                .iter()
+                .map(|s| g711::mulaw_to_linear(*s))
+                .collect(),
+            SampleFormat::ALaw => self.data.iter().map(|s| g711::alaw_to_linear(*s)).collect(),
+            _ => self
+                .data
+                .chunks_exact(2)
+                .map(|s| i16::from_le_bytes([s[0], s[1]]))
+                .collect(),
+        }
+    }
+
+    pub fn write_wav<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&self.wav_header())?;
+        writer.write_all(&self.data)?;
+        writer.flush()
+    }
+
+    pub fn write_raw<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&self.data)?;
+        writer.flush()
+    }
+
+    pub async fn write_wav_async<W: AsyncWrite + Unpin>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&self.wav_header()).await?;
+        writer.write_all(&self.data).await?;
+        writer.flush().await
+    }
+
+    pub async fn write_raw_async<W: AsyncWrite + Unpin>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&self.data).await?;
+        writer.flush().await
+    }
+
+    fn wav_header(&self) -> Vec<u8> {
+        let format = self.audio_format.sample_format();
+        let spec = WavSpec {
+            encoding: match format {
+                SampleFormat::MuLaw => WavEncoding::MuLaw,
+                SampleFormat::ALaw => WavEncoding::ALaw,
+                _ => WavEncoding::Pcm,
+            },
+            channels: 1,
+            sample_rate: self.sample_rate.clone().into(),
+            bits_per_sample: (format.width() * 8) as u16,
+        };
+        wav::header(spec, u32::try_from(self.data.len()).unwrap_or(u32::MAX))
+    }
+}
+
+fn real_time_factor(latency: Duration, audio_duration: Duration) -> Option<f64> {
+    match audio_duration.is_zero() {
+        true => None,
+        false => Some(latency.as_secs_f64() / audio_duration.as_secs_f64()),
+    }
+}
+
+/// Metadata of a response as text.
+pub(crate) fn metadata(metadata: &MetadataMap) -> BTreeMap<String, String> {
+    metadata
+        .iter()
+        .filter_map(|entry| match entry {
+            KeyAndValueRef::Ascii(key, value) => {
+                Some((key.to_string(), value.to_str().ok()?.to_string()))
+            }
+            KeyAndValueRef::Binary(key, value) => {
+                Some((key.to_string(), base64::encode(value.to_bytes().ok()?)))
+            }
+        })
+        .collect()
+}
+
+mod seconds {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(duration.as_secs_f64())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let seconds = f64::deserialize(deserializer)?;
+        Duration::try_from_secs_f64(seconds).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_serialize() {
+        let mut result =
+            RecognitionResult::new("hello", Duration::from_secs(2), Duration::from_millis(500));
+        assert_eq!(result.real_time_factor, Some(0.25));
+        result.endpoint = Some("https://csr.api.speechcenter.verbio.com".to_string());
+        result
+            .metadata
+            .insert("x-request-id".to_string(), "1234".to_string());
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["audio_duration"], 2.0);
+        assert_eq!(json["latency"], 0.5);
+        assert_eq!(json["metadata"]["x-request-id"], "1234");
+        let parsed: RecognitionResult = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, result);
+
+        let silent = RecognitionResult::new("", Duration::ZERO, Duration::from_millis(10));
+        assert_eq!(silent.real_time_factor, None);
+        assert_eq!(
+            silent.with_latency(Duration::from_millis(20)).latency,
+            Duration::from_millis(20)
+        );
+    }
+
+    #[test]
+    fn test_transcript() {
+        let segment = |start, end, text| TranscriptSegment {
+            start: Duration::from_millis(start),
+            end: Duration::from_millis(end),
+            result: RecognitionResult::new(text, Duration::from_secs(1), Duration::ZERO),
+        };
+        let transcript = Transcript::new(
+            vec![
+                segment(0, 1500, "hello "),
+                segment(2000, 2500, ""),
+                segment(3000, 4000, "world"),
+            ],
+            Duration::from_secs(5),
+            Some(0.5),
+            Duration::from_secs(1),
+        );
+        assert_eq!(transcript.transcript, "hello world");
+        assert_eq!(transcript.real_time_factor, Some(0.2));
+        let json = serde_json::to_value(&transcript).unwrap();
+        assert_eq!(json["segments"][0]["start"], 0.0);
+        assert_eq!(json["segments"][0]["end"], 1.5);
+        assert_eq!(json["segments"][2]["transcript"], "world");
+        let parsed: Transcript = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, transcript);
+    }
+
+    #[tokio::test]
+    async fn test_synthesis_result() {
+        let pcm = SynthesisResult::new(
+            Speaker::EnUsTommy,
+            SampleRate::Khz8,
+            AudioFormat::RawLpcmS16le,
+            "hello",
+            vec![1, 0, 0xFF, 0xFF],
+        );
+        assert_eq!(pcm.pcm(), [1, -1]);
+        assert_eq!(pcm.duration(), Duration::from_micros(250));
+        assert_eq!(pcm.audio(), pcm.raw());
+        let mut wav = vec![];
+        pcm.write_wav(&mut wav).unwrap();
+        assert_eq!(wav, pcm.to_wav());
+        let mut reader = &wav[..];
+        let header = wav::read_header(&mut reader).await.unwrap();
+        assert_eq!(header.spec.bits_per_sample, 16);
+        assert_eq!(header.data_len, Some(4));
+        assert_eq!(reader, &[1, 0, 0xFF, 0xFF]);
+
+        let alaw = SynthesisResult::new(
+            Speaker::EsEsDavid,
+            SampleRate::Khz8,
+            AudioFormat::WavAlaw,
+            "hola",
+            vec![0xD5],
+        );
+        assert_eq!(alaw.pcm(), [8]);
+        assert_eq!(alaw.audio(), alaw.to_wav());
+        let mut raw = vec![];
+        alaw.write_raw_async(&mut raw).await.unwrap();
+        assert_eq!(raw, [0xD5]);
+        let mut wav = vec![];
+        alaw.write_wav_async(&mut wav).await.unwrap();
+        let header = wav::read_header(&mut &wav[..]).await.unwrap();
+        assert_eq!(header.spec.encoding, WavEncoding::ALaw);
+    }
+
+    #[test]
+    fn test_metadata() {
+        let mut map = MetadataMap::new();
+        map.insert("x-request-id", "1234".parse().unwrap());
+        map.insert_bin(
+            "trace-bin",
+            tonic::metadata::MetadataValue::from_bytes(b"\x01\x02"),
+        );
+        let metadata = metadata(&map);
+        assert_eq!(metadata["x-request-id"], "1234");
+        assert_eq!(metadata["trace-bin"], "AQI=");
+    }
+}
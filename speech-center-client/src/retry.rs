@@ -0,0 +1,279 @@
+use crate::{Result, SpeechCenterError};
+use rand::Rng;
+use std::future::Future;
+use std::time::Duration;
+use tokio::time::Instant;
+use tonic::{Code, Status};
+
+/// Controls how failed recognitions and syntheses are retried.
+///
+/// Only calls failing with one of the retryable codes are retried, waiting an exponentially
+/// growing, jittered backoff between attempts.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    pub(crate) max_attempts: u32,
+    pub(crate) initial_backoff: Duration,
+    pub(crate) max_backoff: Duration,
+    pub(crate) multiplier: f64,
+    pub(crate) jitter: f64,
+    pub(crate) deadline: Option<Duration>,
+    pub(crate) retryable_codes: Vec<Code>,
+    pub(crate) max_replay_bytes: usize,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            multiplier: 2.0,
+            jitter: 0.5,
+            deadline: None,
+            retryable_codes: vec![
+                Code::Unavailable,
+                Code::DeadlineExceeded,
+                Code::ResourceExhausted,
+                Code::Aborted,
+            ],
+            max_replay_bytes: 1 << 20,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Policy making a single attempt per call.
+    pub fn none() -> Self {
+        Self::default().max_attempts(1)
+    }
+
+    /// Total number of attempts, including the first one.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Backoff before the first retry, multiplied by `multiplier` after every retry.
+    pub fn backoff(mut self, initial: Duration, max: Duration, multiplier: f64) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+
+    /// Fraction of every backoff that is randomised, from 0 (none) to 1 (full jitter).
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Time limit for a call including all of its attempts and backoffs.
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    pub fn retryable_codes(mut self, codes: impl IntoIterator<Item = Code>) -> Self {
+        self.retryable_codes = codes.into_iter().collect();
+        self
+    }
+
+    /// Streamed audio read ahead and kept in memory to send it again on retries, 1MiB by default.
+    ///
+    /// Recognitions of longer audio are sent once and never retried, so long files and live streams
+    /// are not held in memory. The first attempt starts once the limit is read or the audio ends.
+    pub fn max_replay_bytes(mut self, max_replay_bytes: usize) -> Self {
+        self.max_replay_bytes = max_replay_bytes;
+        self
+    }
+
+    pub fn is_retryable(&self, code: Code) -> bool {
+        self.retryable_codes.contains(&code)
+    }
+
+    /// Audio to keep for retries, none if the calls are not retried.
+    pub(crate) fn replay_limit(&self) -> usize {
+        match self.max_attempts {
+            1 => 0,
+            _ => self.max_replay_bytes,
+        }
+    }
+
+    /// Backoff to wait after the given failed attempt, starting at 1.
+    fn backoff_after(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let backoff = self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent);
+        let backoff = backoff.min(self.max_backoff.as_secs_f64());
+        let jitter = match self.jitter {
+            j if j > 0.0 => rand::thread_rng().gen_range(0.0..j),
+            _ => 0.0,
+        };
+        Duration::try_from_secs_f64(backoff * (1.0 - jitter)).unwrap_or(self.max_backoff)
+    }
+}
+
+/// Value returned by a call along with the number of attempts it took.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Retried<T> {
+    pub value: T,
+    pub attempts: u32,
+}
+
+impl<T> Retried<T> {
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+/// Runs `call` until it succeeds, fails with a non retryable status or the policy gives up.
+///
+/// Errors returned by `call` itself are never retried, only its `Status`es.
+pub(crate) async fn retry<T, F, Fut>(policy: &RetryPolicy, mut call: F) -> Result<Retried<T>>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<Result<T, Status>>>,
+{
+    // Deadlines too far away to be represented never expire
+    let deadline = policy
+        .deadline
+        .and_then(|deadline| Instant::now().checked_add(deadline));
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let result = match deadline {
+            Some(deadline) => match tokio::time::timeout_at(deadline, call()).await {
+                Ok(result) => result?,
+                Err(_) => Err(Status::deadline_exceeded("Retry deadline exceeded")),
+            },
+            None => call().await?,
+        };
+        let status = match result {
+            Ok(value) => return Ok(Retried { value, attempts }),
+            Err(status) => status,
+        };
+
+        if attempts >= policy.max_attempts || !policy.is_retryable(status.code()) {
+            return Err(SpeechCenterError::from_status(status, attempts));
+        }
+        let backoff = policy.backoff_after(attempts);
+        let retry_at = Instant::now().checked_add(backoff);
+        if deadline.is_some_and(|deadline| retry_at.is_none_or(|at| at >= deadline)) {
+            return Err(SpeechCenterError::from_status(status, attempts));
+        }
+        warn!(
+            "Attempt {} failed with [{}] {}, retrying in {:?}",
+            attempts,
+            status.code(),
+            status.message(),
+            backoff
+        );
+        tokio::time::sleep(backoff).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy::default().max_attempts(4).backoff(
+            Duration::from_millis(1),
+            Duration::from_millis(2),
+            2.0,
+        )
+    }
+
+    async fn failing(codes: &[Code], policy: &RetryPolicy) -> (Result<Retried<&'static str>>, u32) {
+        let calls = AtomicU32::new(0);
+        let result = retry(policy, || async {
+            let call = calls.fetch_add(1, Ordering::SeqCst) as usize;
+            Ok(match codes.get(call) {
+                Some(code) => Err(Status::new(*code, "failed")),
+                None => Ok("done"),
+            })
+        })
+        .await;
+        (result, calls.load(Ordering::SeqCst))
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy::default()
+            .backoff(Duration::from_millis(100), Duration::from_millis(300), 2.0)
+            .jitter(0.0);
+        assert_eq!(policy.backoff_after(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff_after(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff_after(3), Duration::from_millis(300));
+        let policy = policy.jitter(0.5);
+        for attempt in 1..10 {
+            let backoff = policy.backoff_after(attempt);
+            assert!(backoff > Duration::from_millis(50) && backoff <= Duration::from_millis(300));
+        }
+    }
+
+    #[test]
+    fn test_unbounded_backoff() {
+        let policy = RetryPolicy::default()
+            .backoff(Duration::from_secs(1), Duration::MAX, 10.0)
+            .jitter(0.0);
+        assert_eq!(policy.backoff_after(1000), Duration::MAX);
+    }
+
+    #[tokio::test]
+    async fn test_retries_transient_failures() {
+        let (result, calls) =
+            failing(&[Code::Unavailable, Code::ResourceExhausted], &policy()).await;
+        let result = result.expect("Should succeed on the third attempt");
+        assert_eq!(
+            result,
+            Retried {
+                value: "done",
+                attempts: 3
+            }
+        );
+        assert_eq!(calls, 3);
+    }
+
+    #[tokio::test]
+    async fn test_does_not_retry_permanent_failures() {
+        let (result, calls) = failing(&[Code::InvalidArgument], &policy()).await;
+        let error = result.expect_err("Should not retry invalid arguments");
+        assert!(matches!(error, SpeechCenterError::InvalidArgument(_)));
+        assert_eq!(error.rpc().map(|e| e.attempts()), Some(1));
+        assert_eq!(calls, 1);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up() {
+        let (result, calls) = failing(&[Code::Unavailable; 10], &policy()).await;
+        let error = result.expect_err("Should give up");
+        assert!(matches!(error, SpeechCenterError::Unavailable(_)));
+        assert_eq!(error.rpc().map(|e| e.attempts()), Some(4));
+        assert_eq!(calls, 4);
+
+        let (result, calls) = failing(&[Code::Unavailable; 10], &RetryPolicy::none()).await;
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+
+    #[tokio::test]
+    async fn test_deadline() {
+        let policy = policy()
+            .max_attempts(100)
+            .backoff(Duration::from_millis(20), Duration::from_millis(20), 1.0)
+            .jitter(0.0)
+            .deadline(Duration::from_millis(50));
+        let (result, calls) = failing(&[Code::Unavailable; 100], &policy).await;
+        assert!(result.is_err());
+        assert!((2..=3).contains(&calls), "Made {} calls", calls);
+    }
+
+    #[tokio::test]
+    async fn test_unbounded_deadline() {
+        let policy = policy().deadline(Duration::MAX);
+        let (result, calls) = failing(&[Code::Unavailable], &policy).await;
+        assert_eq!(result.unwrap().attempts, 2);
+        assert_eq!(calls, 2);
+    }
+}
@@ -0,0 +1,412 @@
+use crate::audio_stream::AudioStream;
+use crate::recognizer_client::Resource;
+use crate::result::{Transcript, TranscriptSegment};
+use crate::service::Recognizer;
+use crate::vad::{self, Detector, Vad, FRAME_BYTES, ONSET_FRAMES, VAD_FRAME};
+use crate::{AudioSpec, Result, SpeechCenterError};
+use bytes::Bytes;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, Semaphore};
+use tokio::task::JoinHandle;
+use tokio_stream::StreamExt;
+
+/// Utterance cut out of longer mono 8kHz PCM16 audio.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Segment {
+    /// Offset of the utterance in the audio
+    pub start: Duration,
+    pub end: Duration,
+    pub audio: Bytes,
+}
+
+/// Splits long audio at its pauses into utterances, recognised separately to tell when each one
+/// was said.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Segmenter {
+    vad: Vad,
+    pause: Duration,
+    max_segment: Duration,
+    concurrency: usize,
+}
+
+impl Default for Segmenter {
+    fn default() -> Self {
+        Self::new(Vad::default())
+    }
+}
+
+impl Segmenter {
+    /// Segmenter finding speech with `vad`, whose padding is kept around every utterance.
+    ///
+    /// Utterances are cut out of the speech already, so the recognizer given to `transcribe`
+    /// needs no VAD of its own to trim their silence.
+    pub fn new(vad: Vad) -> Self {
+        Self {
+            vad,
+            pause: Duration::from_millis(500),
+            max_segment: Duration::from_secs(30),
+            concurrency: 4,
+        }
+    }
+
+    /// Silence ending an utterance, 500ms by default.
+    pub fn pause(mut self, pause: Duration) -> Self {
+        self.pause = pause;
+        self
+    }
+
+    /// Longest utterance, cut even if there is no pause in it, 30s by default.
+    pub fn max_segment(mut self, max_segment: Duration) -> Self {
+        self.max_segment = max_segment;
+        self
+    }
+
+    /// Utterances recognised at the same time, 4 by default.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Utterances of audio in memory.
+    pub fn split(&self, pcm: &[u8]) -> Vec<Segment> {
+        let mut splitter = Splitter::new(self);
+        let mut segments = splitter.push(pcm);
+        segments.extend(splitter.finish());
+        segments
+    }
+
+    /// Recognises every utterance of the audio, converted to mono 8kHz PCM16 whatever its format.
+    ///
+    /// Utterances are recognised as soon as they are found, and the audio is read no further while
+    /// as many as the concurrency are being recognised. The first error fails the whole transcript
+    /// right away, cancelling the other recognitions and reading no more audio.
+    pub async fn transcribe(
+        &self,
+        recognizer: Arc<dyn Recognizer>,
+        language: &str,
+        resource: Resource,
+        audio: AudioStream,
+    ) -> Result<Transcript> {
+        let started = Instant::now();
+        let semaphore = Arc::new(Semaphore::new(self.concurrency));
+        let mut splitter = Splitter::new(self);
+        let mut audio = audio.convert()?;
+        let mut tasks = Tasks(vec![]);
+        // Failed recognitions report their error before releasing their permit
+        let (failed_tx, mut failed) = mpsc::unbounded_channel();
+        loop {
+            let (segments, done) = match audio.next().await {
+                Some(Ok(chunk)) => (splitter.push(&chunk), false),
+                Some(Err(e)) => return Err(SpeechCenterError::io("Error reading audio", e)),
+                None => (splitter.finish().into_iter().collect(), true),
+            };
+            for segment in segments {
+                let permit = semaphore
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .expect("The semaphore is never closed");
+                if let Ok(e) = failed.try_recv() {
+                    return Err(e);
+                }
+                let recognizer = recognizer.clone();
+                let language = language.to_string();
+                let resource = resource.clone();
+                let failed = failed_tx.clone();
+                tasks.0.push(tokio::spawn(async move {
+                    let res = recognizer
+                        .recognise(&language, resource, segment.audio.to_vec())
+                        .await;
+                    if let Err(e) = &res {
+                        let _ = failed.send(e.clone());
+                    }
+                    drop(permit);
+                    res.map(|result| TranscriptSegment {
+                        start: segment.start,
+                        end: segment.end,
+                        result,
+                    })
+                }));
+            }
+            if let Ok(e) = failed.try_recv() {
+                return Err(e);
+            }
+            if done {
+                break;
+            }
+        }
+
+        let mut segments = Vec::with_capacity(tasks.0.len());
+        for task in std::mem::take(&mut tasks.0) {
+            match task.await {
+                Ok(segment) => segments.push(segment?),
+                Err(e) => std::panic::resume_unwind(e.into_panic()),
+            }
+        }
+        Ok(Transcript::new(
+            segments,
+            splitter.duration(),
+            splitter.speech_ratio(),
+            started.elapsed(),
+        ))
+    }
+}
+
+/// Recognitions of the utterances, aborted if the transcript fails.
+struct Tasks(Vec<JoinHandle<Result<TranscriptSegment>>>);
+
+impl Drop for Tasks {
+    fn drop(&mut self) {
+        self.0.iter().for_each(|task| task.abort());
+    }
+}
+
+/// Utterance being cut.
+struct Current {
+    start: usize,
+    frames: Vec<Vec<u8>>,
+    /// Frames of silence at its end
+    silence: usize,
+}
+
+/// Cuts utterances out of the audio as it is read.
+struct Splitter {
+    detector: Detector,
+    padding: usize,
+    pause: usize,
+    max_frames: usize,
+    pending: Vec<u8>,
+    read: usize,
+    frame: usize,
+    speech_frames: usize,
+    /// Silence before the next utterance, up to the padding
+    leading: VecDeque<Vec<u8>>,
+    current: Option<Current>,
+}
+
+impl Splitter {
+    fn new(segmenter: &Segmenter) -> Self {
+        Self {
+            detector: Detector::new(segmenter.vad.aggressiveness()),
+            padding: segmenter.vad.padding_frames(),
+            pause: vad::frames(segmenter.pause).max(1),
+            max_frames: vad::frames(segmenter.max_segment).max(1),
+            pending: vec![],
+            read: 0,
+            frame: 0,
+            speech_frames: 0,
+            leading: VecDeque::new(),
+            current: None,
+        }
+    }
+
+    /// Utterances ended by the new data.
+    fn push(&mut self, data: &[u8]) -> Vec<Segment> {
+        self.read += data.len();
+        self.pending.extend_from_slice(data);
+        let mut segments = vec![];
+        while self.pending.len() >= FRAME_BYTES {
+            let frame = self.pending.drain(..FRAME_BYTES).collect::<Vec<_>>();
+            let speech = self.detector.is_speech(&vad::samples(&frame));
+            self.speech_frames += usize::from(speech);
+            self.frame += 1;
+            segments.extend(self.next_frame(frame, speech));
+        }
+        segments
+    }
+
+    fn next_frame(&mut self, frame: Vec<u8>, speech: bool) -> Option<Segment> {
+        let mut current = match self.current.take() {
+            Some(current) => current,
+            None if speech => {
+                let mut frames = self.leading.drain(..).collect::<Vec<_>>();
+                frames.push(frame);
+                self.current = Some(Current {
+                    start: self.frame - frames.len(),
+                    frames,
+                    silence: 0,
+                });
+                return None;
+            }
+            None => {
+                self.leading.push_back(frame);
+                // The padding is kept before the loud frames that started the speech
+                if self.leading.len() > self.padding + ONSET_FRAMES - 1 {
+                    self.leading.pop_front();
+                }
+                return None;
+            }
+        };
+        current.frames.push(frame);
+        current.silence = if speech { 0 } else { current.silence + 1 };
+        if current.silence >= self.pause {
+            Some(self.cut(current))
+        } else if current.frames.len() >= self.max_frames {
+            Some(segment(current.start, current.frames))
+        } else {
+            self.current = Some(current);
+            None
+        }
+    }
+
+    /// Ends the utterance, keeping the padding out of the silence after it.
+    fn cut(&mut self, mut current: Current) -> Segment {
+        let dropped = current.silence.saturating_sub(self.padding);
+        let silence = current.frames.split_off(current.frames.len() - dropped);
+        let skip = silence.len().saturating_sub(self.padding);
+        self.leading = silence.into_iter().skip(skip).collect();
+        segment(current.start, current.frames)
+    }
+
+    /// The last utterance, if the audio ends within it.
+    fn finish(&mut self) -> Option<Segment> {
+        let mut current = self.current.take()?;
+        // A partial frame at the end goes with the speech it follows
+        if current.silence == 0 && !self.pending.is_empty() {
+            current.frames.push(std::mem::take(&mut self.pending));
+        }
+        Some(self.cut(current))
+    }
+
+    fn duration(&self) -> Duration {
+        AudioSpec::RECOGNITION.duration(self.read)
+    }
+
+    fn speech_ratio(&self) -> Option<f64> {
+        match self.frame {
+            0 => None,
+            frames => Some(self.speech_frames as f64 / frames as f64),
+        }
+    }
+}
+
+fn segment(start: usize, frames: Vec<Vec<u8>>) -> Segment {
+    let audio = Bytes::from(frames.concat());
+    let start = VAD_FRAME * start as u32;
+    Segment {
+        start,
+        end: start + AudioSpec::RECOGNITION.duration(audio.len()),
+        audio,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::vad::test::noise_tone_noise;
+    use crate::{Aggressiveness, ChunkSize, FakeRecognizer, Topic};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Tones of the given lengths in milliseconds, separated by silences of the given lengths.
+    fn utterances(tones: &[usize], silences: &[usize]) -> Vec<u8> {
+        let mut audio = vec![];
+        for (tone, silence) in tones.iter().zip(silences) {
+            audio.extend(noise_tone_noise([0, *tone, *silence], 0.0, 0.3));
+        }
+        audio
+    }
+
+    fn times(segments: &[Segment]) -> Vec<(u128, u128)> {
+        segments
+            .iter()
+            .map(|s| (s.start.as_millis(), s.end.as_millis()))
+            .collect()
+    }
+
+    #[test]
+    fn test_split() {
+        let mut audio = vec![0; 8000];
+        audio.extend(utterances(&[1000, 500, 700], &[1000, 300, 1000]));
+        let vad = Vad::new(Aggressiveness::VeryHigh).padding(Duration::from_millis(100));
+        let segmenter = Segmenter::new(vad);
+        let segments = segmenter.split(&audio);
+        // Padding of 100ms around every utterance, which ends with a hangover of 80ms
+        assert_eq!(times(&segments), [(400, 1680), (2400, 4180)]);
+        assert_eq!(
+            segments[0].audio.len(),
+            AudioSpec::RECOGNITION.frame_width() * 10240
+        );
+
+        let segments = segmenter
+            .max_segment(Duration::from_millis(600))
+            .split(&audio);
+        assert_eq!(
+            times(&segments),
+            [
+                (400, 1000),
+                (1000, 1600),
+                (2400, 3000),
+                (3000, 3600),
+                (3600, 4200)
+            ]
+        );
+        assert!(Segmenter::default().split(&[0; 16000]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_transcribe() {
+        let audio = utterances(&[1000, 1000, 1000], &[1000, 1000, 1000]);
+        let segmenter = Segmenter::default().concurrency(2);
+        let recognizer = Arc::new(FakeRecognizer::new("hello"));
+        let stream = AudioStream::from_bytes(audio.clone(), ChunkSize::default());
+        let transcript = segmenter
+            .transcribe(
+                recognizer.clone(),
+                "en-US",
+                Resource::Topic(Topic::Generic),
+                stream,
+            )
+            .await
+            .expect("Should transcribe every utterance");
+        assert_eq!(transcript.transcript, "hello hello hello");
+        assert_eq!(transcript.audio_duration, Duration::from_secs(6));
+        assert_eq!(transcript.segments.len(), 3);
+        assert_eq!(transcript.segments[1].start, Duration::from_millis(1800));
+        assert!(transcript
+            .speech_ratio
+            .is_some_and(|ratio| (0.5..0.6).contains(&ratio)));
+        let requests = recognizer.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[1].audio, segmenter.split(&audio)[1].audio);
+
+        recognizer.fail(SpeechCenterError::InvalidParameter("Oops".to_string()));
+        let stream = AudioStream::from_bytes(audio, ChunkSize::default());
+        let error = segmenter
+            .transcribe(recognizer, "en-US", Resource::Topic(Topic::Generic), stream)
+            .await
+            .expect_err("Should fail with the first segment");
+        assert!(matches!(error, SpeechCenterError::InvalidParameter(_)));
+    }
+
+    #[tokio::test]
+    async fn test_transcribe_stops_reading() {
+        let audio = utterances(&[1000; 10], &[1000; 10]);
+        let chunks = audio.len() / 1600;
+        let read = Arc::new(AtomicUsize::new(0));
+        let counter = read.clone();
+        let source = async_stream::stream! {
+            for chunk in audio.chunks(1600) {
+                counter.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(1)).await;
+                yield Bytes::copy_from_slice(chunk);
+            }
+        };
+        let recognizer = Arc::new(FakeRecognizer::new("hello"));
+        recognizer.fail(SpeechCenterError::InvalidParameter("Oops".to_string()));
+        let error = Segmenter::default()
+            .transcribe(
+                recognizer.clone(),
+                "en-US",
+                Resource::Topic(Topic::Generic),
+                AudioStream::from_stream(source, ChunkSize::default()),
+            )
+            .await
+            .expect_err("Should fail with the first segment");
+        assert!(matches!(error, SpeechCenterError::InvalidParameter(_)));
+        // The failure is noticed right after the first utterance, long before the end of the audio
+        assert!(read.load(Ordering::SeqCst) < chunks / 2);
+        assert_eq!(recognizer.requests().len(), 1);
+    }
+}
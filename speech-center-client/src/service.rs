@@ -0,0 +1,548 @@
+//! Backend independent recognition and synthesis, with combinators and fakes.
+
+use crate::audio::AudioSpec;
+use crate::audio_stream::{AudioStream, ChunkSize};
+use crate::cassette::audio_digest;
+use crate::recognizer_client::{Client as RecognitionClient, Resource};
+use crate::result::{RecognitionResult, SynthesisResult};
+use crate::synthesizer_client::{AudioFormat, Client as SynthesisClient, SampleRate, Speaker};
+use crate::{Result, SpeechCenterError};
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+#[async_trait]
+pub trait Recognizer: fmt::Debug + Send + Sync {
+    async fn recognise_stream(
+        &self,
+        language: &str,
+        resource: Resource,
+        audio: AudioStream,
+    ) -> Result<RecognitionResult>;
+
+    async fn recognise(
+        &self,
+        language: &str,
+        resource: Resource,
+        audio: Vec<u8>,
+    ) -> Result<RecognitionResult> {
+        let audio = AudioStream::from_bytes(audio, ChunkSize::default());
+        self.recognise_stream(language, resource, audio).await
+    }
+}
+
+#[async_trait]
+pub trait Synthesizer: fmt::Debug + Send + Sync {
+    async fn synthesize(
+        &self,
+        speaker: Speaker,
+        sample_rate: SampleRate,
+        audio_format: AudioFormat,
+        text: &str,
+    ) -> Result<SynthesisResult>;
+}
+
+#[async_trait]
+impl Recognizer for RecognitionClient {
+    async fn recognise_stream(
+        &self,
+        language: &str,
+        resource: Resource,
+        audio: AudioStream,
+    ) -> Result<RecognitionResult> {
+        match resource {
+            Resource::Topic(topic) => {
+                self.recognise_stream_with_topic(language, topic, audio)
+                    .await
+            }
+            Resource::Grammar(grammar) => {
+                self.recognise_stream_with_grammar(&grammar, language, audio)
+                    .await
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Synthesizer for SynthesisClient {
+    async fn synthesize(
+        &self,
+        speaker: Speaker,
+        sample_rate: SampleRate,
+        audio_format: AudioFormat,
+        text: &str,
+    ) -> Result<SynthesisResult> {
+        SynthesisClient::synthesize(self, speaker, sample_rate, audio_format, text).await
+    }
+}
+
+#[async_trait]
+impl<T: Recognizer + ?Sized> Recognizer for Arc<T> {
+    async fn recognise_stream(
+        &self,
+        language: &str,
+        resource: Resource,
+        audio: AudioStream,
+    ) -> Result<RecognitionResult> {
+        (**self).recognise_stream(language, resource, audio).await
+    }
+}
+
+#[async_trait]
+impl<T: Synthesizer + ?Sized> Synthesizer for Arc<T> {
+    async fn synthesize(
+        &self,
+        speaker: Speaker,
+        sample_rate: SampleRate,
+        audio_format: AudioFormat,
+        text: &str,
+    ) -> Result<SynthesisResult> {
+        (**self)
+            .synthesize(speaker, sample_rate, audio_format, text)
+            .await
+    }
+}
+
+async fn buffered(audio: AudioStream) -> Result<(Bytes, AudioStream)> {
+    audio
+        .buffered()
+        .await
+        .map_err(|e| SpeechCenterError::io("Error reading audio", e))
+}
+
+/// Bounded map evicting the oldest entries first.
+#[derive(Debug)]
+struct Lru<K, V> {
+    capacity: usize,
+    entries: Mutex<(HashMap<K, V>, VecDeque<K>)>,
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> Lru<K, V> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new((HashMap::new(), VecDeque::new())),
+        }
+    }
+
+    fn get(&self, key: &K) -> Option<V> {
+        let mut entries = self.entries.lock().unwrap();
+        let (values, order) = &mut *entries;
+        let value = values.get(key).cloned()?;
+        order.retain(|k| k != key);
+        order.push_back(key.clone());
+        Some(value)
+    }
+
+    fn insert(&self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        let (values, order) = &mut *entries;
+        if values.insert(key.clone(), value).is_some() {
+            order.retain(|k| *k != key);
+        }
+        order.push_back(key);
+        while order.len() > self.capacity {
+            if let Some(oldest) = order.pop_front() {
+                values.remove(&oldest);
+            }
+        }
+    }
+}
+
+type RecognitionKey = (String, Resource, AudioSpec, String);
+type SynthesisKey = (Speaker, SampleRate, AudioFormat, String);
+
+/// Remembers the results of successful calls, answering repeated requests without the service.
+///
+/// Recognitions are matched by the digest of their audio, which is read into memory. Cached
+/// results report no attempts.
+#[derive(Debug)]
+pub struct Cached<T> {
+    inner: T,
+    recognitions: Lru<RecognitionKey, RecognitionResult>,
+    syntheses: Lru<SynthesisKey, SynthesisResult>,
+}
+
+impl<T> Cached<T> {
+    /// Keeps the results of up to `capacity` recognitions and as many syntheses.
+    pub fn new(inner: T, capacity: usize) -> Self {
+        Self {
+            inner,
+            recognitions: Lru::new(capacity),
+            syntheses: Lru::new(capacity),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+#[async_trait]
+impl<T: Recognizer> Recognizer for Cached<T> {
+    async fn recognise_stream(
+        &self,
+        language: &str,
+        resource: Resource,
+        audio: AudioStream,
+    ) -> Result<RecognitionResult> {
+        let started = Instant::now();
+        let spec = audio.spec();
+        let (bytes, audio) = buffered(audio).await?;
+        let key = (
+            language.to_string(),
+            resource.clone(),
+            spec,
+            audio_digest(&bytes),
+        );
+        if let Some(res) = self.recognitions.get(&key) {
+            return Ok(RecognitionResult {
+                attempts: 0,
+                endpoint: None,
+                metadata: Default::default(),
+                ..res.with_latency(started.elapsed())
+            });
+        }
+        let res = self
+            .inner
+            .recognise_stream(language, resource, audio)
+            .await?;
+        self.recognitions.insert(key, res.clone());
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl<T: Synthesizer> Synthesizer for Cached<T> {
+    async fn synthesize(
+        &self,
+        speaker: Speaker,
+        sample_rate: SampleRate,
+        audio_format: AudioFormat,
+        text: &str,
+    ) -> Result<SynthesisResult> {
+        let key = (
+            speaker.clone(),
+            sample_rate.clone(),
+            audio_format.clone(),
+            text.to_string(),
+        );
+        if let Some(mut res) = self.syntheses.get(&key) {
+            res.attempts = 0;
+            return Ok(res);
+        }
+        let res = self
+            .inner
+            .synthesize(speaker, sample_rate, audio_format, text)
+            .await?;
+        self.syntheses.insert(key, res.clone());
+        Ok(res)
+    }
+}
+
+/// Sends calls to the secondary backend when the primary one fails with a transient error.
+///
+/// Audio is read into memory so that it can be sent again. Attempts include the failed ones.
+#[derive(Debug)]
+pub struct Fallback<P, S> {
+    primary: P,
+    secondary: S,
+}
+
+impl<P, S> Fallback<P, S> {
+    pub fn new(primary: P, secondary: S) -> Self {
+        Self { primary, secondary }
+    }
+}
+
+fn failed_attempts(error: &SpeechCenterError) -> Option<u32> {
+    if !error.is_retryable() {
+        return None;
+    }
+    warn!(
+        "Primary backend failed, falling back to the secondary: {}",
+        error
+    );
+    Some(error.rpc().map_or(1, |e| e.attempts()))
+}
+
+#[async_trait]
+impl<P: Recognizer, S: Recognizer> Recognizer for Fallback<P, S> {
+    async fn recognise_stream(
+        &self,
+        language: &str,
+        resource: Resource,
+        audio: AudioStream,
+    ) -> Result<RecognitionResult> {
+        let started = Instant::now();
+        let (bytes, audio) = buffered(audio).await?;
+        let spec = audio.spec();
+        let error = match self
+            .primary
+            .recognise_stream(language, resource.clone(), audio)
+            .await
+        {
+            Ok(res) => return Ok(res),
+            Err(e) => e,
+        };
+        let failed = failed_attempts(&error).ok_or(error)?;
+        let audio = AudioStream::from_bytes(bytes, ChunkSize::default()).with_spec(spec);
+        let res = self
+            .secondary
+            .recognise_stream(language, resource, audio)
+            .await?;
+        Ok(RecognitionResult {
+            attempts: res.attempts + failed,
+            ..res.with_latency(started.elapsed())
+        })
+    }
+}
+
+#[async_trait]
+impl<P: Synthesizer, S: Synthesizer> Synthesizer for Fallback<P, S> {
+    async fn synthesize(
+        &self,
+        speaker: Speaker,
+        sample_rate: SampleRate,
+        audio_format: AudioFormat,
+        text: &str,
+    ) -> Result<SynthesisResult> {
+        let error = match self
+            .primary
+            .synthesize(
+                speaker.clone(),
+                sample_rate.clone(),
+                audio_format.clone(),
+                text,
+            )
+            .await
+        {
+            Ok(res) => return Ok(res),
+            Err(e) => e,
+        };
+        let failed = failed_attempts(&error).ok_or(error)?;
+        let mut res = self
+            .secondary
+            .synthesize(speaker, sample_rate, audio_format, text)
+            .await?;
+        res.attempts += failed;
+        Ok(res)
+    }
+}
+
+/// Recognition received by a [`FakeRecognizer`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct FakeRecognition {
+    pub language: String,
+    pub resource: Resource,
+    pub audio: Bytes,
+}
+
+/// Recognizer answering every call with the same transcript, for testing code built on it.
+#[derive(Debug, Default)]
+pub struct FakeRecognizer {
+    transcript: String,
+    failures: Mutex<VecDeque<SpeechCenterError>>,
+    requests: Mutex<Vec<FakeRecognition>>,
+}
+
+impl FakeRecognizer {
+    pub fn new(transcript: impl Into<String>) -> Self {
+        Self {
+            transcript: transcript.into(),
+            ..Self::default()
+        }
+    }
+
+    /// Fails the next call without a queued failure with `error`.
+    pub fn fail(&self, error: SpeechCenterError) {
+        self.failures.lock().unwrap().push_back(error);
+    }
+
+    /// Calls received so far, including failed ones.
+    pub fn requests(&self) -> Vec<FakeRecognition> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl Recognizer for FakeRecognizer {
+    async fn recognise_stream(
+        &self,
+        language: &str,
+        resource: Resource,
+        audio: AudioStream,
+    ) -> Result<RecognitionResult> {
+        let started = Instant::now();
+        let spec = audio.spec();
+        let (audio, _) = buffered(audio).await?;
+        let audio_duration = spec.duration(audio.len());
+        self.requests.lock().unwrap().push(FakeRecognition {
+            language: language.to_string(),
+            resource,
+            audio,
+        });
+        match self.failures.lock().unwrap().pop_front() {
+            Some(error) => Err(error),
+            None => Ok(RecognitionResult::new(
+                self.transcript.clone(),
+                audio_duration,
+                started.elapsed(),
+            )),
+        }
+    }
+}
+
+/// Synthesis received by a [`FakeSynthesizer`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct FakeSynthesis {
+    pub speaker: Speaker,
+    pub sample_rate: SampleRate,
+    pub audio_format: AudioFormat,
+    pub text: String,
+}
+
+/// Synthesizer answering every call with the same samples, for testing code built on it.
+#[derive(Debug, Default)]
+pub struct FakeSynthesizer {
+    audio: Bytes,
+    failures: Mutex<VecDeque<SpeechCenterError>>,
+    requests: Mutex<Vec<FakeSynthesis>>,
+}
+
+impl FakeSynthesizer {
+    pub fn new(audio: impl Into<Bytes>) -> Self {
+        Self {
+            audio: audio.into(),
+            ..Self::default()
+        }
+    }
+
+    /// Fails the next call without a queued failure with `error`.
+    pub fn fail(&self, error: SpeechCenterError) {
+        self.failures.lock().unwrap().push_back(error);
+    }
+
+    /// Calls received so far, including failed ones.
+    pub fn requests(&self) -> Vec<FakeSynthesis> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl Synthesizer for FakeSynthesizer {
+    async fn synthesize(
+        &self,
+        speaker: Speaker,
+        sample_rate: SampleRate,
+        audio_format: AudioFormat,
+        text: &str,
+    ) -> Result<SynthesisResult> {
+        self.requests.lock().unwrap().push(FakeSynthesis {
+            speaker: speaker.clone(),
+            sample_rate: sample_rate.clone(),
+            audio_format: audio_format.clone(),
+            text: text.to_string(),
+        });
+        match self.failures.lock().unwrap().pop_front() {
+            Some(error) => Err(error),
+            None => Ok(SynthesisResult::new(
+                speaker,
+                sample_rate,
+                audio_format,
+                text,
+                self.audio.clone(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Topic;
+    use tonic::Status;
+
+    fn unavailable() -> SpeechCenterError {
+        SpeechCenterError::from_status(Status::unavailable("Overloaded"), 2)
+    }
+
+    #[tokio::test]
+    async fn test_cached() {
+        let fake = Arc::new(FakeRecognizer::new("hello"));
+        let cached = Cached::new(fake.clone(), 1);
+        let generic = Resource::Topic(Topic::Generic);
+        for _ in 0..2 {
+            let res = cached
+                .recognise("en-US", generic.clone(), vec![1; 160])
+                .await
+                .unwrap();
+            assert_eq!(res.transcript, "hello");
+        }
+        assert_eq!(fake.requests().len(), 1);
+
+        cached
+            .recognise("en-US", generic.clone(), vec![2; 160])
+            .await
+            .unwrap();
+        let res = cached
+            .recognise("en-US", generic, vec![1; 160])
+            .await
+            .unwrap();
+        assert_eq!(res.attempts, 1, "Should have been evicted");
+        assert_eq!(fake.requests().len(), 3);
+
+        let fake = FakeSynthesizer::new(vec![0u8; 16]);
+        fake.fail(unavailable());
+        let cached = Cached::new(fake, 8);
+        let synthesize = || {
+            cached.synthesize(
+                Speaker::EnUsTommy,
+                SampleRate::Khz8,
+                AudioFormat::RawLpcmS16le,
+                "Hello",
+            )
+        };
+        assert!(synthesize().await.is_err(), "Should not cache errors");
+        assert_eq!(synthesize().await.unwrap().attempts, 1);
+        assert_eq!(synthesize().await.unwrap().attempts, 0);
+    }
+
+    #[tokio::test]
+    async fn test_fallback() {
+        let primary = Arc::new(FakeRecognizer::new("primary"));
+        let secondary = Arc::new(FakeRecognizer::new("secondary"));
+        let fallback = Fallback::new(primary.clone(), secondary.clone());
+        let grammar = Resource::Grammar("yes | no".to_string());
+
+        let res = fallback
+            .recognise("en-US", grammar.clone(), vec![1; 160])
+            .await
+            .unwrap();
+        assert_eq!(res.transcript, "primary");
+
+        primary.fail(unavailable());
+        let res = fallback
+            .recognise("en-US", grammar.clone(), vec![1; 160])
+            .await
+            .unwrap();
+        assert_eq!(res.transcript, "secondary");
+        assert_eq!(res.attempts, 3);
+        assert_eq!(secondary.requests()[0].audio, Bytes::from(vec![1; 160]));
+
+        primary.fail(SpeechCenterError::InvalidParameter("Bad".to_string()));
+        let error = fallback
+            .recognise("en-US", grammar, vec![1; 160])
+            .await
+            .expect_err("Should not fall back on permanent errors");
+        assert!(matches!(error, SpeechCenterError::InvalidParameter(_)));
+        assert_eq!(secondary.requests().len(), 1);
+    }
+}
@@ -0,0 +1,215 @@
+use crate::config::SpeechCenterConfig;
+use crate::credentials::CredentialsProvider;
+use crate::{RecognitionClient, Result, SpeechCenterError, SynthesisClient, Vad};
+use std::sync::Arc;
+
+pub const RECOGNITION_URL: &str = "https://csr.api.speechcenter.verbio.com";
+pub const SYNTHESIS_URL: &str = "https://tts.api.speechcenter.verbio.com";
+
+/// Entry point to both services, sharing configuration and credentials.
+///
+/// Recognizers and synthesizers handed out are cheap clones over the same channels, so they can
+/// be used concurrently from as many tasks as needed.
+#[derive(Clone, Debug)]
+pub struct SpeechCenter {
+    recognizer: RecognitionClient,
+    synthesizer: SynthesisClient,
+}
+
+impl SpeechCenter {
+    pub fn builder(credentials: Arc<dyn CredentialsProvider>) -> SpeechCenterBuilder {
+        SpeechCenterBuilder {
+            config: SpeechCenterConfig::new(RECOGNITION_URL),
+            recognition_url: RECOGNITION_URL.to_string(),
+            synthesis_url: SYNTHESIS_URL.to_string(),
+            convert_audio: false,
+            vad: None,
+            credentials,
+        }
+    }
+
+    /// Connects to the public Speech Center hosts with the default configuration.
+    pub async fn connect(credentials: Arc<dyn CredentialsProvider>) -> Result<Self> {
+        Self::builder(credentials).connect().await
+    }
+
+    pub fn recognizer(&self) -> RecognitionClient {
+        self.recognizer.clone()
+    }
+
+    pub fn synthesizer(&self) -> SynthesisClient {
+        self.synthesizer.clone()
+    }
+}
+
+#[derive(Debug)]
+pub struct SpeechCenterBuilder {
+    config: SpeechCenterConfig,
+    recognition_url: String,
+    synthesis_url: String,
+    convert_audio: bool,
+    vad: Option<Vad>,
+    credentials: Arc<dyn CredentialsProvider>,
+}
+
+impl SpeechCenterBuilder {
+    /// Settings for both services. The url of the configuration is ignored in favour of the
+    /// per-service ones.
+    pub fn config(mut self, config: SpeechCenterConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn recognition_url(mut self, url: impl Into<String>) -> Self {
+        self.recognition_url = url.into();
+        self
+    }
+
+    pub fn synthesis_url(mut self, url: impl Into<String>) -> Self {
+        self.synthesis_url = url.into();
+        self
+    }
+
+    /// Serves both services from the same url, such as a gateway or a local server.
+    pub fn url(self, url: impl Into<String>) -> Self {
+        let url = url.into();
+        self.recognition_url(url.clone()).synthesis_url(url)
+    }
+
+    /// Converts audio in any supported format to mono 8kHz PCM16 before recognising it.
+    pub fn audio_conversion(mut self, enabled: bool) -> Self {
+        self.convert_audio = enabled;
+        self
+    }
+
+    /// Trims the silence around the speech before recognising it.
+    pub fn vad(mut self, vad: Vad) -> Self {
+        self.vad = Some(vad);
+        self
+    }
+
+    /// Connects to both services, reusing the same channels when they share the url.
+    ///
+    /// Failover URLs back the shared url up, so they are rejected when the services have their own.
+    pub async fn connect(self) -> Result<SpeechCenter> {
+        let mut config = self.config;
+        let shared = self.synthesis_url == self.recognition_url;
+        if !shared && !config.failover.is_empty() {
+            return Err(SpeechCenterError::Config(
+                "Failover URLs need both services at the same url".to_string(),
+            ));
+        }
+        config.url = self.recognition_url;
+        let recognition = config.channels().await?;
+        let recognizer = RecognitionClient::from_channels(
+            recognition.clone(),
+            config.clone(),
+            self.credentials.clone(),
+        )
+        .with_audio_conversion(self.convert_audio)
+        .with_vad(self.vad);
+
+        let synthesis = match shared {
+            true => recognition,
+            false => {
+                config.url = self.synthesis_url;
+                config.channels().await?
+            }
+        };
+        let synthesizer = SynthesisClient::from_channels(synthesis, config, self.credentials);
+        Ok(SpeechCenter {
+            recognizer,
+            synthesizer,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testkit::{audio_digest, Match, MockServer, Reply};
+    use crate::{AudioFormat, SampleRate, Speaker, StaticToken, Topic};
+    use tonic::Code;
+
+    #[tokio::test]
+    async fn test_shared_handles() {
+        let audio = vec![1u8; 1600];
+        let server = MockServer::builder()
+            .token("token")
+            .respond(
+                Match::Audio(audio_digest(&audio)),
+                Reply::Transcript("hello".to_string()),
+            )
+            .respond(Match::Text("hello".to_string()), Reply::Audio(vec![0; 16]))
+            .start()
+            .await
+            .unwrap();
+        let speech_center = SpeechCenter::builder(Arc::new(StaticToken::new("token")))
+            .config(server.config())
+            .url(server.url())
+            .connect()
+            .await
+            .expect("Should connect to the mock server");
+
+        let tasks = (0..4).map(|_| {
+            let recognizer = speech_center.recognizer();
+            let audio = audio.clone();
+            tokio::spawn(async move {
+                recognizer
+                    .recognise_with_topic("en-US", Topic::Generic, audio)
+                    .await
+            })
+        });
+        for task in tasks.collect::<Vec<_>>() {
+            let res = task.await.unwrap().expect("Should recognise the audio");
+            assert_eq!(res.transcript, "hello");
+        }
+        let audio = speech_center
+            .synthesizer()
+            .synthesize(
+                Speaker::EnUsTommy,
+                SampleRate::Khz8,
+                AudioFormat::RawLpcmS16le,
+                "hello",
+            )
+            .await
+            .expect("Should synthesize the text");
+        assert_eq!(audio.raw().len(), 16);
+        assert_eq!(server.calls(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_failover() {
+        let primary = MockServer::builder()
+            .respond(Match::Any, Reply::Transcript("primary".to_string()))
+            .start()
+            .await
+            .unwrap();
+        let backup = MockServer::builder()
+            .respond(Match::Any, Reply::Transcript("backup".to_string()))
+            .start()
+            .await
+            .unwrap();
+        let builder = || {
+            SpeechCenter::builder(Arc::new(StaticToken::new("")))
+                .config(primary.config().failover([backup.url()]))
+                .url(primary.url())
+        };
+        let speech_center = builder().connect().await.unwrap();
+        let recognizer = speech_center.recognizer();
+        assert_eq!(recognizer.endpoints().len(), 2);
+        primary.fail(1, Code::Unavailable, "Overloaded");
+        let res = recognizer
+            .recognise_with_topic("en-US", Topic::Generic, vec![0; 160])
+            .await
+            .unwrap();
+        assert_eq!(res.transcript, "backup");
+
+        let error = builder()
+            .synthesis_url(SYNTHESIS_URL)
+            .connect()
+            .await
+            .expect_err("Failover URLs should need a shared url");
+        assert!(matches!(error, SpeechCenterError::Config(_)));
+    }
+}
@@ -0,0 +1,367 @@
+use crate::result::{Transcript, TranscriptSegment};
+use crate::{Result, SpeechCenterError};
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SubtitleFormat {
+    Srt,
+    WebVtt,
+    /// One line per cue, prefixed by its start and end
+    Timestamped,
+}
+
+impl SubtitleFormat {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name.to_lowercase().as_str() {
+            "srt" => Ok(Self::Srt),
+            "vtt" | "webvtt" => Ok(Self::WebVtt),
+            "timestamped" => Ok(Self::Timestamped),
+            _ => Err(SpeechCenterError::InvalidParameter(format!(
+                "Unknown subtitle format: {}",
+                name
+            ))),
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Srt => "srt",
+            Self::WebVtt => "vtt",
+            Self::Timestamped => "txt",
+        }
+    }
+}
+
+/// What is written of a recognition.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum OutputFormat {
+    /// The transcript alone
+    Text,
+    /// The whole recognition
+    Json,
+    /// Subtitles of the utterances
+    Subtitles(SubtitleFormat),
+}
+
+impl FromStr for OutputFormat {
+    type Err = SpeechCenterError;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name.to_lowercase().as_str() {
+            "txt" | "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            name => SubtitleFormat::from_name(name)
+                .map(Self::Subtitles)
+                .map_err(|_| {
+                    SpeechCenterError::InvalidParameter(format!("Unknown output format: {}", name))
+                }),
+        }
+    }
+}
+
+impl OutputFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Text => "txt",
+            Self::Json => "json",
+            Self::Subtitles(format) => format.extension(),
+        }
+    }
+
+    /// Whether the format is made of the utterances of the audio, written from a segmented
+    /// recognition.
+    pub fn is_segmented(self) -> bool {
+        matches!(self, Self::Subtitles(_))
+    }
+}
+
+/// Caption shown from `start` to `end`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cue {
+    pub start: Duration,
+    pub end: Duration,
+    pub lines: Vec<String>,
+}
+
+/// Writes transcripts as subtitles, splitting the utterances into cues that fit on screen.
+///
+/// The service gives no timing within an utterance, so cues are timed in proportion to their
+/// length in characters.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Subtitles {
+    max_line_length: usize,
+    max_lines: usize,
+    max_duration: Duration,
+}
+
+impl Default for Subtitles {
+    fn default() -> Self {
+        Self {
+            max_line_length: 42,
+            max_lines: 2,
+            max_duration: Duration::from_secs(6),
+        }
+    }
+}
+
+impl Subtitles {
+    /// Characters per line, 42 by default. Longer words are left on a line of their own.
+    pub fn max_line_length(mut self, max_line_length: usize) -> Self {
+        self.max_line_length = max_line_length.max(1);
+        self
+    }
+
+    /// Lines per cue, 2 by default.
+    pub fn max_lines(mut self, max_lines: usize) -> Self {
+        self.max_lines = max_lines.max(1);
+        self
+    }
+
+    /// Longest a cue is shown, 6s by default. Single words are never split.
+    pub fn max_duration(mut self, max_duration: Duration) -> Self {
+        self.max_duration = max_duration;
+        self
+    }
+
+    pub fn cues(&self, transcript: &Transcript) -> Vec<Cue> {
+        transcript
+            .segments
+            .iter()
+            .flat_map(|segment| self.segment_cues(segment))
+            .collect()
+    }
+
+    fn segment_cues(&self, segment: &TranscriptSegment) -> Vec<Cue> {
+        let text = &segment.result.transcript;
+        let total = text
+            .split_whitespace()
+            .map(|w| w.chars().count() + 1)
+            .sum::<usize>();
+        let length = segment.end.saturating_sub(segment.start);
+        // Time at which the character at `offset` of the words joined by spaces is said
+        let at = |offset: usize| {
+            segment.start + length.mul_f64(offset as f64 / total.saturating_sub(1).max(1) as f64)
+        };
+
+        let mut cues = vec![];
+        let mut lines: Vec<String> = vec![];
+        let mut line = String::new();
+        let mut start = 0;
+        let mut offset = 0;
+        for word in text.split_whitespace() {
+            let end = offset + word.chars().count();
+            let too_long = !line.is_empty() && at(end) - at(start) > self.max_duration;
+            let fits = line.is_empty()
+                || line.chars().count() + 1 + word.chars().count() <= self.max_line_length;
+            if too_long || (!fits && lines.len() + 1 >= self.max_lines) {
+                lines.push(std::mem::take(&mut line));
+                cues.push(Cue {
+                    start: at(start),
+                    end: at(offset - 1),
+                    lines: std::mem::take(&mut lines),
+                });
+                start = offset;
+            } else if !fits {
+                lines.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+            offset = end + 1;
+        }
+        if !line.is_empty() {
+            lines.push(line);
+            cues.push(Cue {
+                start: at(start),
+                end: segment.end,
+                lines,
+            });
+        }
+        cues
+    }
+
+    /// The transcript in the format, as a string.
+    pub fn render(&self, format: SubtitleFormat, transcript: &Transcript) -> String {
+        let mut out = String::new();
+        if format == SubtitleFormat::WebVtt {
+            out.push_str("WEBVTT\n\n");
+        }
+        for (index, cue) in self.cues(transcript).iter().enumerate() {
+            // Writing into a string cannot fail
+            let _ = match format {
+                SubtitleFormat::Srt => write!(
+                    out,
+                    "{}\n{} --> {}\n{}\n\n",
+                    index + 1,
+                    timestamp(cue.start, ','),
+                    timestamp(cue.end, ','),
+                    cue_text(format, &cue.lines)
+                ),
+                SubtitleFormat::WebVtt => write!(
+                    out,
+                    "{} --> {}\n{}\n\n",
+                    timestamp(cue.start, '.'),
+                    timestamp(cue.end, '.'),
+                    cue_text(format, &cue.lines)
+                ),
+                SubtitleFormat::Timestamped => writeln!(
+                    out,
+                    "[{} - {}] {}",
+                    timestamp(cue.start, '.'),
+                    timestamp(cue.end, '.'),
+                    cue.lines.join(" ")
+                ),
+            };
+        }
+        out
+    }
+
+    pub fn write<W: Write>(
+        &self,
+        format: SubtitleFormat,
+        transcript: &Transcript,
+        mut writer: W,
+    ) -> io::Result<()> {
+        writer.write_all(self.render(format, transcript).as_bytes())?;
+        writer.flush()
+    }
+}
+
+/// Lines of a cue, without the arrow that would be taken for its timing and, in WebVTT, with
+/// the characters starting tags and escapes escaped.
+fn cue_text(format: SubtitleFormat, lines: &[String]) -> String {
+    let text = lines.join("\n").replace("-->", "");
+    match format {
+        SubtitleFormat::WebVtt => text
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;"),
+        _ => text,
+    }
+}
+
+/// `HH:MM:SS` followed by the separator and the milliseconds.
+fn timestamp(time: Duration, separator: char) -> String {
+    let millis = time.as_millis();
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        separator,
+        millis % 1000
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::RecognitionResult;
+
+    fn transcript(segments: &[(u64, u64, &str)]) -> Transcript {
+        let segments = segments
+            .iter()
+            .map(|(start, end, text)| TranscriptSegment {
+                start: Duration::from_millis(*start),
+                end: Duration::from_millis(*end),
+                result: RecognitionResult::new(*text, Duration::ZERO, Duration::ZERO),
+            })
+            .collect();
+        Transcript::new(segments, Duration::ZERO, None, Duration::ZERO)
+    }
+
+    #[test]
+    fn test_formats() {
+        let transcript = transcript(&[(1300, 4560, "hello world"), (5000, 5500, " ")]);
+        let subtitles = Subtitles::default();
+        assert_eq!(
+            subtitles.render(SubtitleFormat::Srt, &transcript),
+            "1\n00:00:01,300 --> 00:00:04,560\nhello world\n\n"
+        );
+        assert_eq!(
+            subtitles.render(SubtitleFormat::WebVtt, &transcript),
+            "WEBVTT\n\n00:00:01.300 --> 00:00:04.560\nhello world\n\n"
+        );
+        let mut out = vec![];
+        subtitles
+            .write(SubtitleFormat::Timestamped, &transcript, &mut out)
+            .unwrap();
+        assert_eq!(out, b"[00:00:01.300 - 00:00:04.560] hello world\n");
+        assert_eq!(
+            timestamp(Duration::from_millis(3_723_004), ','),
+            "01:02:03,004"
+        );
+        assert_eq!(
+            SubtitleFormat::from_name("WebVTT").unwrap().extension(),
+            "vtt"
+        );
+    }
+
+    #[test]
+    fn test_escape() {
+        let transcript = transcript(&[(0, 1000, "a <b> & c --> d")]);
+        let subtitles = Subtitles::default();
+        assert_eq!(
+            subtitles.render(SubtitleFormat::Srt, &transcript),
+            "1\n00:00:00,000 --> 00:00:01,000\na <b> & c  d\n\n"
+        );
+        assert_eq!(
+            subtitles.render(SubtitleFormat::WebVtt, &transcript),
+            "WEBVTT\n\n00:00:00.000 --> 00:00:01.000\na &lt;b&gt; &amp; c  d\n\n"
+        );
+    }
+
+    #[test]
+    fn test_output_format() {
+        let format = "VTT".parse::<OutputFormat>().unwrap();
+        assert_eq!(format, OutputFormat::Subtitles(SubtitleFormat::WebVtt));
+        assert!(format.is_segmented());
+        assert_eq!("json".parse::<OutputFormat>().unwrap().extension(), "json");
+        assert!(!OutputFormat::Text.is_segmented());
+        let error = "doc".parse::<OutputFormat>().unwrap_err();
+        assert!(matches!(error, SpeechCenterError::InvalidParameter(_)));
+    }
+
+    #[test]
+    fn test_split_lines() {
+        // 11 words of 4 characters, 54 characters in all
+        let text = "aaaa bbbb cccc dddd eeee ffff gggg hhhh iiii jjjj kkkk";
+        let transcript = transcript(&[(0, 5400, text)]);
+        let cues = Subtitles::default()
+            .max_line_length(14)
+            .max_duration(Duration::from_secs(60))
+            .cues(&transcript);
+        let lines = cues.iter().map(|cue| cue.lines.clone()).collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                vec!["aaaa bbbb cccc", "dddd eeee ffff"],
+                vec!["gggg hhhh iiii", "jjjj kkkk"],
+            ]
+        );
+        assert_eq!(cues[0].start, Duration::ZERO);
+        assert_eq!(cues[0].end, Duration::from_millis(2900));
+        assert_eq!(cues[1].start, Duration::from_millis(3000));
+        assert_eq!(cues[1].end, Duration::from_millis(5400));
+    }
+
+    #[test]
+    fn test_split_duration() {
+        let transcript = transcript(&[(0, 5400, "aaaa bbbb cccc dddd eeee ffff")]);
+        let cues = Subtitles::default()
+            .max_duration(Duration::from_secs(2))
+            .cues(&transcript);
+        let lines = cues
+            .iter()
+            .map(|cue| cue.lines.join(" "))
+            .collect::<Vec<_>>();
+        assert_eq!(lines, ["aaaa bbbb", "cccc dddd", "eeee ffff"]);
+        assert!(cues
+            .iter()
+            .all(|cue| cue.end - cue.start <= Duration::from_secs(2)));
+    }
+}
@@ -1,19 +1,25 @@
+use crate::cassette::{CallResult, Cassette, Recorded, Synthesize};
+use crate::config::SpeechCenterConfig;
+use crate::credentials::{self, CredentialsProvider, StaticToken};
+use crate::failover::EndpointStatus;
+use crate::language::Language;
+use crate::pool::{self, ChannelPool, Channels, Remote};
+use crate::result::SynthesisResult;
+use crate::retry::{self, RetryPolicy};
 use crate::speechcenter_tts_v1::speech_synthesizer_client::SpeechSynthesizerClient;
 use crate::speechcenter_tts_v1::synthesis_voice::{SynthesisUnion, Voice};
 use crate::speechcenter_tts_v1::{
-    AudioFormat as SynthesisFormat, SynthesisRequest, SynthesisVoice, VoiceSamplingRate,
+    AudioFormat as SynthesisFormat, SynthesisRequest, SynthesisResponse, SynthesisVoice,
+    VoiceSamplingRate,
 };
-use crate::{Result, SpeechCenterError};
-use bytes::Bytes;
-use std::error::Error;
-use std::str::FromStr;
-use tonic::codegen::InterceptedService;
-use tonic::metadata::{Ascii, MetadataValue};
-use tonic::service::Interceptor;
-use tonic::transport::{Channel, ClientTlsConfig, Uri};
-use tonic::{Request, Status};
-
-#[derive(Clone, Debug, PartialEq)]
+use crate::wav;
+use crate::{g711, Result, SampleFormat, SpeechCenterError};
+use async_trait::async_trait;
+use std::sync::Arc;
+use tonic::transport::Channel;
+use tonic::Request;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Speaker {
     EnUsTommy,
     EnUsAnnie,
@@ -24,24 +30,56 @@ pub enum Speaker {
 }
 
 impl Speaker {
+    pub const ALL: [Speaker; 6] = [
+        Self::EnUsTommy,
+        Self::EnUsAnnie,
+        Self::EsEsAurora,
+        Self::EsEsDavid,
+        Self::PtBrLuma,
+        Self::CaEsDavid,
+    ];
+
     pub fn from_name(name: &str, language: &str) -> Result<Self> {
-        match (
-            name.to_lowercase().as_str(),
-            language.to_lowercase().as_str(),
-        ) {
-            ("tommy", "en-us") => Ok(Self::EnUsTommy),
-            ("annie", "en-us") => Ok(Self::EnUsAnnie),
-            ("aurora", "es-es") => Ok(Self::EsEsAurora),
-            ("david", "es-es") => Ok(Self::EsEsDavid),
-            ("luma", "pt-br") => Ok(Self::PtBrLuma),
-            ("david", "ca-es") => Ok(Self::CaEsDavid),
-            _ => Err(SpeechCenterError::Unknown(format!(
-                "Nonexistent Speaker for Name/LanguageTag combination: {}/{}",
-                name, language
-            ))),
+        let tag = Language::parse(language)?;
+        Self::ALL
+            .into_iter()
+            .find(|speaker| speaker.name().eq_ignore_ascii_case(name) && speaker.language() == tag)
+            .ok_or_else(|| {
+                SpeechCenterError::InvalidParameter(format!(
+                    "Nonexistent Speaker for Name/LanguageTag combination: {}/{}",
+                    name, language
+                ))
+            })
+    }
+
+    /// Name of the voice, such as `tommy`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::EnUsTommy => "tommy",
+            Self::EnUsAnnie => "annie",
+            Self::EsEsAurora => "aurora",
+            Self::EsEsDavid | Self::CaEsDavid => "david",
+            Self::PtBrLuma => "luma",
+        }
+    }
+
+    /// Language the voice speaks.
+    pub fn language(&self) -> Language {
+        match self {
+            Self::EnUsTommy | Self::EnUsAnnie => Language::EN_US,
+            Self::EsEsAurora | Self::EsEsDavid => Language::ES_ES,
+            Self::PtBrLuma => Language::PT_BR,
+            Self::CaEsDavid => Language::CA_ES,
         }
     }
 
+    /// Voices speaking the language.
+    pub fn for_language(language: &Language) -> impl Iterator<Item = Speaker> + '_ {
+        Self::ALL
+            .into_iter()
+            .filter(move |speaker| speaker.language() == *language)
+    }
+
     pub fn to_voice(self) -> Voice {
         match self {
             Self::EnUsTommy => Voice::EnUsTommy,
@@ -54,7 +92,7 @@ impl Speaker {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum SampleRate {
     Khz8,
 }
@@ -65,7 +103,7 @@ impl TryFrom<u32> for SampleRate {
     fn try_from(sample_rate: u32) -> Result<Self, Self::Error> {
         match sample_rate {
             8000 => Ok(Self::Khz8),
-            _ => Err(SpeechCenterError::Unknown(format!(
+            _ => Err(SpeechCenterError::InvalidParameter(format!(
                 "Nonexistent Sample Rate option for {}",
                 sample_rate
             ))),
@@ -89,10 +127,14 @@ impl SampleRate {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum AudioFormat {
     WavLpcmS16le,
     RawLpcmS16le,
+    WavMulaw,
+    RawMulaw,
+    WavAlaw,
+    RawAlaw,
 }
 
 impl AudioFormat {
@@ -103,7 +145,11 @@ impl AudioFormat {
         ) {
             ("pcm", "wav") => Ok(Self::WavLpcmS16le),
             ("pcm", "raw") => Ok(Self::RawLpcmS16le),
-            _ => Err(SpeechCenterError::Unknown(format!(
+            ("mulaw" | "ulaw", "wav") => Ok(Self::WavMulaw),
+            ("mulaw" | "ulaw", "raw") => Ok(Self::RawMulaw),
+            ("alaw", "wav") => Ok(Self::WavAlaw),
+            ("alaw", "raw") => Ok(Self::RawAlaw),
+            _ => Err(SpeechCenterError::InvalidParameter(format!(
                 "Nonexistent Audio Format for Encoding/Header combination: {}/{}",
                 audio_encoding, audio_header
             ))),
@@ -113,63 +159,100 @@ impl AudioFormat {
     pub fn to_synthesis_format(self) -> SynthesisFormat {
         match self {
             Self::WavLpcmS16le => SynthesisFormat::WavLpcmS16le,
-            Self::RawLpcmS16le => SynthesisFormat::RawLpcmS16le,
+            // G.711 is encoded client side from the raw PCM returned by the service
+            Self::RawLpcmS16le
+            | Self::WavMulaw
+            | Self::RawMulaw
+            | Self::WavAlaw
+            | Self::RawAlaw => SynthesisFormat::RawLpcmS16le,
         }
     }
-}
 
-pub struct AddAuthorizationInterceptor {
-    credentials: MetadataValue<Ascii>,
-}
+    /// Whether the audio comes in a WAV file rather than as bare samples.
+    pub fn has_header(&self) -> bool {
+        matches!(self, Self::WavLpcmS16le | Self::WavMulaw | Self::WavAlaw)
+    }
 
-impl AddAuthorizationInterceptor {
-    pub fn new(token: &str) -> Result<Self> {
-        let credentials = tonic::metadata::MetadataValue::from_str(token).map_err(|e| {
-            SpeechCenterError::Unknown(format!(
-                "Error converting credentials to MetadataValue: {}",
-                e
-            ))
-        })?;
-        Ok(Self { credentials })
+    pub fn sample_format(&self) -> SampleFormat {
+        match self {
+            Self::WavLpcmS16le | Self::RawLpcmS16le => SampleFormat::S16,
+            Self::WavMulaw | Self::RawMulaw => SampleFormat::MuLaw,
+            Self::WavAlaw | Self::RawAlaw => SampleFormat::ALaw,
+        }
     }
-}
 
-impl Interceptor for AddAuthorizationInterceptor {
-    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
-        request
-            .metadata_mut()
-            .insert("authorization", self.credentials.clone());
-        Ok(request)
+    /// Samples in the encoding of the format from the audio returned by the service.
+    async fn decode(&self, audio: Vec<u8>) -> Result<Vec<u8>> {
+        let pcm = match self.clone().to_synthesis_format() {
+            SynthesisFormat::WavLpcmS16le => {
+                let mut reader = &audio[..];
+                let header = wav::read_header(&mut reader).await?;
+                let len = header.data_len.map_or(reader.len(), |len| len as usize);
+                reader[..len.min(reader.len())].to_vec()
+            }
+            SynthesisFormat::RawLpcmS16le => audio,
+        };
+        Ok(match self.sample_format() {
+            SampleFormat::MuLaw => g711::encode_mulaw(&pcm),
+            SampleFormat::ALaw => g711::encode_alaw(&pcm),
+            _ => pcm,
+        })
     }
 }
 
-#[derive(Debug)]
+/// Handle to the service, cheap to clone and usable concurrently from many tasks.
+#[derive(Clone, Debug)]
 pub struct Client {
-    inner: SpeechSynthesizerClient<InterceptedService<Channel, AddAuthorizationInterceptor>>,
+    channels: Channels,
+    gzip: bool,
+    credentials: Arc<dyn CredentialsProvider>,
+    retry: RetryPolicy,
+    cassette: Option<Arc<Cassette>>,
 }
 
 impl Client {
     pub async fn new(url: &str, credentials: &str) -> Result<Self> {
-        let uri = Uri::from_str(url).map_err(|e| {
-            SpeechCenterError::Unknown(format!(
-                "Error building Uri from string [uri={}]: {}",
-                url, e
-            ))
-        })?;
-        let channel = Channel::builder(uri)
-            .tls_config(ClientTlsConfig::default())
-            .map_err(|e| SpeechCenterError::Unknown(format!("Error setting up tls: {:?}", e)))?;
-        let channel = channel.connect().await.map_err(|e| {
-            SpeechCenterError::Connection(format!(
-                "Could not connect [url={}] {}",
-                url,
-                e.source().map(|e| e.to_string()).unwrap_or_default()
-            ))
-        })?;
-
-        let interceptor = AddAuthorizationInterceptor::new(credentials)?;
-        let c = SpeechSynthesizerClient::with_interceptor(channel, interceptor);
-        Ok(Self { inner: c })
+        let credentials = Arc::new(StaticToken::new(credentials));
+        Self::with_config(SpeechCenterConfig::new(url), credentials).await
+    }
+
+    pub async fn with_config(
+        config: SpeechCenterConfig,
+        credentials: Arc<dyn CredentialsProvider>,
+    ) -> Result<Self> {
+        let channels = config.channels().await?;
+        Ok(Self::from_channels(channels, config, credentials))
+    }
+
+    /// Client sending its calls through the channels of the pool.
+    pub fn with_pool(pool: &ChannelPool, credentials: Arc<dyn CredentialsProvider>) -> Self {
+        Self::from_channels(
+            Channels::Pool(pool.clone()),
+            pool.config().clone(),
+            credentials,
+        )
+    }
+
+    /// Circuit breaker states of the failover endpoints, empty without failover.
+    pub fn endpoints(&self) -> Vec<EndpointStatus> {
+        match &self.channels {
+            Channels::Failover(failover) => failover.status(),
+            _ => vec![],
+        }
+    }
+
+    pub(crate) fn from_channels(
+        channels: Channels,
+        config: SpeechCenterConfig,
+        credentials: Arc<dyn CredentialsProvider>,
+    ) -> Self {
+        Self {
+            channels,
+            gzip: config.gzip,
+            credentials,
+            retry: config.retry,
+            cassette: config.cassette,
+        }
     }
 
     fn synthesis_request(
@@ -192,32 +275,91 @@ impl Client {
     }
 
     pub async fn synthesize(
-        &mut self,
+        &self,
         speaker: Speaker,
         sample_rate: SampleRate,
         audio_format: AudioFormat,
         text: &str,
-    ) -> Result<Bytes> {
-        let r = Self::synthesis_request(speaker, sample_rate, audio_format, text.to_string());
-        let r =
-            self.inner.synthesize(Request::new(r)).await.map_err(|e| {
-                SpeechCenterError::Synthesis(format!("Error in synthesis: {:?}", e))
-            })?;
-        let res = r.get_ref();
-        Ok(Bytes::from(res.audio.to_owned()))
+    ) -> Result<SynthesisResult> {
+        let r = Self::synthesis_request(
+            speaker.clone(),
+            sample_rate.clone(),
+            audio_format.clone(),
+            text.to_string(),
+        );
+        let mut attempt = 0;
+        let call = || {
+            attempt += 1;
+            let lease = self.channels.lease(attempt);
+            let gzip = self.gzip;
+            let credentials = self.credentials.clone();
+            let cassette = self.cassette.clone();
+            let request = r.clone();
+            async move {
+                let lease = match lease {
+                    Some(lease) => lease,
+                    None => return Ok(Err(pool::no_channel())),
+                };
+                let remote = Remote {
+                    lease,
+                    gzip,
+                    credentials,
+                };
+                let r = Recorded::new(remote, cassette).synthesize(request).await?;
+                Ok(r.map(|r| r.into_inner()))
+            }
+        };
+        let res = retry::retry(&self.retry, call).await?;
+        let data = audio_format.decode(res.value.audio).await?;
+        let mut result = SynthesisResult::new(speaker, sample_rate, audio_format, text, data);
+        result.attempts = res.attempts;
+        Ok(result)
+    }
+}
+
+fn service(channel: Channel, gzip: bool) -> SpeechSynthesizerClient<Channel> {
+    let client = SpeechSynthesizerClient::new(channel);
+    match gzip {
+        true => client.send_gzip().accept_gzip(),
+        false => client,
+    }
+}
+
+#[async_trait]
+impl Synthesize for Remote {
+    async fn synthesize(&mut self, request: SynthesisRequest) -> CallResult<SynthesisResponse> {
+        let mut request = Request::new(request);
+        credentials::authorize(&*self.credentials, &mut request).await?;
+        let r = service(self.lease.channel(), self.gzip)
+            .synthesize(request)
+            .await;
+        self.lease.report(&r);
+        Ok(r)
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::testkit::{Match, MockServer, Reply};
+    use std::time::Duration;
 
     #[tokio::test]
     async fn test_empty_url() {
         let error = Client::new("", "")
             .await
             .expect_err("Should not be able to create a client");
-        assert!(matches!(error, SpeechCenterError::Unknown(_)));
+        assert!(matches!(error, SpeechCenterError::Config(_)));
+    }
+
+    #[tokio::test]
+    async fn test_g711_formats() {
+        let format = AudioFormat::from_str("MULAW", "WAV").expect("Should accept mu-law");
+        assert!(format.has_header());
+        assert_eq!(format.decode(vec![0, 0, 0, 0]).await.unwrap(), [0xFF, 0xFF]);
+
+        let format = AudioFormat::from_str("alaw", "raw").expect("Should accept A-law");
+        assert_eq!(format.decode(vec![0, 0]).await.unwrap(), [0xD5]);
     }
 
     #[tokio::test]
@@ -225,6 +367,50 @@ mod test {
         let error = Client::new("http://127.0.0.1:9999", "")
             .await
             .expect_err("Should not be able to connect anywhere");
-        assert!(matches!(error, SpeechCenterError::Connection(_)));
+        assert!(matches!(error, SpeechCenterError::Connection { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_synthesize() {
+        let server = MockServer::builder()
+            .token("token")
+            .respond(Match::Text("Hello".to_string()), Reply::Audio(vec![0; 16]))
+            .start()
+            .await
+            .unwrap();
+        let client = Client::with_config(
+            server.config().gzip(true),
+            Arc::new(StaticToken::new("token")),
+        )
+        .await
+        .expect("Should connect to the mock server");
+
+        let res = client
+            .synthesize(
+                Speaker::EnUsTommy,
+                SampleRate::Khz8,
+                AudioFormat::WavLpcmS16le,
+                "Hello",
+            )
+            .await
+            .expect("Should synthesize the text");
+        assert_eq!(res.attempts, 1);
+        assert_eq!(res.text, "Hello");
+        assert_eq!(res.duration(), Duration::from_millis(1));
+        assert_eq!(res.raw().len(), 16);
+        assert_eq!(&res.audio()[0..4], b"RIFF");
+        assert_eq!(res.audio().len(), 44 + 16);
+
+        let res = client
+            .synthesize(
+                Speaker::EnUsTommy,
+                SampleRate::Khz8,
+                AudioFormat::RawMulaw,
+                "Hello",
+            )
+            .await
+            .expect("Should synthesize the text");
+        assert_eq!(&res.audio()[..], &[0xFF; 8]);
+        assert_eq!(res.pcm(), vec![0; 8]);
     }
 }
@@ -0,0 +1,306 @@
+//! Local Speech Center server with scripted responses, for testing code built on the clients.
+
+pub use crate::cassette::audio_digest;
+use crate::config::SpeechCenterConfig;
+use crate::csr_grpc_gateway::recognition_request::RequestUnion;
+use crate::csr_grpc_gateway::recognition_resource::ResourceUnion;
+use crate::csr_grpc_gateway::speech_recognizer_server::{SpeechRecognizer, SpeechRecognizerServer};
+use crate::csr_grpc_gateway::{RecognitionRequest, RecognitionResponse};
+use crate::speechcenter_tts_v1::speech_synthesizer_server::{
+    SpeechSynthesizer, SpeechSynthesizerServer,
+};
+use crate::speechcenter_tts_v1::{AudioFormat, SynthesisRequest, SynthesisResponse};
+use crate::wav::{self, WavEncoding, WavSpec};
+use std::collections::VecDeque;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tokio_stream::wrappers::TcpListenerStream;
+use tokio_stream::StreamExt;
+use tonic::metadata::{MetadataMap, MetadataValue};
+use tonic::transport::Server;
+use tonic::{Code, Request, Response, Status, Streaming};
+
+/// Requests a scripted response applies to.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Match {
+    /// Recognitions of audio with the given [`audio_digest`]
+    Audio(String),
+    /// Recognitions with the given inline grammar
+    Grammar(String),
+    /// Syntheses of the given text
+    Text(String),
+    /// Any request of the service the reply belongs to
+    Any,
+}
+
+/// Scripted response of the server.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Reply {
+    /// Transcription returned by the recognizer
+    Transcript(String),
+    /// Mono 8kHz PCM16 returned by the synthesizer, with a WAV header when requested
+    Audio(Vec<u8>),
+    Error(Code, String),
+}
+
+impl Reply {
+    fn applies_to_recognition(&self) -> bool {
+        !matches!(self, Self::Audio(_))
+    }
+
+    fn applies_to_synthesis(&self) -> bool {
+        !matches!(self, Self::Transcript(_))
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    token: Option<String>,
+    latency: Duration,
+    rules: Vec<(Match, Reply)>,
+    failures: Mutex<VecDeque<(Code, String)>>,
+    calls: AtomicU32,
+}
+
+impl State {
+    /// Counts the call, then applies the latency, token check and injected failures common to
+    /// every call, returning its number for the `x-mock-call` header of the response.
+    async fn enter(&self, metadata: &MetadataMap) -> Result<u32, Status> {
+        let call = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+        tokio::time::sleep(self.latency).await;
+        if let Some(token) = &self.token {
+            let authorization = metadata.get("authorization").and_then(|v| v.to_str().ok());
+            if authorization != Some(token.as_str()) {
+                return Err(Status::unauthenticated("Invalid or missing token"));
+            }
+        }
+        match self.failures.lock().unwrap().pop_front() {
+            Some((code, message)) => Err(Status::new(code, message)),
+            None => Ok(call),
+        }
+    }
+
+    fn push_failures(&self, times: usize, code: Code, message: String) {
+        self.failures
+            .lock()
+            .unwrap()
+            .extend((0..times).map(|_| (code, message.clone())));
+    }
+
+    fn reply(&self, applies: impl Fn(&Match, &Reply) -> bool) -> Option<Reply> {
+        self.rules
+            .iter()
+            .find(|(when, reply)| applies(when, reply))
+            .map(|(_, reply)| reply.clone())
+    }
+}
+
+fn unscripted() -> Status {
+    Status::not_found("No scripted response for the request")
+}
+
+#[derive(Debug)]
+struct Service(Arc<State>);
+
+#[tonic::async_trait]
+impl SpeechRecognizer for Service {
+    async fn recognize_stream(
+        &self,
+        request: Request<Streaming<RecognitionRequest>>,
+    ) -> Result<Response<RecognitionResponse>, Status> {
+        let call = self.0.enter(request.metadata()).await?;
+        let mut stream = request.into_inner();
+        let init = match stream
+            .next()
+            .await
+            .transpose()?
+            .and_then(|r| r.request_union)
+        {
+            Some(RequestUnion::Init(init)) => init,
+            _ => return Err(Status::invalid_argument("First message must be an init")),
+        };
+        let grammar = match init.resource.and_then(|r| r.resource_union) {
+            Some(ResourceUnion::InlineGrammar(grammar)) => Some(grammar),
+            Some(ResourceUnion::Topic(_)) => None,
+            None => return Err(Status::invalid_argument("Missing topic or grammar")),
+        };
+        let mut audio = vec![];
+        while let Some(request) = stream.next().await.transpose()? {
+            match request.request_union {
+                Some(RequestUnion::Audio(chunk)) => audio.extend(chunk),
+                _ => return Err(Status::invalid_argument("Unexpected init message")),
+            }
+        }
+
+        let digest = audio_digest(&audio);
+        let reply = self
+            .0
+            .reply(|when, reply| {
+                reply.applies_to_recognition()
+                    && match when {
+                        Match::Audio(d) => *d == digest,
+                        Match::Grammar(g) => Some(g) == grammar.as_ref(),
+                        Match::Text(_) => false,
+                        Match::Any => true,
+                    }
+            })
+            .ok_or_else(unscripted)?;
+        match reply {
+            Reply::Transcript(text) => {
+                let mut response = Response::new(RecognitionResponse { text });
+                response
+                    .metadata_mut()
+                    .insert("x-mock-call", MetadataValue::from(call));
+                Ok(response)
+            }
+            Reply::Error(code, message) => Err(Status::new(code, message)),
+            Reply::Audio(_) => unreachable!(),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl SpeechSynthesizer for Service {
+    async fn synthesize(
+        &self,
+        request: Request<SynthesisRequest>,
+    ) -> Result<Response<SynthesisResponse>, Status> {
+        self.0.enter(request.metadata()).await?;
+        let request = request.into_inner();
+        let reply = self
+            .0
+            .reply(|when, reply| {
+                reply.applies_to_synthesis()
+                    && match when {
+                        Match::Text(t) => *t == request.text,
+                        Match::Audio(_) | Match::Grammar(_) => false,
+                        Match::Any => true,
+                    }
+            })
+            .ok_or_else(unscripted)?;
+        let audio = match reply {
+            Reply::Audio(audio) => audio,
+            Reply::Error(code, message) => return Err(Status::new(code, message)),
+            Reply::Transcript(_) => unreachable!(),
+        };
+        let audio = match AudioFormat::from_i32(request.audio_format) {
+            Some(AudioFormat::WavLpcmS16le) => {
+                let spec = WavSpec {
+                    encoding: WavEncoding::Pcm,
+                    channels: 1,
+                    sample_rate: 8000,
+                    bits_per_sample: 16,
+                };
+                let mut wav = wav::header(spec, audio.len() as u32);
+                wav.extend(audio);
+                wav
+            }
+            Some(AudioFormat::RawLpcmS16le) => audio,
+            None => return Err(Status::invalid_argument("Unknown audio format")),
+        };
+        Ok(Response::new(SynthesisResponse { audio }))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MockServerBuilder {
+    state: State,
+}
+
+impl MockServerBuilder {
+    /// Rejects calls whose authorization header is not exactly `token`.
+    pub fn token(mut self, token: impl Into<String>) -> Self {
+        self.state.token = Some(token.into());
+        self
+    }
+
+    /// Delays every call.
+    pub fn latency(mut self, latency: Duration) -> Self {
+        self.state.latency = latency;
+        self
+    }
+
+    /// Adds a scripted response, rules being checked in the order they were added.
+    pub fn respond(mut self, when: Match, reply: Reply) -> Self {
+        self.state.rules.push((when, reply));
+        self
+    }
+
+    /// Fails the next `times` calls with the given status, before looking at the request.
+    pub fn fail(self, times: usize, code: Code, message: impl Into<String>) -> Self {
+        self.state.push_failures(times, code, message.into());
+        self
+    }
+
+    /// Serves both services on an ephemeral local port until the server is dropped.
+    pub async fn start(self) -> io::Result<MockServer> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(self.state);
+        let (shutdown, stopped) = oneshot::channel::<()>();
+
+        let server = Server::builder()
+            .add_service(
+                SpeechRecognizerServer::new(Service(state.clone()))
+                    .accept_gzip()
+                    .send_gzip(),
+            )
+            .add_service(
+                SpeechSynthesizerServer::new(Service(state.clone()))
+                    .accept_gzip()
+                    .send_gzip(),
+            )
+            .serve_with_incoming_shutdown(TcpListenerStream::new(listener), async {
+                let _ = stopped.await;
+            });
+        tokio::spawn(async move {
+            if let Err(e) = server.await {
+                error!("Mock server failed: {}", e);
+            }
+        });
+
+        Ok(MockServer {
+            addr,
+            state,
+            _shutdown: shutdown,
+        })
+    }
+}
+
+/// Local recognizer and synthesizer, stopped when dropped.
+#[derive(Debug)]
+pub struct MockServer {
+    addr: SocketAddr,
+    state: Arc<State>,
+    _shutdown: oneshot::Sender<()>,
+}
+
+impl MockServer {
+    pub fn builder() -> MockServerBuilder {
+        MockServerBuilder::default()
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Plaintext configuration pointing at the server.
+    pub fn config(&self) -> SpeechCenterConfig {
+        SpeechCenterConfig::new(self.url()).plaintext(true)
+    }
+
+    /// Number of calls received by both services, including rejected ones.
+    pub fn calls(&self) -> u32 {
+        self.state.calls.load(Ordering::SeqCst)
+    }
+
+    /// Fails the next `times` calls with the given status.
+    pub fn fail(&self, times: usize, code: Code, message: impl Into<String>) {
+        self.state.push_failures(times, code, message.into());
+    }
+}
@@ -0,0 +1,449 @@
+use crate::audio_stream::{AudioStream, RECOGNITION_SAMPLE_RATE, RECOGNITION_SAMPLE_WIDTH};
+use crate::{AudioSpec, Result, SpeechCenterError};
+use bytes::Bytes;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio_stream::StreamExt;
+
+/// Length of the frames classified as speech or silence
+pub const VAD_FRAME: Duration = Duration::from_millis(20);
+const FRAME_SAMPLES: usize = (RECOGNITION_SAMPLE_RATE / 50) as usize;
+pub(crate) const FRAME_BYTES: usize = FRAME_SAMPLES * RECOGNITION_SAMPLE_WIDTH;
+/// Frames over which the quietest one is taken as the noise floor, 1.5s
+const NOISE_WINDOW: usize = 75;
+/// Consecutive loud frames needed to start speech, so that clicks are not taken for it
+pub(crate) const ONSET_FRAMES: usize = 2;
+/// Energy of digital silence, in dBFS
+const MIN_ENERGY: f64 = -100.0;
+
+/// How readily audio is taken for silence, the higher the more is trimmed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Aggressiveness {
+    Low,
+    #[default]
+    Normal,
+    High,
+    VeryHigh,
+}
+
+impl Aggressiveness {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name.to_lowercase().as_str() {
+            "low" => Ok(Self::Low),
+            "normal" => Ok(Self::Normal),
+            "high" => Ok(Self::High),
+            "very-high" => Ok(Self::VeryHigh),
+            _ => Err(SpeechCenterError::InvalidParameter(format!(
+                "Unknown VAD aggressiveness: {}",
+                name
+            ))),
+        }
+    }
+
+    /// Decibels a frame must stand above the noise floor to be speech.
+    fn margin(self) -> f64 {
+        match self {
+            Self::Low => 6.0,
+            Self::Normal => 9.0,
+            Self::High => 12.0,
+            Self::VeryHigh => 15.0,
+        }
+    }
+
+    /// Energy in dBFS below which a frame is always silence.
+    fn floor(self) -> f64 {
+        match self {
+            Self::Low => -60.0,
+            Self::Normal => -55.0,
+            Self::High => -50.0,
+            Self::VeryHigh => -45.0,
+        }
+    }
+
+    /// Quiet frames still taken for speech after it, bridging the gaps between words.
+    fn hangover(self) -> usize {
+        match self {
+            Self::Low => 15,
+            Self::Normal => 10,
+            Self::High => 6,
+            Self::VeryHigh => 4,
+        }
+    }
+}
+
+/// Energy based voice activity detection on mono 8kHz signed 16-bit PCM.
+///
+/// Frames are taken for speech when they stand out of the noise floor, tracked as the quietest
+/// frame of the last 1.5s, so that steady background noise is trimmed along with silence.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Vad {
+    aggressiveness: Aggressiveness,
+    padding: Duration,
+    max_pause: Option<Duration>,
+}
+
+impl Default for Vad {
+    fn default() -> Self {
+        Self::new(Aggressiveness::default())
+    }
+}
+
+impl Vad {
+    pub fn new(aggressiveness: Aggressiveness) -> Self {
+        Self {
+            aggressiveness,
+            padding: Duration::from_millis(200),
+            max_pause: None,
+        }
+    }
+
+    /// Silence kept before and after the speech, 200ms by default.
+    pub fn padding(mut self, padding: Duration) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    /// Shortens pauses within the speech to `max_pause`, keeping their start and end. Pauses are
+    /// kept whole by default.
+    pub fn max_pause(mut self, max_pause: Duration) -> Self {
+        self.max_pause = Some(max_pause);
+        self
+    }
+
+    pub fn aggressiveness(&self) -> Aggressiveness {
+        self.aggressiveness
+    }
+
+    /// Trims the silence of the audio as it is streamed, which must already be recognizable.
+    ///
+    /// The statistics are complete once the returned stream has ended.
+    pub(crate) fn trim(&self, audio: AudioStream) -> (AudioStream, Arc<Mutex<VadStats>>) {
+        let stats = Arc::new(Mutex::new(VadStats::default()));
+        let stream_stats = stats.clone();
+        let chunk_bytes = audio.chunk_bytes();
+        let mut trimmer = Trimmer::new(self);
+        let mut source = audio;
+        let s = async_stream::stream! {
+            while let Some(data) = source.next().await {
+                match data {
+                    Ok(data) => {
+                        let kept = trimmer.push(&data);
+                        *stream_stats.lock().unwrap() = trimmer.stats;
+                        if !kept.is_empty() {
+                            yield Ok(Bytes::from(kept));
+                        }
+                    }
+                    Err(e) => {
+                        yield Err(e);
+                        return;
+                    }
+                }
+            }
+            let kept = trimmer.finish();
+            *stream_stats.lock().unwrap() = trimmer.stats;
+            if !kept.is_empty() {
+                yield Ok(Bytes::from(kept));
+            }
+        };
+        (
+            AudioStream::rechunked(s, AudioSpec::RECOGNITION, chunk_bytes),
+            stats,
+        )
+    }
+
+    /// Trims the silence of audio in memory, returning the audio kept.
+    pub fn trim_bytes(&self, pcm: &[u8]) -> (Vec<u8>, VadStats) {
+        let mut trimmer = Trimmer::new(self);
+        let mut kept = trimmer.push(pcm);
+        kept.extend(trimmer.finish());
+        (kept, trimmer.stats)
+    }
+
+    pub(crate) fn padding_frames(&self) -> usize {
+        frames(self.padding)
+    }
+}
+
+/// Whole frames in `duration`.
+pub(crate) fn frames(duration: Duration) -> usize {
+    (duration.as_millis() / VAD_FRAME.as_millis()) as usize
+}
+
+/// Samples of a frame of PCM16.
+pub(crate) fn samples(frame: &[u8]) -> Vec<i16> {
+    frame
+        .chunks_exact(2)
+        .map(|s| i16::from_le_bytes([s[0], s[1]]))
+        .collect()
+}
+
+/// Speech found while trimming the audio.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct VadStats {
+    pub frames: usize,
+    pub speech_frames: usize,
+    /// Bytes of audio read and sent
+    pub read: usize,
+    pub kept: usize,
+}
+
+impl VadStats {
+    /// Fraction of the frames taken for speech, none without audio.
+    pub fn speech_ratio(&self) -> Option<f64> {
+        match self.frames {
+            0 => None,
+            frames => Some(self.speech_frames as f64 / frames as f64),
+        }
+    }
+
+    /// Playing time of the audio left out.
+    pub fn trimmed(&self) -> Duration {
+        AudioSpec::RECOGNITION.duration(self.read.saturating_sub(self.kept))
+    }
+}
+
+/// Classifies frames one after the other.
+#[derive(Debug)]
+pub(crate) struct Detector {
+    aggressiveness: Aggressiveness,
+    energies: VecDeque<f64>,
+    loud: usize,
+    hangover: usize,
+    speaking: bool,
+}
+
+impl Detector {
+    pub(crate) fn new(aggressiveness: Aggressiveness) -> Self {
+        Self {
+            aggressiveness,
+            energies: VecDeque::with_capacity(NOISE_WINDOW),
+            loud: 0,
+            hangover: 0,
+            speaking: false,
+        }
+    }
+
+    /// Whether the frame of samples is speech, given the frames before it.
+    pub(crate) fn is_speech(&mut self, frame: &[i16]) -> bool {
+        let energy = energy(frame);
+        if self.energies.len() == NOISE_WINDOW {
+            self.energies.pop_front();
+        }
+        self.energies.push_back(energy);
+        // Until a whole window is seen, the audio is taken to start from quiet
+        let quiet = match self.energies.len() {
+            NOISE_WINDOW => f64::INFINITY,
+            _ => self.aggressiveness.floor(),
+        };
+        let noise = self.energies.iter().copied().fold(quiet, f64::min);
+        let loud =
+            energy > self.aggressiveness.floor() && energy - noise > self.aggressiveness.margin();
+        self.loud = if loud { self.loud + 1 } else { 0 };
+
+        if loud && (self.speaking || self.loud >= ONSET_FRAMES) {
+            self.speaking = true;
+            self.hangover = self.aggressiveness.hangover();
+        } else if self.speaking && !loud {
+            match self.hangover {
+                0 => self.speaking = false,
+                _ => self.hangover -= 1,
+            }
+        }
+        self.speaking
+    }
+}
+
+/// Energy of the samples in dBFS.
+fn energy(frame: &[i16]) -> f64 {
+    if frame.is_empty() {
+        return MIN_ENERGY;
+    }
+    let power = frame
+        .iter()
+        .map(|s| (f64::from(*s) / 32768.0).powi(2))
+        .sum::<f64>()
+        / frame.len() as f64;
+    (10.0 * power.log10()).max(MIN_ENERGY)
+}
+
+/// Drops the silence before and after the speech, keeping some padding around it.
+struct Trimmer {
+    detector: Detector,
+    padding: usize,
+    max_pause: Option<usize>,
+    pending: Vec<u8>,
+    /// Silence before the speech, up to the padding
+    leading: VecDeque<Vec<u8>>,
+    /// Silence since the speech stopped, sent only if it starts again
+    pause: Vec<Vec<u8>>,
+    started: bool,
+    stats: VadStats,
+}
+
+impl Trimmer {
+    fn new(vad: &Vad) -> Self {
+        Self {
+            detector: Detector::new(vad.aggressiveness),
+            padding: vad.padding_frames(),
+            max_pause: vad.max_pause.map(frames),
+            pending: vec![],
+            leading: VecDeque::new(),
+            pause: vec![],
+            started: false,
+            stats: VadStats::default(),
+        }
+    }
+
+    /// Audio to send out of the new data.
+    fn push(&mut self, data: &[u8]) -> Vec<u8> {
+        self.stats.read += data.len();
+        self.pending.extend_from_slice(data);
+        let mut kept = vec![];
+        while self.pending.len() >= FRAME_BYTES {
+            let frame = self.pending.drain(..FRAME_BYTES).collect::<Vec<_>>();
+            let speech = self.detector.is_speech(&samples(&frame));
+            self.stats.frames += 1;
+            if speech {
+                self.stats.speech_frames += 1;
+                self.speech(frame, &mut kept);
+            } else if self.started {
+                self.pause.push(frame);
+            } else {
+                self.leading.push_back(frame);
+                // The padding is kept before the loud frames that started the speech
+                if self.leading.len() > self.padding + ONSET_FRAMES - 1 {
+                    self.leading.pop_front();
+                }
+            }
+        }
+        self.stats.kept += kept.len();
+        kept
+    }
+
+    /// Audio left to send once the source has ended.
+    fn finish(&mut self) -> Vec<u8> {
+        let mut kept = vec![];
+        if self.started {
+            // A partial frame at the end goes with the speech it follows
+            if self.pause.is_empty() {
+                kept.append(&mut self.pending);
+            }
+            let trailing = self.pause.len().min(self.padding);
+            self.pause.drain(..trailing).for_each(|f| kept.extend(f));
+        }
+        self.pause.clear();
+        self.pending.clear();
+        self.stats.kept += kept.len();
+        kept
+    }
+
+    fn speech(&mut self, frame: Vec<u8>, kept: &mut Vec<u8>) {
+        if !self.started {
+            self.started = true;
+            self.leading.drain(..).for_each(|f| kept.extend(f));
+        }
+        let pause = std::mem::take(&mut self.pause);
+        match self.max_pause {
+            Some(max) if pause.len() > max => {
+                let end = max - max / 2;
+                pause[..max / 2].iter().for_each(|f| kept.extend(f));
+                pause[pause.len() - end..]
+                    .iter()
+                    .for_each(|f| kept.extend(f));
+            }
+            _ => pause.into_iter().for_each(|f| kept.extend(f)),
+        }
+        kept.extend(frame);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::ChunkSize;
+
+    /// Noise of the given amplitude followed by a tone and noise again, each `ms` long.
+    pub(crate) fn noise_tone_noise(ms: [usize; 3], noise: f64, tone: f64) -> Vec<u8> {
+        let mut seed = 1u32;
+        let samples = ms[0] * 8 + ms[1] * 8 + ms[2] * 8;
+        (0..samples)
+            .map(|i| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                let random = f64::from(seed >> 16) / 65536.0 - 0.5;
+                let mut sample = random * noise;
+                if (ms[0] * 8..(ms[0] + ms[1]) * 8).contains(&i) {
+                    sample += (i as f64 * 2.0 * std::f64::consts::PI * 440.0 / 8000.0).sin() * tone;
+                }
+                (sample * 32767.0) as i16
+            })
+            .flat_map(|s| s.to_le_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn test_trim_bytes() {
+        let audio = noise_tone_noise([1000, 1000, 1000], 0.002, 0.3);
+        let vad = Vad::new(Aggressiveness::Normal).padding(Duration::from_millis(100));
+        let (kept, stats) = vad.trim_bytes(&audio);
+        let kept = AudioSpec::RECOGNITION.duration(kept.len());
+        // The tone plus the padding and the hangover after it
+        assert!(kept >= Duration::from_millis(1200), "{:?}", kept);
+        assert!(kept <= Duration::from_millis(1500), "{:?}", kept);
+        assert_eq!(stats.frames, 150);
+        let ratio = stats.speech_ratio().unwrap();
+        assert!((0.3..0.45).contains(&ratio), "{}", ratio);
+        assert_eq!(
+            stats.trimmed() + kept,
+            AudioSpec::RECOGNITION.duration(audio.len())
+        );
+
+        let (kept, stats) = vad.trim_bytes(&vec![0; 16000]);
+        assert!(kept.is_empty());
+        assert_eq!(stats.speech_ratio(), Some(0.0));
+        assert_eq!(vad.trim_bytes(&[]).1.speech_ratio(), None);
+    }
+
+    #[test]
+    fn test_max_pause() {
+        let mut audio = noise_tone_noise([0, 500, 2000], 0.0, 0.3);
+        audio.extend(noise_tone_noise([0, 500, 0], 0.0, 0.3));
+        let vad = Vad::new(Aggressiveness::VeryHigh).padding(Duration::ZERO);
+        let whole = vad.trim_bytes(&audio).0.len();
+        assert_eq!(
+            AudioSpec::RECOGNITION.duration(whole),
+            Duration::from_secs(3)
+        );
+        let vad = vad.max_pause(Duration::from_millis(400));
+        let shortened = vad.trim_bytes(&audio).0.len();
+        assert_eq!(
+            AudioSpec::RECOGNITION.duration(shortened),
+            Duration::from_millis(1460)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_trim_stream() {
+        let audio = noise_tone_noise([500, 500, 500], 0.001, 0.3);
+        let vad = Vad::new(Aggressiveness::High);
+        let expected = vad.trim_bytes(&audio);
+        let stream = AudioStream::from_bytes(audio, ChunkSize::Bytes(700));
+        let (mut stream, stats) = vad.trim(stream);
+        let mut kept = vec![];
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.unwrap();
+            assert!(chunk.len() <= 700);
+            kept.extend_from_slice(&chunk);
+        }
+        assert_eq!((kept, *stats.lock().unwrap()), expected);
+    }
+
+    #[test]
+    fn test_aggressiveness() {
+        assert_eq!(
+            Aggressiveness::from_name("Very-High").unwrap(),
+            Aggressiveness::VeryHigh
+        );
+        assert!(Aggressiveness::from_name("extreme").is_err());
+    }
+}
@@ -0,0 +1,357 @@
+use std::fmt;
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_IEEE_FLOAT: u16 = 3;
+const FORMAT_ALAW: u16 = 6;
+const FORMAT_MULAW: u16 = 7;
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+/// Length of the extensible fmt chunk, the longest one
+const FORMAT_CHUNK_LEN: usize = 40;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WavEncoding {
+    Pcm,
+    Float,
+    ALaw,
+    MuLaw,
+    Other(u16),
+}
+
+impl From<u16> for WavEncoding {
+    fn from(format_tag: u16) -> Self {
+        match format_tag {
+            FORMAT_PCM => Self::Pcm,
+            FORMAT_IEEE_FLOAT => Self::Float,
+            FORMAT_ALAW => Self::ALaw,
+            FORMAT_MULAW => Self::MuLaw,
+            _ => Self::Other(format_tag),
+        }
+    }
+}
+
+impl From<WavEncoding> for u16 {
+    fn from(encoding: WavEncoding) -> u16 {
+        match encoding {
+            WavEncoding::Pcm => FORMAT_PCM,
+            WavEncoding::Float => FORMAT_IEEE_FLOAT,
+            WavEncoding::ALaw => FORMAT_ALAW,
+            WavEncoding::MuLaw => FORMAT_MULAW,
+            WavEncoding::Other(format_tag) => format_tag,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WavSpec {
+    pub encoding: WavEncoding,
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub bits_per_sample: u16,
+}
+
+impl fmt::Display for WavSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} {}-bit, {} channel(s), {}Hz",
+            self.encoding, self.bits_per_sample, self.channels, self.sample_rate
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, thiserror::Error)]
+pub enum WavError {
+    #[error("Not a RIFF/WAVE file")]
+    NotWav,
+    #[error("Missing {} chunk", _0)]
+    MissingChunk(&'static str),
+    #[error("Malformed fmt chunk")]
+    InvalidFormatChunk,
+    #[error("Unexpected end of file")]
+    Truncated,
+    #[error("Unsupported WAV encoding [{}]", _0)]
+    Unsupported(WavSpec),
+    #[error("Error reading audio: {}", _0)]
+    Io(String),
+}
+
+impl From<io::Error> for WavError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::UnexpectedEof => Self::Truncated,
+            _ => Self::Io(e.to_string()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WavHeader {
+    pub spec: WavSpec,
+    /// Length of the data chunk, unknown for streamed files
+    pub data_len: Option<u32>,
+}
+
+/// Reads the RIFF header up to the start of the data chunk, leaving the reader at the first sample.
+pub async fn read_header<R>(reader: &mut R) -> Result<WavHeader, WavError>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut riff = [0u8; 12];
+    reader
+        .read_exact(&mut riff)
+        .await
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => WavError::NotWav,
+            _ => WavError::from(e),
+        })?;
+    if &riff[0..4] != b"RIFF" || &riff[8..12] != b"WAVE" {
+        return Err(WavError::NotWav);
+    }
+
+    let mut spec = None;
+    loop {
+        let mut chunk = [0u8; 8];
+        reader
+            .read_exact(&mut chunk)
+            .await
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::UnexpectedEof if spec.is_none() => WavError::MissingChunk("fmt"),
+                io::ErrorKind::UnexpectedEof => WavError::MissingChunk("data"),
+                _ => WavError::from(e),
+            })?;
+        let len = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+        match &chunk[0..4] {
+            b"fmt " => spec = Some(read_format(reader, len).await?),
+            b"data" => {
+                let spec = spec.ok_or(WavError::MissingChunk("fmt"))?;
+                let data_len = match len {
+                    0 | u32::MAX => None,
+                    len => Some(len),
+                };
+                return Ok(WavHeader { spec, data_len });
+            }
+            _ => skip(reader, u64::from(len) + u64::from(len % 2)).await?,
+        }
+    }
+}
+
+/// Builds the header of a WAV file holding `data_len` bytes of audio.
+///
+/// Sizes past the 4GiB a RIFF file can hold are saturated, as players do for streamed files.
+pub fn header(spec: WavSpec, data_len: u32) -> Vec<u8> {
+    let block_align = spec.channels.saturating_mul(spec.bits_per_sample) / 8;
+    // Non-PCM formats carry an empty extension and a fact chunk with the number of samples
+    let extended = spec.encoding != WavEncoding::Pcm;
+    let fmt_len: u32 = if extended { 18 } else { 16 };
+    let fact_len: u32 = if extended { 12 } else { 0 };
+
+    let mut header = Vec::with_capacity(28 + (fmt_len + fact_len) as usize);
+    header.extend_from_slice(b"RIFF");
+    let riff_len = (20 + fmt_len + fact_len).saturating_add(data_len);
+    header.extend_from_slice(&riff_len.to_le_bytes());
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend_from_slice(&fmt_len.to_le_bytes());
+    header.extend_from_slice(&u16::from(spec.encoding).to_le_bytes());
+    header.extend_from_slice(&spec.channels.to_le_bytes());
+    header.extend_from_slice(&spec.sample_rate.to_le_bytes());
+    let byte_rate = spec.sample_rate.saturating_mul(u32::from(block_align));
+    header.extend_from_slice(&byte_rate.to_le_bytes());
+    header.extend_from_slice(&block_align.to_le_bytes());
+    header.extend_from_slice(&spec.bits_per_sample.to_le_bytes());
+    if extended {
+        header.extend_from_slice(&0u16.to_le_bytes());
+        header.extend_from_slice(b"fact");
+        header.extend_from_slice(&4u32.to_le_bytes());
+        header.extend_from_slice(&(data_len / u32::from(block_align.max(1))).to_le_bytes());
+    }
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&data_len.to_le_bytes());
+    header
+}
+
+async fn read_format<R>(reader: &mut R, len: u32) -> Result<WavSpec, WavError>
+where
+    R: AsyncRead + Unpin,
+{
+    if len < 16 {
+        return Err(WavError::InvalidFormatChunk);
+    }
+    // Nothing past the extensible format is used, so the rest is skipped rather than buffered
+    let mut fmt = [0u8; FORMAT_CHUNK_LEN];
+    let read = (len as usize).min(FORMAT_CHUNK_LEN);
+    reader.read_exact(&mut fmt[..read]).await?;
+    skip(reader, u64::from(len) - read as u64 + u64::from(len % 2)).await?;
+
+    let u16_at = |i: usize| u16::from_le_bytes([fmt[i], fmt[i + 1]]);
+    let mut format_tag = u16_at(0);
+    if format_tag == FORMAT_EXTENSIBLE {
+        // The actual format tag is the first field of the sub-format GUID
+        if read < FORMAT_CHUNK_LEN {
+            return Err(WavError::InvalidFormatChunk);
+        }
+        format_tag = u16_at(24);
+    }
+    Ok(WavSpec {
+        encoding: WavEncoding::from(format_tag),
+        channels: u16_at(2),
+        sample_rate: u32::from_le_bytes([fmt[4], fmt[5], fmt[6], fmt[7]]),
+        bits_per_sample: u16_at(14),
+    })
+}
+
+async fn skip<R>(reader: &mut R, len: u64) -> Result<(), WavError>
+where
+    R: AsyncRead + Unpin,
+{
+    let skipped = tokio::io::copy(&mut reader.take(len), &mut tokio::io::sink()).await?;
+    if skipped < len {
+        return Err(WavError::Truncated);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::audio::{AudioSpec, SampleFormat};
+    use crate::audio_stream::{AudioStream, ChunkSize};
+    use std::io::Cursor;
+    use tokio_stream::StreamExt;
+
+    pub(crate) fn wav_file(
+        format_tag: u16,
+        channels: u16,
+        rate: u32,
+        bits: u16,
+        data: &[u8],
+    ) -> Vec<u8> {
+        let block_align = channels * bits / 8;
+        let mut wav = vec![];
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(48 + data.len() as u32).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&format_tag.to_le_bytes());
+        wav.extend_from_slice(&channels.to_le_bytes());
+        wav.extend_from_slice(&rate.to_le_bytes());
+        wav.extend_from_slice(&(rate * u32::from(block_align)).to_le_bytes());
+        wav.extend_from_slice(&block_align.to_le_bytes());
+        wav.extend_from_slice(&bits.to_le_bytes());
+        wav.extend_from_slice(b"LIST");
+        wav.extend_from_slice(&3u32.to_le_bytes());
+        wav.extend_from_slice(&[1, 2, 3, 0]);
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        wav.extend_from_slice(data);
+        wav
+    }
+
+    #[tokio::test]
+    async fn test_read_header() {
+        let wav = wav_file(1, 2, 44100, 16, &[0u8; 8]);
+        let header = read_header(&mut Cursor::new(wav))
+            .await
+            .expect("Should parse the header");
+        let spec = WavSpec {
+            encoding: WavEncoding::Pcm,
+            channels: 2,
+            sample_rate: 44100,
+            bits_per_sample: 16,
+        };
+        assert_eq!(
+            header,
+            WavHeader {
+                spec,
+                data_len: Some(8)
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_strips_header() {
+        let mut wav = wav_file(1, 1, 8000, 16, &[1u8; 6]);
+        wav.extend_from_slice(b"junk");
+        let mut audio = AudioStream::from_wav_reader(Cursor::new(wav), ChunkSize::default())
+            .await
+            .expect("Should accept the audio");
+        let chunk = audio.next().await.unwrap().unwrap();
+        assert_eq!(&chunk[..], &[1u8; 6]);
+        assert!(audio.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_reports_spec() {
+        let wav = wav_file(3, 2, 16000, 32, &[0u8; 8]);
+        let audio = AudioStream::from_wav_reader(Cursor::new(wav), ChunkSize::default())
+            .await
+            .expect("Should accept float audio");
+        let spec = AudioSpec {
+            sample_rate: 16000,
+            channels: 2,
+            format: SampleFormat::F32,
+        };
+        assert_eq!(audio.spec(), spec);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_unsupported() {
+        let wav = wav_file(2, 1, 8000, 4, &[0u8; 8]);
+        let error = AudioStream::from_wav_reader(Cursor::new(wav), ChunkSize::default())
+            .await
+            .err()
+            .expect("Should reject ADPCM audio");
+        assert!(matches!(error, WavError::Unsupported(_)));
+    }
+
+    #[tokio::test]
+    async fn test_header_roundtrip() {
+        let spec = WavSpec {
+            encoding: WavEncoding::MuLaw,
+            channels: 1,
+            sample_rate: 8000,
+            bits_per_sample: 8,
+        };
+        let mut wav = header(spec, 4);
+        wav.extend_from_slice(&[0xFF; 4]);
+        let parsed = read_header(&mut Cursor::new(wav))
+            .await
+            .expect("Should parse the header");
+        assert_eq!(
+            parsed,
+            WavHeader {
+                spec,
+                data_len: Some(4)
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_long_format_chunk() {
+        let mut wav = wav_file(1, 1, 8000, 16, &[0u8; 4]);
+        // The chunk claims to be 4GiB long, which must be skipped without being read in memory
+        wav[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+        let error = read_header(&mut Cursor::new(wav))
+            .await
+            .expect_err("Should run out of data skipping the chunk");
+        assert_eq!(error, WavError::Truncated);
+
+        let spec = WavSpec {
+            encoding: WavEncoding::Pcm,
+            channels: 1,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+        };
+        let wav = header(spec, u32::MAX);
+        assert_eq!(&wav[4..8], &u32::MAX.to_le_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_not_wav() {
+        let error = read_header(&mut Cursor::new(vec![0u8; 4]))
+            .await
+            .expect_err("Should not parse raw audio");
+        assert_eq!(error, WavError::NotWav);
+    }
+}
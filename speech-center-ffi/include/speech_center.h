@@ -0,0 +1,246 @@
+/* Generated by cbindgen from src/lib.rs, do not edit. */
+
+#ifndef SPEECH_CENTER_H
+#define SPEECH_CENTER_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Outcome of a call, mirroring the kinds of `SpeechCenterError`.
+typedef enum SpeechCenterStatus {
+  SPEECH_CENTER_STATUS_OK = 0,
+  SPEECH_CENTER_STATUS_INVALID_ARGUMENT,
+  SPEECH_CENTER_STATUS_INVALID_GRAMMAR,
+  SPEECH_CENTER_STATUS_UNAUTHENTICATED,
+  SPEECH_CENTER_STATUS_PERMISSION_DENIED,
+  SPEECH_CENTER_STATUS_UNAVAILABLE,
+  SPEECH_CENTER_STATUS_TIMEOUT,
+  SPEECH_CENTER_STATUS_RESOURCE_EXHAUSTED,
+  SPEECH_CENTER_STATUS_SERVICE,
+  SPEECH_CENTER_STATUS_CONNECTION,
+  SPEECH_CENTER_STATUS_CREDENTIALS,
+  SPEECH_CENTER_STATUS_CONFIG,
+  SPEECH_CENTER_STATUS_INVALID_PARAMETER,
+  SPEECH_CENTER_STATUS_INVALID_AUDIO,
+  SPEECH_CENTER_STATUS_DECODING,
+  SPEECH_CENTER_STATUS_UNSUPPORTED_AUDIO,
+  SPEECH_CENTER_STATUS_IO,
+  // A required pointer was null
+  SPEECH_CENTER_STATUS_NULL_POINTER = 100,
+  // A string was not valid UTF-8
+  SPEECH_CENTER_STATUS_INVALID_UTF8,
+  // The output buffer cannot hold the result, whose size is written instead
+  SPEECH_CENTER_STATUS_BUFFER_TOO_SMALL,
+  // The library panicked, which is a bug
+  SPEECH_CENTER_STATUS_PANIC,
+} SpeechCenterStatus;
+
+// Recognition in progress, fed with audio chunks as they arrive.
+typedef struct SpeechCenterRecognition SpeechCenterRecognition;
+
+// Recognition client handle.
+typedef struct SpeechCenterRecognizer SpeechCenterRecognizer;
+
+// Synthesized audio, owned by the library until freed.
+typedef struct SpeechCenterSynthesis SpeechCenterSynthesis;
+
+// Synthesis client handle.
+typedef struct SpeechCenterSynthesizer SpeechCenterSynthesizer;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Message of the last error raised on the calling thread, or null if none was.
+//
+// The message stays valid until the next failing call on the same thread.
+const char *speech_center_last_error(void);
+
+// Frees a string returned by the library.
+//
+// # Safety
+//
+// The string must have been returned by the library and not freed before.
+void speech_center_string_free(char *string);
+
+// Connects a recognizer to the url, authenticating with the token.
+//
+// Connections are secured with TLS unless `plaintext` is set, meant for local test servers.
+//
+// # Safety
+//
+// Strings must be null-terminated and `recognizer` a valid pointer.
+enum SpeechCenterStatus speech_center_recognizer_new(const char *url,
+                                                     const char *token,
+                                                     bool plaintext,
+                                                     struct SpeechCenterRecognizer **recognizer);
+
+// Frees a recognizer. Recognitions it started remain usable.
+//
+// # Safety
+//
+// The recognizer must not be used after being freed.
+void speech_center_recognizer_free(struct SpeechCenterRecognizer *recognizer);
+
+// Recognises mono 8kHz signed 16-bit PCM with a topic model.
+//
+// The transcript must be freed with `speech_center_string_free`.
+//
+// # Safety
+//
+// Strings must be null-terminated, `audio` hold `len` bytes and `transcript` be a valid pointer.
+enum SpeechCenterStatus speech_center_recognize_topic(const struct SpeechCenterRecognizer *recognizer,
+                                                      const char *language,
+                                                      const char *topic,
+                                                      const uint8_t *audio,
+                                                      size_t len,
+                                                      char **transcript);
+
+// Recognises mono 8kHz signed 16-bit PCM with an ABNF grammar.
+//
+// The transcript must be freed with `speech_center_string_free`.
+//
+// # Safety
+//
+// Strings must be null-terminated, `audio` hold `len` bytes and `transcript` be a valid pointer.
+enum SpeechCenterStatus speech_center_recognize_grammar(const struct SpeechCenterRecognizer *recognizer,
+                                                        const char *grammar,
+                                                        const char *language,
+                                                        const uint8_t *audio,
+                                                        size_t len,
+                                                        char **transcript);
+
+// Starts a recognition with a topic model, to be fed with `speech_center_recognition_push`.
+//
+// # Safety
+//
+// Strings must be null-terminated and `recognition` a valid pointer.
+enum SpeechCenterStatus speech_center_recognition_start_topic(const struct SpeechCenterRecognizer *recognizer,
+                                                              const char *language,
+                                                              const char *topic,
+                                                              struct SpeechCenterRecognition **recognition);
+
+// Starts a recognition with an ABNF grammar, to be fed with `speech_center_recognition_push`.
+//
+// # Safety
+//
+// Strings must be null-terminated and `recognition` a valid pointer.
+enum SpeechCenterStatus speech_center_recognition_start_grammar(const struct SpeechCenterRecognizer *recognizer,
+                                                                const char *grammar,
+                                                                const char *language,
+                                                                struct SpeechCenterRecognition **recognition);
+
+// Sends the next chunk of mono 8kHz signed 16-bit PCM.
+//
+// Fails with `SPEECH_CENTER_STATUS_IO` once the recognition has ended, whose outcome is returned
+// by `speech_center_recognition_finish`.
+//
+// # Safety
+//
+// `audio` must hold `len` bytes.
+enum SpeechCenterStatus speech_center_recognition_push(struct SpeechCenterRecognition *recognition,
+                                                       const uint8_t *audio,
+                                                       size_t len);
+
+// Ends the audio and waits for the transcript, freeing the recognition whatever the outcome.
+//
+// The transcript must be freed with `speech_center_string_free`.
+//
+// # Safety
+//
+// The recognition must not be used afterwards and `transcript` must be a valid pointer.
+enum SpeechCenterStatus speech_center_recognition_finish(struct SpeechCenterRecognition *recognition,
+                                                         char **transcript);
+
+// Cancels a recognition that has not been finished and frees it.
+//
+// # Safety
+//
+// The recognition must not be used afterwards.
+void speech_center_recognition_free(struct SpeechCenterRecognition *recognition);
+
+// Connects a synthesizer to the url, authenticating with the token.
+//
+// Connections are secured with TLS unless `plaintext` is set, meant for local test servers.
+//
+// # Safety
+//
+// Strings must be null-terminated and `synthesizer` a valid pointer.
+enum SpeechCenterStatus speech_center_synthesizer_new(const char *url,
+                                                      const char *token,
+                                                      bool plaintext,
+                                                      struct SpeechCenterSynthesizer **synthesizer);
+
+// Frees a synthesizer.
+//
+// # Safety
+//
+// The synthesizer must not be used after being freed.
+void speech_center_synthesizer_free(struct SpeechCenterSynthesizer *synthesizer);
+
+// Synthesizes the text into the buffer, writing the size of the audio to `written`.
+//
+// The speaker is selected by name and language, such as "tommy" and "en-US", and the format by
+// encoding ("pcm", "alaw" or "mulaw") and header ("wav" or "raw"). If the buffer is too small,
+// fails with `SPEECH_CENTER_STATUS_BUFFER_TOO_SMALL` and writes the size needed instead.
+//
+// # Safety
+//
+// Strings must be null-terminated, `buffer` hold `capacity` bytes and `written` be a valid
+// pointer.
+enum SpeechCenterStatus speech_center_synthesize(const struct SpeechCenterSynthesizer *synthesizer,
+                                                 const char *speaker,
+                                                 const char *language,
+                                                 uint32_t sample_rate,
+                                                 const char *encoding,
+                                                 const char *header,
+                                                 const char *text,
+                                                 uint8_t *buffer,
+                                                 size_t capacity,
+                                                 size_t *written);
+
+// Synthesizes the text like `speech_center_synthesize`, returning audio of any size to read
+// with `speech_center_synthesis_data` and `speech_center_synthesis_len`, and to free with
+// `speech_center_synthesis_free`.
+//
+// # Safety
+//
+// Strings must be null-terminated and `synthesis` a valid pointer.
+enum SpeechCenterStatus speech_center_synthesize_owned(const struct SpeechCenterSynthesizer *synthesizer,
+                                                       const char *speaker,
+                                                       const char *language,
+                                                       uint32_t sample_rate,
+                                                       const char *encoding,
+                                                       const char *header,
+                                                       const char *text,
+                                                       struct SpeechCenterSynthesis **synthesis);
+
+// Audio of a synthesis, valid until the synthesis is freed, or null if the synthesis is.
+//
+// # Safety
+//
+// The synthesis must have been returned by `speech_center_synthesize_owned` and not freed.
+const uint8_t *speech_center_synthesis_data(const struct SpeechCenterSynthesis *synthesis);
+
+// Size in bytes of the audio of a synthesis, 0 if the synthesis is null.
+//
+// # Safety
+//
+// The synthesis must have been returned by `speech_center_synthesize_owned` and not freed.
+size_t speech_center_synthesis_len(const struct SpeechCenterSynthesis *synthesis);
+
+// Frees a synthesis along with its audio.
+//
+// # Safety
+//
+// The synthesis and its audio must not be used after being freed.
+void speech_center_synthesis_free(struct SpeechCenterSynthesis *synthesis);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* SPEECH_CENTER_H */
@@ -0,0 +1,764 @@
+//! C bindings to the Speech Center clients.
+//!
+//! Every fallible function returns a [`SpeechCenterStatus`], writing its results through out
+//! pointers when it succeeds. The message of the last error raised on the calling thread is
+//! available from [`speech_center_last_error`].
+
+use speech_center_client::blocking::{Recognition, RecognitionClient, SynthesisClient};
+use speech_center_client::{
+    AudioFormat, SampleRate, Speaker, SpeechCenterConfig, SpeechCenterError, StaticToken,
+    SynthesisResult, Topic,
+};
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::{ptr, slice};
+
+/// Outcome of a call, mirroring the kinds of `SpeechCenterError`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpeechCenterStatus {
+    Ok = 0,
+    InvalidArgument,
+    InvalidGrammar,
+    Unauthenticated,
+    PermissionDenied,
+    Unavailable,
+    Timeout,
+    ResourceExhausted,
+    Service,
+    Connection,
+    Credentials,
+    Config,
+    InvalidParameter,
+    InvalidAudio,
+    Decoding,
+    UnsupportedAudio,
+    Io,
+    /// A required pointer was null
+    NullPointer = 100,
+    /// A string was not valid UTF-8
+    InvalidUtf8,
+    /// The output buffer cannot hold the result, whose size is written instead
+    BufferTooSmall,
+    /// The library panicked, which is a bug
+    Panic,
+}
+
+impl From<&SpeechCenterError> for SpeechCenterStatus {
+    fn from(error: &SpeechCenterError) -> Self {
+        match error {
+            SpeechCenterError::InvalidArgument(_) => Self::InvalidArgument,
+            SpeechCenterError::InvalidGrammar(_) => Self::InvalidGrammar,
+            SpeechCenterError::Unauthenticated(_) => Self::Unauthenticated,
+            SpeechCenterError::PermissionDenied(_) => Self::PermissionDenied,
+            SpeechCenterError::Unavailable(_) => Self::Unavailable,
+            SpeechCenterError::Timeout(_) => Self::Timeout,
+            SpeechCenterError::ResourceExhausted(_) => Self::ResourceExhausted,
+            SpeechCenterError::Service(_) => Self::Service,
+            SpeechCenterError::Connection { .. } => Self::Connection,
+            SpeechCenterError::Credentials { .. } => Self::Credentials,
+            SpeechCenterError::Config(_) => Self::Config,
+            SpeechCenterError::InvalidParameter(_) => Self::InvalidParameter,
+            SpeechCenterError::InvalidAudio(_) => Self::InvalidAudio,
+            SpeechCenterError::Decoding(_) => Self::Decoding,
+            SpeechCenterError::UnsupportedAudio(_) => Self::UnsupportedAudio,
+            SpeechCenterError::Io { .. } => Self::Io,
+        }
+    }
+}
+
+/// Recognition client handle.
+pub struct SpeechCenterRecognizer(RecognitionClient);
+
+/// Recognition in progress, fed with audio chunks as they arrive.
+pub struct SpeechCenterRecognition(Recognition);
+
+/// Synthesis client handle.
+pub struct SpeechCenterSynthesizer(SynthesisClient);
+
+/// Synthesized audio, owned by the library until freed.
+pub struct SpeechCenterSynthesis(Vec<u8>);
+
+struct Error {
+    status: SpeechCenterStatus,
+    message: String,
+}
+
+impl Error {
+    fn new(status: SpeechCenterStatus, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<SpeechCenterError> for Error {
+    fn from(error: SpeechCenterError) -> Self {
+        Self::new((&error).into(), error.to_string())
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Runs the body of an exported function, recording its error and catching panics.
+fn call(body: impl FnOnce() -> Result<(), Error>) -> SpeechCenterStatus {
+    let error = match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(Ok(())) => return SpeechCenterStatus::Ok,
+        Ok(Err(e)) => e,
+        Err(_) => Error::new(SpeechCenterStatus::Panic, "Unexpected panic"),
+    };
+    let message = CString::new(error.message.replace('\0', " ")).ok();
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+    error.status
+}
+
+fn non_null<T>(pointer: *const T, name: &str) -> Result<*const T, Error> {
+    if pointer.is_null() {
+        return Err(Error::new(
+            SpeechCenterStatus::NullPointer,
+            format!("Null {}", name),
+        ));
+    }
+    Ok(pointer)
+}
+
+unsafe fn string<'a>(pointer: *const c_char, name: &str) -> Result<&'a str, Error> {
+    CStr::from_ptr(non_null(pointer, name)?)
+        .to_str()
+        .map_err(|_| Error::new(SpeechCenterStatus::InvalidUtf8, format!("Invalid {}", name)))
+}
+
+unsafe fn bytes<'a>(pointer: *const u8, len: usize) -> Result<&'a [u8], Error> {
+    if len == 0 {
+        return Ok(&[]);
+    }
+    Ok(slice::from_raw_parts(non_null(pointer, "audio")?, len))
+}
+
+unsafe fn handle<'a, T>(pointer: *const T) -> Result<&'a T, Error> {
+    Ok(&*non_null(pointer, "handle")?)
+}
+
+unsafe fn output<T>(pointer: *mut T, value: T) -> Result<(), Error> {
+    non_null(pointer, "output")?;
+    pointer.write(value);
+    Ok(())
+}
+
+unsafe fn connection(
+    url: *const c_char,
+    token: *const c_char,
+    plaintext: bool,
+) -> Result<(SpeechCenterConfig, Arc<StaticToken>), Error> {
+    let config = SpeechCenterConfig::new(string(url, "url")?).plaintext(plaintext);
+    Ok((config, Arc::new(StaticToken::new(string(token, "token")?))))
+}
+
+fn transcript(transcript: String) -> *mut c_char {
+    CString::new(transcript.replace('\0', " "))
+        .map(CString::into_raw)
+        .unwrap_or(ptr::null_mut())
+}
+
+/// Message of the last error raised on the calling thread, or null if none was.
+///
+/// The message stays valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn speech_center_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |m| m.as_ptr()))
+}
+
+/// Frees a string returned by the library.
+///
+/// # Safety
+///
+/// The string must have been returned by the library and not freed before.
+#[no_mangle]
+pub unsafe extern "C" fn speech_center_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+/// Connects a recognizer to the url, authenticating with the token.
+///
+/// Connections are secured with TLS unless `plaintext` is set, meant for local test servers.
+///
+/// # Safety
+///
+/// Strings must be null-terminated and `recognizer` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn speech_center_recognizer_new(
+    url: *const c_char,
+    token: *const c_char,
+    plaintext: bool,
+    recognizer: *mut *mut SpeechCenterRecognizer,
+) -> SpeechCenterStatus {
+    call(|| {
+        non_null(recognizer, "output")?;
+        let (config, credentials) = connection(url, token, plaintext)?;
+        let client = RecognitionClient::with_config(config, credentials)?;
+        output(
+            recognizer,
+            Box::into_raw(Box::new(SpeechCenterRecognizer(client))),
+        )
+    })
+}
+
+/// Frees a recognizer. Recognitions it started remain usable.
+///
+/// # Safety
+///
+/// The recognizer must not be used after being freed.
+#[no_mangle]
+pub unsafe extern "C" fn speech_center_recognizer_free(recognizer: *mut SpeechCenterRecognizer) {
+    if !recognizer.is_null() {
+        drop(Box::from_raw(recognizer));
+    }
+}
+
+/// Recognises mono 8kHz signed 16-bit PCM with a topic model.
+///
+/// The transcript must be freed with `speech_center_string_free`.
+///
+/// # Safety
+///
+/// Strings must be null-terminated, `audio` hold `len` bytes and `transcript` be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn speech_center_recognize_topic(
+    recognizer: *const SpeechCenterRecognizer,
+    language: *const c_char,
+    topic: *const c_char,
+    audio: *const u8,
+    len: usize,
+    transcript: *mut *mut c_char,
+) -> SpeechCenterStatus {
+    call(|| {
+        non_null(transcript, "output")?;
+        let topic = Topic::from_name(string(topic, "topic")?)?;
+        let res = handle(recognizer)?.0.recognise_with_topic(
+            string(language, "language")?,
+            topic,
+            bytes(audio, len)?.to_vec(),
+        )?;
+        output(transcript, self::transcript(res.transcript))
+    })
+}
+
+/// Recognises mono 8kHz signed 16-bit PCM with an ABNF grammar.
+///
+/// The transcript must be freed with `speech_center_string_free`.
+///
+/// # Safety
+///
+/// Strings must be null-terminated, `audio` hold `len` bytes and `transcript` be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn speech_center_recognize_grammar(
+    recognizer: *const SpeechCenterRecognizer,
+    grammar: *const c_char,
+    language: *const c_char,
+    audio: *const u8,
+    len: usize,
+    transcript: *mut *mut c_char,
+) -> SpeechCenterStatus {
+    call(|| {
+        non_null(transcript, "output")?;
+        let res = handle(recognizer)?.0.recognise_with_grammar(
+            string(grammar, "grammar")?,
+            string(language, "language")?,
+            bytes(audio, len)?.to_vec(),
+        )?;
+        output(transcript, self::transcript(res.transcript))
+    })
+}
+
+/// Starts a recognition with a topic model, to be fed with `speech_center_recognition_push`.
+///
+/// # Safety
+///
+/// Strings must be null-terminated and `recognition` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn speech_center_recognition_start_topic(
+    recognizer: *const SpeechCenterRecognizer,
+    language: *const c_char,
+    topic: *const c_char,
+    recognition: *mut *mut SpeechCenterRecognition,
+) -> SpeechCenterStatus {
+    call(|| {
+        non_null(recognition, "output")?;
+        let topic = Topic::from_name(string(topic, "topic")?)?;
+        let started = handle(recognizer)?
+            .0
+            .start_with_topic(string(language, "language")?, topic);
+        output(
+            recognition,
+            Box::into_raw(Box::new(SpeechCenterRecognition(started))),
+        )
+    })
+}
+
+/// Starts a recognition with an ABNF grammar, to be fed with `speech_center_recognition_push`.
+///
+/// # Safety
+///
+/// Strings must be null-terminated and `recognition` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn speech_center_recognition_start_grammar(
+    recognizer: *const SpeechCenterRecognizer,
+    grammar: *const c_char,
+    language: *const c_char,
+    recognition: *mut *mut SpeechCenterRecognition,
+) -> SpeechCenterStatus {
+    call(|| {
+        non_null(recognition, "output")?;
+        let started = handle(recognizer)?
+            .0
+            .start_with_grammar(string(grammar, "grammar")?, string(language, "language")?);
+        output(
+            recognition,
+            Box::into_raw(Box::new(SpeechCenterRecognition(started))),
+        )
+    })
+}
+
+/// Sends the next chunk of mono 8kHz signed 16-bit PCM.
+///
+/// Fails with `SPEECH_CENTER_STATUS_IO` once the recognition has ended, whose outcome is returned
+/// by `speech_center_recognition_finish`.
+///
+/// # Safety
+///
+/// `audio` must hold `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn speech_center_recognition_push(
+    recognition: *mut SpeechCenterRecognition,
+    audio: *const u8,
+    len: usize,
+) -> SpeechCenterStatus {
+    call(|| {
+        let recognition = &mut *(non_null(recognition, "handle")? as *mut SpeechCenterRecognition);
+        Ok(recognition.0.push(bytes(audio, len)?)?)
+    })
+}
+
+/// Ends the audio and waits for the transcript, freeing the recognition whatever the outcome.
+///
+/// The transcript must be freed with `speech_center_string_free`.
+///
+/// # Safety
+///
+/// The recognition must not be used afterwards and `transcript` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn speech_center_recognition_finish(
+    recognition: *mut SpeechCenterRecognition,
+    transcript: *mut *mut c_char,
+) -> SpeechCenterStatus {
+    call(|| {
+        non_null(recognition, "handle")?;
+        let recognition = Box::from_raw(recognition);
+        non_null(transcript, "output")?;
+        let res = recognition.0.finish()?;
+        output(transcript, self::transcript(res.transcript))
+    })
+}
+
+/// Cancels a recognition that has not been finished and frees it.
+///
+/// # Safety
+///
+/// The recognition must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn speech_center_recognition_free(recognition: *mut SpeechCenterRecognition) {
+    if !recognition.is_null() {
+        drop(Box::from_raw(recognition));
+    }
+}
+
+/// Connects a synthesizer to the url, authenticating with the token.
+///
+/// Connections are secured with TLS unless `plaintext` is set, meant for local test servers.
+///
+/// # Safety
+///
+/// Strings must be null-terminated and `synthesizer` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn speech_center_synthesizer_new(
+    url: *const c_char,
+    token: *const c_char,
+    plaintext: bool,
+    synthesizer: *mut *mut SpeechCenterSynthesizer,
+) -> SpeechCenterStatus {
+    call(|| {
+        non_null(synthesizer, "output")?;
+        let (config, credentials) = connection(url, token, plaintext)?;
+        let client = SynthesisClient::with_config(config, credentials)?;
+        output(
+            synthesizer,
+            Box::into_raw(Box::new(SpeechCenterSynthesizer(client))),
+        )
+    })
+}
+
+/// Frees a synthesizer.
+///
+/// # Safety
+///
+/// The synthesizer must not be used after being freed.
+#[no_mangle]
+pub unsafe extern "C" fn speech_center_synthesizer_free(synthesizer: *mut SpeechCenterSynthesizer) {
+    if !synthesizer.is_null() {
+        drop(Box::from_raw(synthesizer));
+    }
+}
+
+/// Synthesizes the text with the speaker, sample rate and format given by name.
+unsafe fn synthesis(
+    synthesizer: *const SpeechCenterSynthesizer,
+    speaker: *const c_char,
+    language: *const c_char,
+    sample_rate: u32,
+    encoding: *const c_char,
+    header: *const c_char,
+    text: *const c_char,
+) -> Result<SynthesisResult, Error> {
+    let speaker = Speaker::from_name(string(speaker, "speaker")?, string(language, "language")?)?;
+    let sample_rate = SampleRate::try_from(sample_rate)?;
+    let audio_format =
+        AudioFormat::from_str(string(encoding, "encoding")?, string(header, "header")?)?;
+    Ok(handle(synthesizer)?.0.synthesize(
+        speaker,
+        sample_rate,
+        audio_format,
+        string(text, "text")?,
+    )?)
+}
+
+/// Synthesizes the text into the buffer, writing the size of the audio to `written`.
+///
+/// The speaker is selected by name and language, such as "tommy" and "en-US", and the format by
+/// encoding ("pcm", "alaw" or "mulaw") and header ("wav" or "raw"). If the buffer is too small,
+/// fails with `SPEECH_CENTER_STATUS_BUFFER_TOO_SMALL` and writes the size needed instead.
+///
+/// # Safety
+///
+/// Strings must be null-terminated, `buffer` hold `capacity` bytes and `written` be a valid
+/// pointer.
+#[no_mangle]
+pub unsafe extern "C" fn speech_center_synthesize(
+    synthesizer: *const SpeechCenterSynthesizer,
+    speaker: *const c_char,
+    language: *const c_char,
+    sample_rate: u32,
+    encoding: *const c_char,
+    header: *const c_char,
+    text: *const c_char,
+    buffer: *mut u8,
+    capacity: usize,
+    written: *mut usize,
+) -> SpeechCenterStatus {
+    call(|| {
+        non_null(written, "output")?;
+        let res = synthesis(
+            synthesizer,
+            speaker,
+            language,
+            sample_rate,
+            encoding,
+            header,
+            text,
+        )?;
+        let audio = res.audio();
+        written.write(audio.len());
+        if audio.len() > capacity {
+            return Err(Error::new(
+                SpeechCenterStatus::BufferTooSmall,
+                format!(
+                    "Buffer of {} bytes too small for {} bytes of audio",
+                    capacity,
+                    audio.len()
+                ),
+            ));
+        }
+        if !audio.is_empty() {
+            non_null(buffer, "buffer")?;
+            ptr::copy_nonoverlapping(audio.as_ptr(), buffer, audio.len());
+        }
+        Ok(())
+    })
+}
+
+/// Synthesizes the text like `speech_center_synthesize`, returning audio of any size to read
+/// with `speech_center_synthesis_data` and `speech_center_synthesis_len`, and to free with
+/// `speech_center_synthesis_free`.
+///
+/// # Safety
+///
+/// Strings must be null-terminated and `synthesis` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn speech_center_synthesize_owned(
+    synthesizer: *const SpeechCenterSynthesizer,
+    speaker: *const c_char,
+    language: *const c_char,
+    sample_rate: u32,
+    encoding: *const c_char,
+    header: *const c_char,
+    text: *const c_char,
+    synthesis: *mut *mut SpeechCenterSynthesis,
+) -> SpeechCenterStatus {
+    call(|| {
+        non_null(synthesis, "output")?;
+        let res = self::synthesis(
+            synthesizer,
+            speaker,
+            language,
+            sample_rate,
+            encoding,
+            header,
+            text,
+        )?;
+        output(
+            synthesis,
+            Box::into_raw(Box::new(SpeechCenterSynthesis(res.audio().to_vec()))),
+        )
+    })
+}
+
+/// Audio of a synthesis, valid until the synthesis is freed, or null if the synthesis is.
+///
+/// # Safety
+///
+/// The synthesis must have been returned by `speech_center_synthesize_owned` and not freed.
+#[no_mangle]
+pub unsafe extern "C" fn speech_center_synthesis_data(
+    synthesis: *const SpeechCenterSynthesis,
+) -> *const u8 {
+    synthesis
+        .as_ref()
+        .map_or(ptr::null(), |synthesis| synthesis.0.as_ptr())
+}
+
+/// Size in bytes of the audio of a synthesis, 0 if the synthesis is null.
+///
+/// # Safety
+///
+/// The synthesis must have been returned by `speech_center_synthesize_owned` and not freed.
+#[no_mangle]
+pub unsafe extern "C" fn speech_center_synthesis_len(
+    synthesis: *const SpeechCenterSynthesis,
+) -> usize {
+    synthesis.as_ref().map_or(0, |synthesis| synthesis.0.len())
+}
+
+/// Frees a synthesis along with its audio.
+///
+/// # Safety
+///
+/// The synthesis and its audio must not be used after being freed.
+#[no_mangle]
+pub unsafe extern "C" fn speech_center_synthesis_free(synthesis: *mut SpeechCenterSynthesis) {
+    if !synthesis.is_null() {
+        drop(Box::from_raw(synthesis));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use speech_center_client::testkit::{audio_digest, Match, MockServer, Reply};
+
+    fn c(s: &str) -> CString {
+        CString::new(s).unwrap()
+    }
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(speech_center_last_error()) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn test_recognition() {
+        let audio = vec![1u8; 8000];
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let server = runtime
+            .block_on(
+                MockServer::builder()
+                    .token("token")
+                    .respond(
+                        Match::Audio(audio_digest(&audio)),
+                        Reply::Transcript("hello".to_string()),
+                    )
+                    .start(),
+            )
+            .unwrap();
+        let (url, language, topic) = (c(&server.url()), c("en-US"), c("generic"));
+        unsafe {
+            let mut recognizer = ptr::null_mut();
+            let status = speech_center_recognizer_new(
+                url.as_ptr(),
+                c("token").as_ptr(),
+                true,
+                &mut recognizer,
+            );
+            assert_eq!(status, SpeechCenterStatus::Ok);
+
+            let mut transcript = ptr::null_mut();
+            let status = speech_center_recognize_topic(
+                recognizer,
+                language.as_ptr(),
+                topic.as_ptr(),
+                audio.as_ptr(),
+                audio.len(),
+                &mut transcript,
+            );
+            assert_eq!(status, SpeechCenterStatus::Ok);
+            assert_eq!(CStr::from_ptr(transcript).to_str(), Ok("hello"));
+            speech_center_string_free(transcript);
+
+            let mut recognition = ptr::null_mut();
+            let status = speech_center_recognition_start_topic(
+                recognizer,
+                language.as_ptr(),
+                topic.as_ptr(),
+                &mut recognition,
+            );
+            assert_eq!(status, SpeechCenterStatus::Ok);
+            for chunk in audio.chunks(3000) {
+                let status =
+                    speech_center_recognition_push(recognition, chunk.as_ptr(), chunk.len());
+                assert_eq!(status, SpeechCenterStatus::Ok);
+            }
+            let mut transcript = ptr::null_mut();
+            let status = speech_center_recognition_finish(recognition, &mut transcript);
+            assert_eq!(status, SpeechCenterStatus::Ok);
+            assert_eq!(CStr::from_ptr(transcript).to_str(), Ok("hello"));
+            speech_center_string_free(transcript);
+
+            let status = speech_center_recognize_topic(
+                recognizer,
+                language.as_ptr(),
+                c("weather").as_ptr(),
+                audio.as_ptr(),
+                audio.len(),
+                &mut transcript,
+            );
+            assert_eq!(status, SpeechCenterStatus::InvalidParameter);
+            assert!(last_error().contains("weather"));
+            let status = speech_center_recognize_topic(
+                recognizer,
+                language.as_ptr(),
+                topic.as_ptr(),
+                audio.as_ptr(),
+                audio.len(),
+                ptr::null_mut(),
+            );
+            assert_eq!(status, SpeechCenterStatus::NullPointer);
+            assert_eq!(server.calls(), 2);
+            speech_center_recognizer_free(recognizer);
+        }
+    }
+
+    #[test]
+    fn test_synthesis() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let server = runtime
+            .block_on(
+                MockServer::builder()
+                    .token("token")
+                    .respond(Match::Any, Reply::Audio(vec![7; 32]))
+                    .start(),
+            )
+            .unwrap();
+        let url = c(&server.url());
+        let args = [c("tommy"), c("en-US"), c("pcm"), c("raw"), c("hello")];
+        let synthesize = |synthesizer, buffer: &mut [u8], written: &mut usize| unsafe {
+            speech_center_synthesize(
+                synthesizer,
+                args[0].as_ptr(),
+                args[1].as_ptr(),
+                8000,
+                args[2].as_ptr(),
+                args[3].as_ptr(),
+                args[4].as_ptr(),
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                written,
+            )
+        };
+        let synthesize_owned = |synthesizer, synthesis: *mut *mut SpeechCenterSynthesis| unsafe {
+            speech_center_synthesize_owned(
+                synthesizer,
+                args[0].as_ptr(),
+                args[1].as_ptr(),
+                8000,
+                args[2].as_ptr(),
+                args[3].as_ptr(),
+                args[4].as_ptr(),
+                synthesis,
+            )
+        };
+        unsafe {
+            let mut synthesizer = ptr::null_mut();
+            let status = speech_center_synthesizer_new(
+                url.as_ptr(),
+                c("token").as_ptr(),
+                true,
+                &mut synthesizer,
+            );
+            assert_eq!(status, SpeechCenterStatus::Ok);
+
+            let (mut buffer, mut written) = ([0; 16], 0);
+            let status = synthesize(synthesizer, &mut buffer, &mut written);
+            assert_eq!(status, SpeechCenterStatus::BufferTooSmall);
+            assert_eq!(written, 32);
+            let mut buffer = [0; 64];
+            let status = synthesize(synthesizer, &mut buffer, &mut written);
+            assert_eq!(status, SpeechCenterStatus::Ok);
+            assert_eq!(&buffer[..written], [7; 32]);
+
+            let mut synthesis = ptr::null_mut();
+            let status = synthesize_owned(synthesizer, &mut synthesis);
+            assert_eq!(status, SpeechCenterStatus::Ok);
+            let audio = slice::from_raw_parts(
+                speech_center_synthesis_data(synthesis),
+                speech_center_synthesis_len(synthesis),
+            );
+            assert_eq!(audio, [7; 32]);
+            speech_center_synthesis_free(synthesis);
+            let status = synthesize_owned(synthesizer, ptr::null_mut());
+            assert_eq!(status, SpeechCenterStatus::NullPointer);
+            assert_eq!(server.calls(), 3);
+            speech_center_synthesizer_free(synthesizer);
+
+            let status = speech_center_synthesizer_new(
+                url.as_ptr(),
+                c("wrong").as_ptr(),
+                true,
+                &mut synthesizer,
+            );
+            assert_eq!(status, SpeechCenterStatus::Ok);
+            let status = synthesize(synthesizer, &mut buffer, &mut written);
+            assert_eq!(status, SpeechCenterStatus::Unauthenticated);
+            speech_center_synthesizer_free(synthesizer);
+
+            let status =
+                speech_center_synthesizer_new(ptr::null(), ptr::null(), true, &mut synthesizer);
+            assert_eq!(status, SpeechCenterStatus::NullPointer);
+            assert_eq!(last_error(), "Null url");
+            let status = speech_center_synthesizer_new(
+                url.as_ptr(),
+                c("token").as_ptr(),
+                true,
+                ptr::null_mut(),
+            );
+            assert_eq!(status, SpeechCenterStatus::NullPointer);
+            assert_eq!(last_error(), "Null output");
+        }
+    }
+}
@@ -0,0 +1,112 @@
+/*
+ * Exercises the C API against the mock server of speech-center-client/examples/mock_server.rs:
+ *
+ *   cargo build -p speech-center-ffi
+ *   cc speech-center-ffi/tests/test.c -Ispeech-center-ffi/include -Ltarget/debug -lspeech_center -o target/test-ffi
+ *   cargo run -q -p speech-center-client --features testkit --example mock_server > target/mock-url &
+ *   LD_LIBRARY_PATH=target/debug ./target/test-ffi "$(cat target/mock-url)"
+ */
+#include <speech_center.h>
+#include <stdio.h>
+#include <string.h>
+
+static int failures = 0;
+
+#define CHECK(condition)                                                    \
+    do {                                                                    \
+        if (!(condition)) {                                                 \
+            const char *error = speech_center_last_error();                 \
+            fprintf(stderr, "%s:%d: check failed: %s (last error: %s)\n",   \
+                    __FILE__, __LINE__, #condition, error ? error : "none"); \
+            failures++;                                                     \
+        }                                                                   \
+    } while (0)
+
+static void test_recognition(const char *url, const uint8_t *audio, size_t len) {
+    SpeechCenterRecognizer *recognizer = NULL;
+    CHECK(speech_center_recognizer_new(url, "token", true, &recognizer) == SPEECH_CENTER_STATUS_OK);
+
+    char *transcript = NULL;
+    CHECK(speech_center_recognize_topic(recognizer, "en-US", "generic", audio, len, &transcript) ==
+          SPEECH_CENTER_STATUS_OK);
+    CHECK(transcript && strcmp(transcript, "hello world") == 0);
+    speech_center_string_free(transcript);
+
+    SpeechCenterRecognition *recognition = NULL;
+    CHECK(speech_center_recognition_start_topic(recognizer, "en-US", "generic", &recognition) ==
+          SPEECH_CENTER_STATUS_OK);
+    for (size_t offset = 0; offset < len; offset += 320) {
+        size_t chunk = len - offset < 320 ? len - offset : 320;
+        CHECK(speech_center_recognition_push(recognition, audio + offset, chunk) ==
+              SPEECH_CENTER_STATUS_OK);
+    }
+    transcript = NULL;
+    CHECK(speech_center_recognition_finish(recognition, &transcript) == SPEECH_CENTER_STATUS_OK);
+    CHECK(transcript && strcmp(transcript, "hello world") == 0);
+    speech_center_string_free(transcript);
+
+    CHECK(speech_center_recognize_topic(recognizer, "en-US", "weather", audio, len, &transcript) ==
+          SPEECH_CENTER_STATUS_INVALID_PARAMETER);
+    CHECK(speech_center_last_error() != NULL);
+    CHECK(speech_center_recognize_grammar(recognizer, "builtin:grammar/digits", "en-US", audio, len,
+                                          NULL) == SPEECH_CENTER_STATUS_NULL_POINTER);
+    speech_center_recognizer_free(recognizer);
+
+    CHECK(speech_center_recognizer_new(url, "wrong", true, &recognizer) == SPEECH_CENTER_STATUS_OK);
+    CHECK(speech_center_recognize_topic(recognizer, "en-US", "generic", audio, len, &transcript) ==
+          SPEECH_CENTER_STATUS_UNAUTHENTICATED);
+    speech_center_recognizer_free(recognizer);
+}
+
+static void test_synthesis(const char *url) {
+    SpeechCenterSynthesizer *synthesizer = NULL;
+    CHECK(speech_center_synthesizer_new(url, "token", true, &synthesizer) == SPEECH_CENTER_STATUS_OK);
+
+    uint8_t small[16], buffer[4096];
+    size_t written = 0;
+    CHECK(speech_center_synthesize(synthesizer, "tommy", "en-US", 8000, "pcm", "raw", "hello world",
+                                   small, sizeof(small), &written) ==
+          SPEECH_CENTER_STATUS_BUFFER_TOO_SMALL);
+    CHECK(written == 1600);
+    CHECK(speech_center_synthesize(synthesizer, "tommy", "en-US", 8000, "pcm", "raw", "hello world",
+                                   buffer, sizeof(buffer), &written) == SPEECH_CENTER_STATUS_OK);
+    CHECK(written == 1600 && buffer[0] == 1 && buffer[written - 1] == 1);
+    CHECK(speech_center_synthesize(synthesizer, "tommy", "en-US", 16000, "pcm", "raw", "hello world",
+                                   buffer, sizeof(buffer), &written) ==
+          SPEECH_CENTER_STATUS_INVALID_PARAMETER);
+
+    SpeechCenterSynthesis *synthesis = NULL;
+    CHECK(speech_center_synthesize_owned(synthesizer, "tommy", "en-US", 8000, "pcm", "raw",
+                                         "hello world", &synthesis) == SPEECH_CENTER_STATUS_OK);
+    const uint8_t *audio = speech_center_synthesis_data(synthesis);
+    size_t len = speech_center_synthesis_len(synthesis);
+    CHECK(len == 1600 && audio[0] == 1 && audio[len - 1] == 1);
+    speech_center_synthesis_free(synthesis);
+    CHECK(speech_center_synthesize_owned(synthesizer, "tommy", "en-US", 8000, "pcm", "raw",
+                                         "hello world", NULL) == SPEECH_CENTER_STATUS_NULL_POINTER);
+    speech_center_synthesizer_free(synthesizer);
+
+    CHECK(speech_center_synthesizer_new(NULL, "token", true, &synthesizer) ==
+          SPEECH_CENTER_STATUS_NULL_POINTER);
+    CHECK(speech_center_synthesizer_new(url, "token", true, NULL) ==
+          SPEECH_CENTER_STATUS_NULL_POINTER);
+}
+
+int main(int argc, char **argv) {
+    if (argc != 2) {
+        fprintf(stderr, "usage: %s <mock server url>\n", argv[0]);
+        return 2;
+    }
+    uint8_t audio[8000];
+    memset(audio, 1, sizeof(audio));
+
+    test_recognition(argv[1], audio, sizeof(audio));
+    test_synthesis(argv[1]);
+
+    if (failures) {
+        fprintf(stderr, "%d checks failed\n", failures);
+        return 1;
+    }
+    printf("All checks passed\n");
+    return 0;
+}
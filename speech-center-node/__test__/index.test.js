@@ -0,0 +1,55 @@
+const assert = require('assert')
+const { spawn } = require('child_process')
+const path = require('path')
+const { Readable } = require('stream')
+const { after, before, test } = require('node:test')
+const { RecognitionClient, SynthesisClient } = require('..')
+
+// Answers "hello world" to any recognition and 1600 bytes of audio to any synthesis
+const MOCK_SERVER = path.join(__dirname, '..', '..', 'target', 'debug', 'examples', 'mock_server')
+const options = { plaintext: true }
+let server
+let url
+
+before(async () => {
+  server = spawn(MOCK_SERVER, { stdio: ['ignore', 'pipe', 'inherit'] })
+  url = await new Promise((resolve, reject) => {
+    server.stdout.once('data', (data) => resolve(data.toString().trim()))
+    server.once('error', reject)
+  })
+})
+
+after(() => server.kill())
+
+test('recognizes buffers and streams', async () => {
+  const client = await RecognitionClient.connect(url, 'token', options)
+  const audio = Buffer.alloc(8000, 1)
+  assert.strictEqual(await client.recognize(audio, { language: 'en-US', topic: 'generic' }), 'hello world')
+
+  const chunks = Readable.from([audio.subarray(0, 3000), audio.subarray(3000)])
+  const grammar = { language: 'en-US', grammar: 'builtin:digits' }
+  assert.strictEqual(await client.recognize(chunks, grammar), 'hello world')
+})
+
+test('rejects with the kind of error', async () => {
+  const client = await RecognitionClient.connect(url, 'wrong', options)
+  const audio = Buffer.alloc(160)
+  await assert.rejects(client.recognize(audio, { language: 'en-US', topic: 'generic' }), {
+    code: 'Unauthenticated',
+  })
+  await assert.rejects(client.recognize(audio, { language: 'en-US', topic: 'weather' }), {
+    code: 'InvalidParameter',
+  })
+  await assert.rejects(client.recognize('audio', { language: 'en-US', topic: 'generic' }), TypeError)
+})
+
+test('synthesizes with format metadata', async () => {
+  const client = await SynthesisClient.connect(url, 'token', options)
+  const result = await client.synthesize('hello world', { speaker: 'tommy', header: 'raw' })
+  assert.deepStrictEqual(result.audio, Buffer.alloc(1600, 1))
+  assert.deepStrictEqual(
+    { ...result, audio: undefined },
+    { audio: undefined, encoding: 'pcm', header: 'raw', sampleRate: 8000, channels: 1, bitsPerSample: 16, duration: 0.1 },
+  )
+  await assert.rejects(client.synthesize('hello world', { speaker: 'nobody' }), { code: 'InvalidParameter' })
+})
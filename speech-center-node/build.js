@@ -0,0 +1,15 @@
+// Builds the native module with cargo and copies it next to index.js as speech-center.node.
+const { execFileSync } = require('child_process')
+const fs = require('fs')
+const path = require('path')
+
+const release = process.argv.includes('--release')
+const args = ['build', '-p', 'speech-center-node'].concat(release ? ['--release'] : [])
+execFileSync('cargo', args, { stdio: 'inherit', cwd: __dirname })
+
+const library = {
+  darwin: 'libspeech_center_node.dylib',
+  win32: 'speech_center_node.dll',
+}[process.platform] || 'libspeech_center_node.so'
+const target = path.join(__dirname, '..', 'target', release ? 'release' : 'debug', library)
+fs.copyFileSync(target, path.join(__dirname, 'speech-center.node'))
@@ -0,0 +1,77 @@
+import type { Readable } from 'stream'
+
+export type ErrorCode =
+  | 'InvalidArgument'
+  | 'InvalidGrammar'
+  | 'Unauthenticated'
+  | 'PermissionDenied'
+  | 'Unavailable'
+  | 'Timeout'
+  | 'ResourceExhausted'
+  | 'Service'
+  | 'Connection'
+  | 'Credentials'
+  | 'Config'
+  | 'InvalidParameter'
+  | 'InvalidAudio'
+  | 'Decoding'
+  | 'UnsupportedAudio'
+  | 'Io'
+
+/** Error rejecting the promises of the clients. */
+export interface SpeechCenterError extends Error {
+  code: ErrorCode
+}
+
+export interface ConnectOptions {
+  /** Disables TLS, meant for local test servers */
+  plaintext?: boolean
+  /** Converts audio in any supported format to mono 8kHz PCM16 before recognising it */
+  audioConversion?: boolean
+}
+
+export type Topic = 'generic' | 'banking' | 'telco'
+
+/** Language and either a topic or an ABNF grammar to recognise with. */
+export type RecognizeOptions = { language: string } & (
+  | { topic: Topic; grammar?: undefined }
+  | { grammar: string; topic?: undefined }
+)
+
+export class RecognitionClient {
+  static connect(url: string, token: string, options?: ConnectOptions): Promise<RecognitionClient>
+  /** Recognises mono 8kHz signed 16-bit PCM, streamed as it is read from a Readable. */
+  recognize(audio: Buffer | Readable | AsyncIterable<Uint8Array>, options: RecognizeOptions): Promise<string>
+}
+
+export type Encoding = 'pcm' | 'alaw' | 'mulaw'
+export type Header = 'wav' | 'raw'
+
+export interface SynthesizeOptions {
+  /** Name of the voice, such as tommy */
+  speaker: string
+  /** Language of the voice, en-US by default */
+  language?: string
+  /** 8000 by default */
+  sampleRate?: number
+  /** pcm by default */
+  encoding?: Encoding
+  /** wav by default */
+  header?: Header
+}
+
+export interface SynthesisResult {
+  audio: Buffer
+  encoding: Encoding
+  header: Header
+  sampleRate: number
+  channels: number
+  bitsPerSample: number
+  /** Playing time in seconds */
+  duration: number
+}
+
+export class SynthesisClient {
+  static connect(url: string, token: string, options?: ConnectOptions): Promise<SynthesisClient>
+  synthesize(text: string, options: SynthesizeOptions): Promise<SynthesisResult>
+}
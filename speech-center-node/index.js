@@ -0,0 +1,71 @@
+const native = require('./speech-center.node')
+
+// Native errors carry the kind of SpeechCenterError as a prefix: "[Unauthenticated] ..."
+function withCode(error) {
+  const match = /^\[(\w+)\] /.exec(error.message)
+  if (match) {
+    error.code = match[1]
+    error.message = error.message.slice(match[0].length)
+  }
+  return error
+}
+
+async function call(promise) {
+  try {
+    return await promise
+  } catch (error) {
+    throw withCode(error)
+  }
+}
+
+function isReadable(audio) {
+  return audio != null && typeof audio[Symbol.asyncIterator] === 'function'
+}
+
+class RecognitionClient {
+  constructor(inner) {
+    this.inner = inner
+  }
+
+  static async connect(url, token, options) {
+    return new RecognitionClient(await call(native.RecognitionClient.connect(url, token, options)))
+  }
+
+  async recognize(audio, options) {
+    if (Buffer.isBuffer(audio)) {
+      return call(this.inner.recognize(audio, options))
+    }
+    if (!isReadable(audio)) {
+      throw new TypeError('Audio must be a Buffer or a Readable stream')
+    }
+    const recognition = await call(this.inner.start(options))
+    try {
+      for await (const chunk of audio) {
+        // The recognition ended early, finishing it tells why
+        if (!(await recognition.push(Buffer.from(chunk)))) {
+          break
+        }
+      }
+    } catch (error) {
+      recognition.cancel()
+      throw error
+    }
+    return call(recognition.finish())
+  }
+}
+
+class SynthesisClient {
+  constructor(inner) {
+    this.inner = inner
+  }
+
+  static async connect(url, token, options) {
+    return new SynthesisClient(await call(native.SynthesisClient.connect(url, token, options)))
+  }
+
+  async synthesize(text, options) {
+    return call(this.inner.synthesize(text, options))
+  }
+}
+
+module.exports = { RecognitionClient, SynthesisClient }
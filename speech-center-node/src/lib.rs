@@ -0,0 +1,267 @@
+//! Node.js bindings to the Speech Center clients.
+//!
+//! The native classes are wrapped by `index.js`, which adds stream input and error codes.
+
+#[macro_use]
+extern crate napi_derive;
+
+use bytes::Bytes;
+use napi::bindgen_prelude::Buffer;
+use speech_center_client::{
+    AudioFormat, AudioStream, ChunkSize, Recognizer, Resource, SampleFormat, SampleRate, Speaker,
+    SpeechCenterConfig, SpeechCenterError, StaticToken, Topic,
+};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::ReceiverStream;
+
+/// Chunks queued while the previous ones are being sent.
+const QUEUED_CHUNKS: usize = 16;
+
+/// Rejects with the kind of error as a prefix, turned into the `code` of the error by `index.js`.
+fn to_js(error: SpeechCenterError) -> napi::Error {
+    let kind = match &error {
+        SpeechCenterError::InvalidArgument(_) => "InvalidArgument",
+        SpeechCenterError::InvalidGrammar(_) => "InvalidGrammar",
+        SpeechCenterError::Unauthenticated(_) => "Unauthenticated",
+        SpeechCenterError::PermissionDenied(_) => "PermissionDenied",
+        SpeechCenterError::Unavailable(_) => "Unavailable",
+        SpeechCenterError::Timeout(_) => "Timeout",
+        SpeechCenterError::ResourceExhausted(_) => "ResourceExhausted",
+        SpeechCenterError::Service(_) => "Service",
+        SpeechCenterError::Connection { .. } => "Connection",
+        SpeechCenterError::Credentials { .. } => "Credentials",
+        SpeechCenterError::Config(_) => "Config",
+        SpeechCenterError::InvalidParameter(_) => "InvalidParameter",
+        SpeechCenterError::InvalidAudio(_) => "InvalidAudio",
+        SpeechCenterError::Decoding(_) => "Decoding",
+        SpeechCenterError::UnsupportedAudio(_) => "UnsupportedAudio",
+        SpeechCenterError::Io { .. } => "Io",
+    };
+    napi::Error::from_reason(format!("[{}] {}", kind, error))
+}
+
+#[napi(object)]
+pub struct ConnectOptions {
+    /// Disables TLS, meant for local test servers
+    pub plaintext: Option<bool>,
+    /// Converts audio in any supported format to mono 8kHz PCM16 before recognising it
+    pub audio_conversion: Option<bool>,
+}
+
+fn connection(url: &str, options: &Option<ConnectOptions>) -> SpeechCenterConfig {
+    let plaintext = options.as_ref().and_then(|o| o.plaintext);
+    SpeechCenterConfig::new(url).plaintext(plaintext.unwrap_or(false))
+}
+
+#[napi(object)]
+pub struct RecognizeOptions {
+    pub language: String,
+    /// Statistical model to use: generic, banking or telco
+    pub topic: Option<String>,
+    /// ABNF grammar to use instead of a topic
+    pub grammar: Option<String>,
+}
+
+impl RecognizeOptions {
+    fn resource(&self) -> Result<Resource, SpeechCenterError> {
+        match (&self.topic, &self.grammar) {
+            (Some(topic), None) => Topic::from_name(topic).map(Resource::Topic),
+            (None, Some(grammar)) => Ok(Resource::Grammar(grammar.clone())),
+            _ => Err(SpeechCenterError::InvalidParameter(
+                "Either a topic or a grammar is required".to_string(),
+            )),
+        }
+    }
+}
+
+#[napi]
+pub struct RecognitionClient {
+    inner: speech_center_client::RecognitionClient,
+}
+
+#[napi]
+impl RecognitionClient {
+    #[napi]
+    pub async fn connect(
+        url: String,
+        token: String,
+        options: Option<ConnectOptions>,
+    ) -> napi::Result<RecognitionClient> {
+        let config = connection(&url, &options);
+        let audio_conversion = options.and_then(|o| o.audio_conversion);
+        let inner = speech_center_client::RecognitionClient::with_config(
+            config,
+            Arc::new(StaticToken::new(token)),
+        )
+        .await
+        .map_err(to_js)?
+        .with_audio_conversion(audio_conversion.unwrap_or(false));
+        Ok(Self { inner })
+    }
+
+    #[napi]
+    pub async fn recognize(
+        &self,
+        audio: Buffer,
+        options: RecognizeOptions,
+    ) -> napi::Result<String> {
+        let resource = options.resource().map_err(to_js)?;
+        let res = self
+            .inner
+            .recognise(&options.language, resource, audio.to_vec())
+            .await;
+        res.map(|r| r.transcript).map_err(to_js)
+    }
+
+    /// Starts a recognition of audio pushed as it arrives.
+    #[napi]
+    pub async fn start(&self, options: RecognizeOptions) -> napi::Result<Recognition> {
+        let resource = options.resource().map_err(to_js)?;
+        let (tx, rx) = mpsc::channel(QUEUED_CHUNKS);
+        let audio = AudioStream::from_stream(ReceiverStream::new(rx), ChunkSize::default());
+        let inner = self.inner.clone();
+        let result = tokio::spawn(async move {
+            inner
+                .recognise_stream(&options.language, resource, audio)
+                .await
+        });
+        Ok(Recognition {
+            audio: Mutex::new(Some(tx)),
+            result: Mutex::new(Some(result)),
+        })
+    }
+}
+
+type RecognitionResult = speech_center_client::Result<speech_center_client::RecognitionResult>;
+
+#[napi]
+pub struct Recognition {
+    audio: Mutex<Option<mpsc::Sender<Bytes>>>,
+    result: Mutex<Option<JoinHandle<RecognitionResult>>>,
+}
+
+#[napi]
+impl Recognition {
+    /// Queues a chunk of audio, resolving to false if the recognition has already ended.
+    #[napi]
+    pub async fn push(&self, audio: Buffer) -> bool {
+        let tx = self.audio.lock().unwrap().clone();
+        match tx {
+            Some(tx) => tx.send(Bytes::from(audio.to_vec())).await.is_ok(),
+            None => false,
+        }
+    }
+
+    /// Ends the audio and waits for the transcript.
+    #[napi]
+    pub async fn finish(&self) -> napi::Result<String> {
+        self.audio.lock().unwrap().take();
+        let result = self.result.lock().unwrap().take();
+        let result =
+            result.ok_or_else(|| napi::Error::from_reason("Recognition already finished"))?;
+        let res = result
+            .await
+            .map_err(|e| napi::Error::from_reason(format!("Recognition failed: {}", e)))?;
+        res.map(|r| r.transcript).map_err(to_js)
+    }
+
+    /// Abandons the recognition.
+    #[napi]
+    pub fn cancel(&self) {
+        self.audio.lock().unwrap().take();
+        if let Some(result) = self.result.lock().unwrap().take() {
+            result.abort();
+        }
+    }
+}
+
+#[napi(object)]
+pub struct SynthesizeOptions {
+    /// Name of the voice, such as tommy
+    pub speaker: String,
+    /// Language of the voice, en-US by default
+    pub language: Option<String>,
+    /// 8000 by default
+    pub sample_rate: Option<u32>,
+    /// pcm, alaw or mulaw, pcm by default
+    pub encoding: Option<String>,
+    /// wav or raw, wav by default
+    pub header: Option<String>,
+}
+
+#[napi(object)]
+pub struct SynthesisResult {
+    pub audio: Buffer,
+    pub encoding: String,
+    pub header: String,
+    pub sample_rate: u32,
+    pub channels: u32,
+    pub bits_per_sample: u32,
+    /// Playing time in seconds
+    pub duration: f64,
+}
+
+#[napi]
+pub struct SynthesisClient {
+    inner: speech_center_client::SynthesisClient,
+}
+
+#[napi]
+impl SynthesisClient {
+    #[napi]
+    pub async fn connect(
+        url: String,
+        token: String,
+        options: Option<ConnectOptions>,
+    ) -> napi::Result<SynthesisClient> {
+        let config = connection(&url, &options);
+        let inner = speech_center_client::SynthesisClient::with_config(
+            config,
+            Arc::new(StaticToken::new(token)),
+        )
+        .await
+        .map_err(to_js)?;
+        Ok(Self { inner })
+    }
+
+    #[napi]
+    pub async fn synthesize(
+        &self,
+        text: String,
+        options: SynthesizeOptions,
+    ) -> napi::Result<SynthesisResult> {
+        let language = options.language.as_deref().unwrap_or("en-US");
+        let encoding = options.encoding.as_deref().unwrap_or("pcm");
+        let header = options.header.as_deref().unwrap_or("wav");
+        let speaker = Speaker::from_name(&options.speaker, language).map_err(to_js)?;
+        let rate = SampleRate::try_from(options.sample_rate.unwrap_or(8000)).map_err(to_js)?;
+        let audio_format = AudioFormat::from_str(encoding, header).map_err(to_js)?;
+        let res = self
+            .inner
+            .synthesize(speaker, rate, audio_format, &text)
+            .await
+            .map_err(to_js)?;
+        let spec = res.spec();
+        Ok(SynthesisResult {
+            audio: res.audio().to_vec().into(),
+            encoding: match spec.format {
+                SampleFormat::MuLaw => "mulaw",
+                SampleFormat::ALaw => "alaw",
+                _ => "pcm",
+            }
+            .to_string(),
+            header: if res.audio_format.has_header() {
+                "wav"
+            } else {
+                "raw"
+            }
+            .to_string(),
+            sample_rate: spec.sample_rate,
+            channels: u32::from(spec.channels),
+            bits_per_sample: spec.format.width() as u32 * 8,
+            duration: res.duration().as_secs_f64(),
+        })
+    }
+}
@@ -0,0 +1,76 @@
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use speech_center_client::SpeechCenterError as Error;
+
+create_exception!(
+    speech_center,
+    SpeechCenterError,
+    PyException,
+    "Base class of the errors raised by the clients."
+);
+
+/// Declares the subclasses of `SpeechCenterError` and registers them all in the module.
+macro_rules! exceptions {
+    ($($name:ident),* $(,)?) => {
+        $(create_exception!(speech_center, $name, SpeechCenterError);)*
+
+        pub(crate) fn register(m: &Bound<'_, PyModule>) -> PyResult<()> {
+            m.add("SpeechCenterError", m.py().get_type::<SpeechCenterError>())?;
+            $(m.add(stringify!($name), m.py().get_type::<$name>())?;)*
+            Ok(())
+        }
+    };
+}
+
+exceptions!(
+    InvalidArgumentError,
+    InvalidGrammarError,
+    UnauthenticatedError,
+    PermissionDeniedError,
+    UnavailableError,
+    DeadlineExceededError,
+    ResourceExhaustedError,
+    ServiceError,
+    ConnectionFailedError,
+    CredentialsError,
+    ConfigError,
+    InvalidParameterError,
+    InvalidAudioError,
+    DecodingError,
+    UnsupportedAudioError,
+    IoError,
+);
+
+/// Raises the exception matching the kind of error.
+///
+/// Errors returned by the service also carry the gRPC `code` and the number of `attempts` made.
+pub(crate) fn to_py(error: Error) -> PyErr {
+    let message = error.to_string();
+    let exception = match &error {
+        Error::InvalidArgument(_) => InvalidArgumentError::new_err(message),
+        Error::InvalidGrammar(_) => InvalidGrammarError::new_err(message),
+        Error::Unauthenticated(_) => UnauthenticatedError::new_err(message),
+        Error::PermissionDenied(_) => PermissionDeniedError::new_err(message),
+        Error::Unavailable(_) => UnavailableError::new_err(message),
+        Error::Timeout(_) => DeadlineExceededError::new_err(message),
+        Error::ResourceExhausted(_) => ResourceExhaustedError::new_err(message),
+        Error::Service(_) => ServiceError::new_err(message),
+        Error::Connection { .. } => ConnectionFailedError::new_err(message),
+        Error::Credentials { .. } => CredentialsError::new_err(message),
+        Error::Config(_) => ConfigError::new_err(message),
+        Error::InvalidParameter(_) => InvalidParameterError::new_err(message),
+        Error::InvalidAudio(_) => InvalidAudioError::new_err(message),
+        Error::Decoding(_) => DecodingError::new_err(message),
+        Error::UnsupportedAudio(_) => UnsupportedAudioError::new_err(message),
+        Error::Io { .. } => IoError::new_err(message),
+    };
+    if let Some(rpc) = error.rpc() {
+        Python::with_gil(|py| {
+            let value = exception.value(py);
+            value.setattr("code", format!("{:?}", rpc.code())).ok();
+            value.setattr("attempts", rpc.attempts()).ok();
+        });
+    }
+    exception
+}
@@ -0,0 +1,104 @@
+//! Python bindings to the Speech Center clients.
+//!
+//! Every call has a blocking form, releasing the GIL while it waits, and an asyncio form suffixed
+//! `_async` returning an awaitable. Audio is passed as bytes or as any buffer of int16 samples,
+//! such as numpy arrays.
+
+mod error;
+mod recognizer;
+mod synthesizer;
+mod types;
+
+use pyo3::buffer::PyBuffer;
+use pyo3::exceptions::PyTypeError;
+use pyo3::prelude::*;
+use speech_center_client::{SpeechCenterConfig, StaticToken};
+use std::sync::Arc;
+use tokio::runtime::Runtime;
+
+fn runtime() -> &'static Runtime {
+    pyo3_async_runtimes::tokio::get_runtime()
+}
+
+fn connection(url: &str, token: &str, plaintext: bool) -> (SpeechCenterConfig, Arc<StaticToken>) {
+    let config = SpeechCenterConfig::new(url).plaintext(plaintext);
+    (config, Arc::new(StaticToken::new(token)))
+}
+
+/// Little-endian PCM from bytes or a buffer of int16 samples.
+fn audio(audio: &Bound<'_, PyAny>) -> PyResult<Vec<u8>> {
+    let py = audio.py();
+    if let Ok(samples) = PyBuffer::<i16>::get(audio) {
+        let samples = samples.to_vec(py)?;
+        return Ok(samples.iter().flat_map(|s| s.to_le_bytes()).collect());
+    }
+    if let Ok(bytes) = PyBuffer::<u8>::get(audio) {
+        return bytes.to_vec(py);
+    }
+    Err(PyTypeError::new_err(
+        "Audio must be bytes or a buffer of int16 samples, such as a numpy array",
+    ))
+}
+
+#[pymodule]
+#[pyo3(name = "speech_center")]
+fn speech_center(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<recognizer::RecognitionClient>()?;
+    m.add_class::<synthesizer::SynthesisClient>()?;
+    m.add_class::<types::Topic>()?;
+    m.add_class::<types::Speaker>()?;
+    m.add_class::<types::SampleRate>()?;
+    m.add_class::<types::AudioFormat>()?;
+    error::register(m)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pyo3::ffi::c_str;
+    use pyo3::types::PyDict;
+    use speech_center_client::testkit::{Match, MockServer, Reply};
+
+    /// Runs the script with the module imported as `sc` and `url` pointing at the server.
+    pub(crate) fn run(server: &MockServer, script: &std::ffi::CStr) {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let module = PyModule::new(py, "speech_center").unwrap();
+            speech_center(&module).unwrap();
+            let globals = PyDict::new(py);
+            globals.set_item("sc", module).unwrap();
+            globals.set_item("url", server.url()).unwrap();
+            if let Err(e) = py.run(script, Some(&globals), None) {
+                e.display(py);
+                panic!("Python script failed: {}", e);
+            }
+        });
+    }
+
+    pub(crate) fn server(builder: speech_center_client::testkit::MockServerBuilder) -> MockServer {
+        runtime().block_on(builder.token("token").start()).unwrap()
+    }
+
+    #[test]
+    fn test_types() {
+        let server =
+            server(MockServer::builder().respond(Match::Any, Reply::Transcript(String::new())));
+        run(
+            &server,
+            c_str!(
+                r#"
+assert sc.Topic.from_name("banking") == sc.Topic.Banking
+assert sc.Speaker.from_name("tommy", "en-US") == sc.Speaker.EnUsTommy
+assert sc.SampleRate.from_hz(8000).hz == 8000
+assert sc.AudioFormat.from_str("alaw", "raw") == sc.AudioFormat.RawAlaw
+try:
+    sc.Topic.from_name("weather")
+    raise AssertionError("Should reject the topic")
+except sc.InvalidParameterError as e:
+    assert isinstance(e, sc.SpeechCenterError)
+    assert "weather" in str(e)
+"#
+            ),
+        );
+    }
+}
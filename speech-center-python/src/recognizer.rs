@@ -0,0 +1,185 @@
+use crate::error::to_py;
+use crate::types::Topic;
+use crate::{connection, runtime};
+use pyo3::prelude::*;
+use speech_center_client as client;
+
+/// Client of the recognition service.
+#[pyclass(frozen, module = "speech_center")]
+pub struct RecognitionClient {
+    inner: client::RecognitionClient,
+}
+
+impl RecognitionClient {
+    async fn connect_with(
+        url: String,
+        token: String,
+        plaintext: bool,
+        audio_conversion: bool,
+    ) -> PyResult<Self> {
+        let (config, credentials) = connection(&url, &token, plaintext);
+        let inner = client::RecognitionClient::with_config(config, credentials)
+            .await
+            .map_err(to_py)?
+            .with_audio_conversion(audio_conversion);
+        Ok(Self { inner })
+    }
+}
+
+#[pymethods]
+impl RecognitionClient {
+    /// Connects to the url, authenticating with the token.
+    ///
+    /// Connections are secured with TLS unless `plaintext` is set, meant for local test servers.
+    /// With `audio_conversion`, audio in any supported format is converted to mono 8kHz PCM16.
+    #[new]
+    #[pyo3(signature = (url, token, plaintext = false, audio_conversion = false))]
+    fn new(
+        py: Python<'_>,
+        url: String,
+        token: String,
+        plaintext: bool,
+        audio_conversion: bool,
+    ) -> PyResult<Self> {
+        py.allow_threads(|| {
+            runtime().block_on(Self::connect_with(url, token, plaintext, audio_conversion))
+        })
+    }
+
+    /// Awaitable connecting a client, as the constructor does.
+    #[staticmethod]
+    #[pyo3(signature = (url, token, plaintext = false, audio_conversion = false))]
+    fn connect(
+        py: Python<'_>,
+        url: String,
+        token: String,
+        plaintext: bool,
+        audio_conversion: bool,
+    ) -> PyResult<Bound<'_, PyAny>> {
+        pyo3_async_runtimes::tokio::future_into_py(
+            py,
+            Self::connect_with(url, token, plaintext, audio_conversion),
+        )
+    }
+
+    fn recognise_with_topic(
+        &self,
+        py: Python<'_>,
+        language: &str,
+        topic: Topic,
+        audio: &Bound<'_, PyAny>,
+    ) -> PyResult<String> {
+        let audio = crate::audio(audio)?;
+        let res = py.allow_threads(|| {
+            runtime().block_on(
+                self.inner
+                    .recognise_with_topic(language, topic.into(), audio),
+            )
+        });
+        res.map(|r| r.transcript).map_err(to_py)
+    }
+
+    fn recognise_with_grammar(
+        &self,
+        py: Python<'_>,
+        grammar: &str,
+        language: &str,
+        audio: &Bound<'_, PyAny>,
+    ) -> PyResult<String> {
+        let audio = crate::audio(audio)?;
+        let res = py.allow_threads(|| {
+            runtime().block_on(self.inner.recognise_with_grammar(grammar, language, audio))
+        });
+        res.map(|r| r.transcript).map_err(to_py)
+    }
+
+    fn recognise_with_topic_async<'py>(
+        &self,
+        py: Python<'py>,
+        language: String,
+        topic: Topic,
+        audio: &Bound<'py, PyAny>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let audio = crate::audio(audio)?;
+        let inner = self.inner.clone();
+        pyo3_async_runtimes::tokio::future_into_py(py, async move {
+            let res = inner
+                .recognise_with_topic(&language, topic.into(), audio)
+                .await;
+            res.map(|r| r.transcript).map_err(to_py)
+        })
+    }
+
+    fn recognise_with_grammar_async<'py>(
+        &self,
+        py: Python<'py>,
+        grammar: String,
+        language: String,
+        audio: &Bound<'py, PyAny>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let audio = crate::audio(audio)?;
+        let inner = self.inner.clone();
+        pyo3_async_runtimes::tokio::future_into_py(py, async move {
+            let res = inner
+                .recognise_with_grammar(&grammar, &language, audio)
+                .await;
+            res.map(|r| r.transcript).map_err(to_py)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test::{run, server};
+    use pyo3::ffi::c_str;
+    use speech_center_client::testkit::{audio_digest, Match, MockServer, Reply};
+
+    #[test]
+    fn test_recognition() {
+        let server = server(
+            MockServer::builder()
+                .respond(
+                    Match::Audio(audio_digest(&[1; 8000])),
+                    Reply::Transcript("hello".to_string()),
+                )
+                .respond(
+                    Match::Grammar("builtin:digits".to_string()),
+                    Reply::Transcript("1 2 3".to_string()),
+                ),
+        );
+        run(
+            &server,
+            c_str!(
+                r#"
+import array, asyncio
+
+samples = array.array("h", [0x0101] * 4000)
+client = sc.RecognitionClient(url, "token", plaintext=True)
+assert client.recognise_with_topic("en-US", sc.Topic.Generic, samples) == "hello"
+assert client.recognise_with_topic("en-US", sc.Topic.Generic, bytes(samples)) == "hello"
+assert client.recognise_with_grammar("builtin:digits", "en-US", b"\0" * 160) == "1 2 3"
+
+async def recognise():
+    client = await sc.RecognitionClient.connect(url, "token", plaintext=True)
+    return await asyncio.gather(
+        client.recognise_with_topic_async("en-US", sc.Topic.Generic, samples),
+        client.recognise_with_grammar_async("builtin:digits", "en-US", b"\0" * 160),
+    )
+assert asyncio.run(recognise()) == ["hello", "1 2 3"]
+
+try:
+    client.recognise_with_topic("en-US", sc.Topic.Generic, [1, 2, 3])
+    raise AssertionError("Should reject the audio")
+except TypeError:
+    pass
+try:
+    client = sc.RecognitionClient(url, "wrong", plaintext=True)
+    client.recognise_with_topic("en-US", sc.Topic.Generic, samples)
+    raise AssertionError("Should reject the token")
+except sc.UnauthenticatedError as e:
+    assert (e.code, e.attempts) == ("Unauthenticated", 1)
+"#
+            ),
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use crate::error::{to_py, InvalidParameterError};
+use crate::types::{AudioFormat, SampleRate, Speaker};
+use crate::{connection, runtime};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use speech_center_client as client;
+
+/// Client of the synthesis service.
+#[pyclass(frozen, module = "speech_center")]
+pub struct SynthesisClient {
+    inner: client::SynthesisClient,
+}
+
+impl SynthesisClient {
+    async fn connect_with(url: String, token: String, plaintext: bool) -> PyResult<Self> {
+        let (config, credentials) = connection(&url, &token, plaintext);
+        let inner = client::SynthesisClient::with_config(config, credentials)
+            .await
+            .map_err(to_py)?;
+        Ok(Self { inner })
+    }
+}
+
+/// Numpy dtype of the samples when asked for an array, failing for formats with a header.
+fn dtype(audio_format: AudioFormat, numpy: bool) -> PyResult<Option<&'static str>> {
+    if !numpy {
+        return Ok(None);
+    }
+    match audio_format.dtype() {
+        Some(dtype) => Ok(Some(dtype)),
+        None => Err(InvalidParameterError::new_err(format!(
+            "Numpy output needs a headerless audio format, not {:?}",
+            audio_format
+        ))),
+    }
+}
+
+/// Synthesized audio as bytes, or as a numpy array of samples of the given dtype.
+fn output(py: Python<'_>, audio: &[u8], dtype: Option<&str>) -> PyResult<PyObject> {
+    let bytes = PyBytes::new(py, audio);
+    let Some(dtype) = dtype else {
+        return Ok(bytes.into_any().unbind());
+    };
+    let array = py
+        .import("numpy")?
+        .call_method1("frombuffer", (bytes, dtype))?
+        .call_method0("copy")?;
+    Ok(array.unbind())
+}
+
+#[pymethods]
+impl SynthesisClient {
+    /// Connects to the url, authenticating with the token.
+    ///
+    /// Connections are secured with TLS unless `plaintext` is set, meant for local test servers.
+    #[new]
+    #[pyo3(signature = (url, token, plaintext = false))]
+    fn new(py: Python<'_>, url: String, token: String, plaintext: bool) -> PyResult<Self> {
+        py.allow_threads(|| runtime().block_on(Self::connect_with(url, token, plaintext)))
+    }
+
+    /// Awaitable connecting a client, as the constructor does.
+    #[staticmethod]
+    #[pyo3(signature = (url, token, plaintext = false))]
+    fn connect(
+        py: Python<'_>,
+        url: String,
+        token: String,
+        plaintext: bool,
+    ) -> PyResult<Bound<'_, PyAny>> {
+        pyo3_async_runtimes::tokio::future_into_py(py, Self::connect_with(url, token, plaintext))
+    }
+
+    /// Synthesizes the text, returning bytes, or a numpy array of samples with `numpy` set.
+    #[pyo3(signature = (speaker, sample_rate, audio_format, text, numpy = false))]
+    fn synthesize(
+        &self,
+        py: Python<'_>,
+        speaker: Speaker,
+        sample_rate: SampleRate,
+        audio_format: AudioFormat,
+        text: &str,
+        numpy: bool,
+    ) -> PyResult<PyObject> {
+        let dtype = dtype(audio_format, numpy)?;
+        let res = py.allow_threads(|| {
+            runtime().block_on(self.inner.synthesize(
+                speaker.into(),
+                sample_rate.into(),
+                audio_format.into(),
+                text,
+            ))
+        });
+        output(py, &res.map_err(to_py)?.audio(), dtype)
+    }
+
+    #[pyo3(signature = (speaker, sample_rate, audio_format, text, numpy = false))]
+    fn synthesize_async<'py>(
+        &self,
+        py: Python<'py>,
+        speaker: Speaker,
+        sample_rate: SampleRate,
+        audio_format: AudioFormat,
+        text: String,
+        numpy: bool,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let dtype = dtype(audio_format, numpy)?;
+        let inner = self.inner.clone();
+        pyo3_async_runtimes::tokio::future_into_py(py, async move {
+            let res = inner
+                .synthesize(
+                    speaker.into(),
+                    sample_rate.into(),
+                    audio_format.into(),
+                    &text,
+                )
+                .await
+                .map_err(to_py)?;
+            Python::with_gil(|py| output(py, &res.audio(), dtype))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test::{run, server};
+    use pyo3::ffi::c_str;
+    use speech_center_client::testkit::{Match, MockServer, Reply};
+
+    #[test]
+    fn test_synthesis() {
+        let server = server(MockServer::builder().respond(
+            Match::Text("hello".to_string()),
+            Reply::Audio([1, 0, 2, 0].repeat(80)),
+        ));
+        run(
+            &server,
+            c_str!(
+                r#"
+import asyncio, importlib.util
+
+args = (sc.Speaker.EnUsTommy, sc.SampleRate.Khz8, sc.AudioFormat.RawLpcmS16le, "hello")
+client = sc.SynthesisClient(url, "token", plaintext=True)
+assert client.synthesize(*args) == bytes([1, 0, 2, 0] * 80)
+
+async def synthesize():
+    client = await sc.SynthesisClient.connect(url, "token", plaintext=True)
+    return await client.synthesize_async(*args)
+assert asyncio.run(synthesize()) == bytes([1, 0, 2, 0] * 80)
+
+if importlib.util.find_spec("numpy"):
+    samples = client.synthesize(*args, numpy=True)
+    assert samples.dtype.name == "int16" and list(samples[:2]) == [1, 2]
+
+wav = (sc.Speaker.EnUsTommy, sc.SampleRate.Khz8, sc.AudioFormat.WavLpcmS16le, "hello")
+try:
+    client.synthesize(*wav, numpy=True)
+    raise AssertionError("Should reject numpy output of WAV audio")
+except sc.InvalidParameterError:
+    pass
+try:
+    client.synthesize(sc.Speaker.EnUsTommy, sc.SampleRate.Khz8, sc.AudioFormat.RawLpcmS16le, "bye")
+    raise AssertionError("Should fail on unscripted text")
+except sc.SpeechCenterError as e:
+    assert e.code
+"#
+            ),
+        );
+    }
+}
@@ -0,0 +1,189 @@
+use crate::error::to_py;
+use pyo3::prelude::*;
+use speech_center_client as client;
+
+/// Statistical model recognising free speech about a topic.
+#[pyclass(eq, eq_int, hash, frozen, module = "speech_center")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Topic {
+    Generic,
+    Banking,
+    Telco,
+}
+
+#[pymethods]
+impl Topic {
+    #[staticmethod]
+    fn from_name(name: &str) -> PyResult<Self> {
+        client::Topic::from_name(name)
+            .map(Self::from)
+            .map_err(to_py)
+    }
+}
+
+impl From<client::Topic> for Topic {
+    fn from(topic: client::Topic) -> Self {
+        match topic {
+            client::Topic::Generic => Self::Generic,
+            client::Topic::Banking => Self::Banking,
+            client::Topic::Telco => Self::Telco,
+        }
+    }
+}
+
+impl From<Topic> for client::Topic {
+    fn from(topic: Topic) -> Self {
+        match topic {
+            Topic::Generic => Self::Generic,
+            Topic::Banking => Self::Banking,
+            Topic::Telco => Self::Telco,
+        }
+    }
+}
+
+/// Voice to synthesize with.
+#[pyclass(eq, eq_int, hash, frozen, module = "speech_center")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Speaker {
+    EnUsTommy,
+    EnUsAnnie,
+    EsEsAurora,
+    EsEsDavid,
+    PtBrLuma,
+    CaEsDavid,
+}
+
+#[pymethods]
+impl Speaker {
+    /// Speaker with the given name for the language, such as "tommy" and "en-US".
+    #[staticmethod]
+    fn from_name(name: &str, language: &str) -> PyResult<Self> {
+        client::Speaker::from_name(name, language)
+            .map(Self::from)
+            .map_err(to_py)
+    }
+}
+
+impl From<client::Speaker> for Speaker {
+    fn from(speaker: client::Speaker) -> Self {
+        match speaker {
+            client::Speaker::EnUsTommy => Self::EnUsTommy,
+            client::Speaker::EnUsAnnie => Self::EnUsAnnie,
+            client::Speaker::EsEsAurora => Self::EsEsAurora,
+            client::Speaker::EsEsDavid => Self::EsEsDavid,
+            client::Speaker::PtBrLuma => Self::PtBrLuma,
+            client::Speaker::CaEsDavid => Self::CaEsDavid,
+        }
+    }
+}
+
+impl From<Speaker> for client::Speaker {
+    fn from(speaker: Speaker) -> Self {
+        match speaker {
+            Speaker::EnUsTommy => Self::EnUsTommy,
+            Speaker::EnUsAnnie => Self::EnUsAnnie,
+            Speaker::EsEsAurora => Self::EsEsAurora,
+            Speaker::EsEsDavid => Self::EsEsDavid,
+            Speaker::PtBrLuma => Self::PtBrLuma,
+            Speaker::CaEsDavid => Self::CaEsDavid,
+        }
+    }
+}
+
+/// Sample rate of synthesized audio.
+#[pyclass(eq, eq_int, hash, frozen, module = "speech_center")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SampleRate {
+    Khz8,
+}
+
+#[pymethods]
+impl SampleRate {
+    #[staticmethod]
+    fn from_hz(hz: u32) -> PyResult<Self> {
+        client::SampleRate::try_from(hz)
+            .map(Self::from)
+            .map_err(to_py)
+    }
+
+    #[getter]
+    fn hz(&self) -> u32 {
+        client::SampleRate::from(*self).into()
+    }
+}
+
+impl From<client::SampleRate> for SampleRate {
+    fn from(sample_rate: client::SampleRate) -> Self {
+        match sample_rate {
+            client::SampleRate::Khz8 => Self::Khz8,
+        }
+    }
+}
+
+impl From<SampleRate> for client::SampleRate {
+    fn from(sample_rate: SampleRate) -> Self {
+        match sample_rate {
+            SampleRate::Khz8 => Self::Khz8,
+        }
+    }
+}
+
+/// Encoding and container of synthesized audio.
+#[pyclass(eq, eq_int, hash, frozen, module = "speech_center")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AudioFormat {
+    WavLpcmS16le,
+    RawLpcmS16le,
+    WavMulaw,
+    RawMulaw,
+    WavAlaw,
+    RawAlaw,
+}
+
+#[pymethods]
+impl AudioFormat {
+    /// Format with the given encoding ("pcm", "alaw" or "mulaw") and header ("wav" or "raw").
+    #[staticmethod]
+    fn from_str(encoding: &str, header: &str) -> PyResult<Self> {
+        client::AudioFormat::from_str(encoding, header)
+            .map(Self::from)
+            .map_err(to_py)
+    }
+}
+
+impl AudioFormat {
+    /// Numpy dtype of the samples, for headerless formats.
+    pub(crate) fn dtype(self) -> Option<&'static str> {
+        match self {
+            Self::RawLpcmS16le => Some("<i2"),
+            Self::RawMulaw | Self::RawAlaw => Some("u1"),
+            Self::WavLpcmS16le | Self::WavMulaw | Self::WavAlaw => None,
+        }
+    }
+}
+
+impl From<client::AudioFormat> for AudioFormat {
+    fn from(audio_format: client::AudioFormat) -> Self {
+        match audio_format {
+            client::AudioFormat::WavLpcmS16le => Self::WavLpcmS16le,
+            client::AudioFormat::RawLpcmS16le => Self::RawLpcmS16le,
+            client::AudioFormat::WavMulaw => Self::WavMulaw,
+            client::AudioFormat::RawMulaw => Self::RawMulaw,
+            client::AudioFormat::WavAlaw => Self::WavAlaw,
+            client::AudioFormat::RawAlaw => Self::RawAlaw,
+        }
+    }
+}
+
+impl From<AudioFormat> for client::AudioFormat {
+    fn from(audio_format: AudioFormat) -> Self {
+        match audio_format {
+            AudioFormat::WavLpcmS16le => Self::WavLpcmS16le,
+            AudioFormat::RawLpcmS16le => Self::RawLpcmS16le,
+            AudioFormat::WavMulaw => Self::WavMulaw,
+            AudioFormat::RawMulaw => Self::RawMulaw,
+            AudioFormat::WavAlaw => Self::WavAlaw,
+            AudioFormat::RawAlaw => Self::RawAlaw,
+        }
+    }
+}